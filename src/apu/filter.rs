@@ -0,0 +1,195 @@
+//! Filters for the APU's output samples.
+//!
+//! Between the 2A03 and the speaker, a console's audio goes through two high-pass filters (90Hz
+//! and 440Hz) and a low-pass one (14kHz). Without them the output has a large DC offset and every
+//! edge of the square waves is as sharp as the sample rate allows. [`FilterChain::hardware`]
+//! recreates them; frontends can add their own [`Filter`]s or leave the chain empty for the raw
+//! signal.
+
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::f32::consts::PI;
+
+/// One stage of the chain, run once per output sample.
+pub trait Filter: Send {
+    fn process(&mut self, sample: f32) -> f32;
+
+    /// Called when the output sample rate changes, for filters whose coefficients depend on it.
+    fn set_sample_rate(&mut self, _hz: u32) {}
+}
+
+/// A first-order high-pass RC filter.
+#[derive(Debug, Clone, Copy)]
+pub struct HighPass {
+    cutoff_hz: f32,
+    alpha: f32,
+    previous_input: f32,
+    previous_output: f32,
+}
+
+impl HighPass {
+    pub fn new(cutoff_hz: f32, sample_rate: u32) -> Self {
+        let mut filter = Self {
+            cutoff_hz,
+            alpha: 0.0,
+            previous_input: 0.0,
+            previous_output: 0.0,
+        };
+        filter.set_sample_rate(sample_rate);
+        filter
+    }
+}
+
+impl Filter for HighPass {
+    fn process(&mut self, sample: f32) -> f32 {
+        self.previous_output = self.alpha * (self.previous_output + sample - self.previous_input);
+        self.previous_input = sample;
+        self.previous_output
+    }
+
+    fn set_sample_rate(&mut self, hz: u32) {
+        let (rc, dt) = rc_and_dt(self.cutoff_hz, hz);
+        self.alpha = rc / (rc + dt);
+    }
+}
+
+/// A first-order low-pass RC filter.
+#[derive(Debug, Clone, Copy)]
+pub struct LowPass {
+    cutoff_hz: f32,
+    alpha: f32,
+    previous_output: f32,
+}
+
+impl LowPass {
+    pub fn new(cutoff_hz: f32, sample_rate: u32) -> Self {
+        let mut filter = Self {
+            cutoff_hz,
+            alpha: 0.0,
+            previous_output: 0.0,
+        };
+        filter.set_sample_rate(sample_rate);
+        filter
+    }
+}
+
+impl Filter for LowPass {
+    fn process(&mut self, sample: f32) -> f32 {
+        self.previous_output += self.alpha * (sample - self.previous_output);
+        self.previous_output
+    }
+
+    fn set_sample_rate(&mut self, hz: u32) {
+        let (rc, dt) = rc_and_dt(self.cutoff_hz, hz);
+        self.alpha = dt / (rc + dt);
+    }
+}
+
+/// The RC time constant for `cutoff_hz` and the time between samples at `sample_rate`.
+fn rc_and_dt(cutoff_hz: f32, sample_rate: u32) -> (f32, f32) {
+    (1.0 / (2.0 * PI * cutoff_hz), 1.0 / sample_rate as f32)
+}
+
+/// Filters run one after another, in the order they were added.
+#[derive(Default)]
+pub struct FilterChain {
+    filters: Vec<Box<dyn Filter>>,
+}
+
+impl FilterChain {
+    /// An empty chain, which passes samples through untouched.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The console's own filters at `sample_rate`.
+    pub fn hardware(sample_rate: u32) -> Self {
+        let mut chain = Self::new();
+        chain.push(HighPass::new(90.0, sample_rate));
+        chain.push(HighPass::new(440.0, sample_rate));
+        chain.push(LowPass::new(14_000.0, sample_rate));
+        chain
+    }
+
+    /// Adds `filter` to the end of the chain.
+    pub fn push(&mut self, filter: impl Filter + 'static) {
+        self.filters.push(Box::new(filter));
+    }
+
+    /// Removes every filter.
+    pub fn clear(&mut self) {
+        self.filters.clear();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.filters.is_empty()
+    }
+
+    pub fn process(&mut self, sample: f32) -> f32 {
+        self.filters
+            .iter_mut()
+            .fold(sample, |sample, filter| filter.process(sample))
+    }
+
+    pub fn set_sample_rate(&mut self, hz: u32) {
+        for filter in &mut self.filters {
+            filter.set_sample_rate(hz);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_high_pass_removes_dc() {
+        let mut filter = HighPass::new(90.0, 44_100);
+        assert!(filter.process(1.0) > 0.98);
+        let mut sample = 0.0;
+        for _ in 0..44_100 {
+            sample = filter.process(1.0);
+        }
+        assert!(sample.abs() < 1e-3)
+    }
+
+    #[test]
+    fn test_low_pass_smooths_edges() {
+        let mut filter = LowPass::new(14_000.0, 44_100);
+        let first = filter.process(1.0);
+        assert!(first > 0.5 && first < 1.0);
+        for _ in 0..100 {
+            filter.process(1.0);
+        }
+        assert!((filter.process(1.0) - 1.0).abs() < 1e-3)
+    }
+
+    struct Double;
+
+    impl Filter for Double {
+        fn process(&mut self, sample: f32) -> f32 {
+            sample * 2.0
+        }
+    }
+
+    #[test]
+    fn test_chain() {
+        let mut chain = FilterChain::new();
+        assert_eq!(chain.process(0.25), 0.25);
+        chain.push(Double);
+        chain.push(Double);
+        assert_eq!(chain.process(0.25), 1.0);
+        chain.clear();
+        assert!(chain.is_empty());
+
+        let mut hardware = FilterChain::hardware(48_000);
+        hardware.set_sample_rate(44_100);
+        let mut first = HighPass::new(90.0, 44_100);
+        let mut second = HighPass::new(440.0, 44_100);
+        let mut low = LowPass::new(14_000.0, 44_100);
+        for sample in [0.5, 0.5, 0.0, 0.3] {
+            let expected = low.process(second.process(first.process(sample)));
+            assert_eq!(hardware.process(sample), expected);
+        }
+    }
+}
@@ -0,0 +1,1452 @@
+//! The audio processing unit, built into the 2A03.
+//!
+//! ```text
+//! $4000-$4003  pulse 1: DDLC VVVV (duty, halt, constant volume, volume), sweep, period low,
+//!              LLLL LHHH (length index, period high)
+//! $4004-$4007  pulse 2, same layout
+//! $4008        triangle: CRRR RRRR (length halt and linear counter control, linear counter reload)
+//! $400A-$400B  triangle period low, LLLL LHHH
+//! $400C        noise: --LC VVVV (halt, constant volume, volume)
+//! $400E-$400F  noise M--- PPPP (short mode, period index), LLLL L---
+//! $4010        DMC: IL-- RRRR (IRQ enable, loop, rate index)
+//! $4011        DMC -DDD DDDD (output level)
+//! $4012-$4013  DMC sample address ($C000 + 64A) and length (16L + 1 bytes)
+//! $4015        channel enables; reads return which length counters are running and the IRQs
+//! $4017        frame counter: MI-- ---- (5-step mode, IRQ inhibit)
+//! ```
+//!
+//! The channels' timers run off the CPU clock. Envelopes, sweeps and length counters are clocked
+//! by the frame counter, a quarter or half frame at a time. In 4-step mode the frame counter also
+//! raises an IRQ at the end of every sequence unless it is inhibited.
+//!
+//! The DMC plays samples straight out of CPU memory. The APU can't reach the bus itself, so it
+//! asks for each byte through [`Apu::dmc_request`] and whoever owns the bus answers with
+//! [`Apu::dmc_fill`].
+//!
+//! The channels are mixed non-linearly, the way the 2A03's output stage does it: the pulses through
+//! one resistor network and the triangle, noise and DMC through another, so that each channel gets
+//! quieter the louder the others in its group are. Each channel's volume can be scaled on top of
+//! that with [`Volumes`].
+//!
+//! The mixed output is produced every CPU cycle, far faster than any sound card plays it, so the
+//! APU resamples it down to [`Apu::sample_rate`] and runs it through a [`FilterChain`]. Frontends
+//! collect the result with [`Apu::take_samples`].
+
+use alloc::vec::Vec;
+use core::mem;
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::{NesError, Result};
+use crate::region::{Region, Timing};
+use crate::state;
+
+mod filter;
+
+pub use filter::{Filter, FilterChain, HighPass, LowPass};
+
+/// The host sample rate the APU resamples to unless told otherwise.
+pub const DEFAULT_SAMPLE_RATE: u32 = 44_100;
+
+/// CPU cycles a DMC sample fetch steals from the CPU. The real figure is 1-4 depending on what the
+/// CPU was doing; this is the usual case of a fetch landing on a CPU read.
+pub const DMC_STALL_CYCLES: u32 = 4;
+
+/// Length counter load values, indexed by the top five bits of a channel's fourth register.
+const LENGTH_TABLE: [u8; 32] = [
+    10, 254, 20, 2, 40, 4, 80, 6, 160, 8, 60, 10, 14, 12, 26, 14, 12, 16, 24, 18, 48, 20, 96, 22,
+    192, 24, 72, 26, 16, 28, 32, 30,
+];
+
+/// The pulse channels' 8-step duty waveforms: 12.5%, 25%, 50% and 25% inverted.
+const DUTY_TABLE: [[u8; 8]; 4] = [
+    [0, 1, 0, 0, 0, 0, 0, 0],
+    [0, 1, 1, 0, 0, 0, 0, 0],
+    [0, 1, 1, 1, 1, 0, 0, 0],
+    [1, 0, 0, 1, 1, 1, 1, 1],
+];
+
+/// The triangle's 32-step waveform.
+const TRIANGLE_SEQUENCE: [u8; 32] = [
+    15, 14, 13, 12, 11, 10, 9, 8, 7, 6, 5, 4, 3, 2, 1, 0, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12,
+    13, 14, 15,
+];
+
+/// The pulse group's mixer output for the sum of both pulse levels, 0-30.
+const PULSE_TABLE: [f32; 31] = mixer_table(95.52, 8128.0);
+
+/// The triangle, noise and DMC group's mixer output, indexed by 3 * triangle + 2 * noise + DMC.
+const TND_TABLE: [f32; 203] = mixer_table(163.67, 24329.0);
+
+/// nesdev's approximation of a group's output, `scale / (divisor / n + 100)`, for each weighted sum
+/// of levels `n`.
+const fn mixer_table<const N: usize>(scale: f32, divisor: f32) -> [f32; N] {
+    let mut table = [0.0; N];
+    let mut n = 1;
+    while n < N {
+        table[n] = scale / (divisor / n as f32 + 100.0);
+        n += 1;
+    }
+    table
+}
+
+/// Reads `table` at a fractional `index`, interpolating between entries. Scaled channel volumes
+/// land between them.
+fn lookup(table: &[f32], index: f32) -> f32 {
+    let index = index.clamp(0.0, (table.len() - 1) as f32);
+    let whole = index as usize;
+    match table.get(whole + 1) {
+        Some(next) => table[whole] + (next - table[whole]) * (index - whole as f32),
+        None => table[whole],
+    }
+}
+
+/// How loud each channel is mixed, 1.0 being its level on hardware and 0.0 muting it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Volumes {
+    pub pulse1: f32,
+    pub pulse2: f32,
+    pub triangle: f32,
+    pub noise: f32,
+    pub dmc: f32,
+    /// The cartridge's expansion audio, if it has any.
+    pub expansion: f32,
+}
+
+impl Default for Volumes {
+    fn default() -> Self {
+        Self {
+            pulse1: 1.0,
+            pulse2: 1.0,
+            triangle: 1.0,
+            noise: 1.0,
+            dmc: 1.0,
+            expansion: 1.0,
+        }
+    }
+}
+
+/// Serializes only what's emulated. Host settings (`sample_rate`, `filters`, `volumes`) and the
+/// samples waiting to be collected are left out, and kept as they are when a state is loaded.
+#[derive(Serialize, Deserialize)]
+pub struct Apu {
+    #[serde(with = "state::timing")]
+    timing: &'static Timing,
+    pulses: [Pulse; 2],
+    triangle: Triangle,
+    noise: Noise,
+    dmc: Dmc,
+    /// The pulse timers tick every other CPU cycle.
+    odd_cycle: bool,
+    /// CPU cycles into the frame sequence.
+    frame_cycle: u32,
+    five_step: bool,
+    irq_inhibit: bool,
+    frame_irq: bool,
+    /// A $4017 write restarts the sequence 3 or 4 CPU cycles later; this counts down to it.
+    frame_reset_delay: Option<u8>,
+    #[serde(skip)]
+    sample_rate: u32,
+    /// How far into the current output sample we are, in master clock ticks times the sample
+    /// rate. A sample is done when it reaches the master clock rate.
+    sample_phase: u64,
+    /// The CPU cycles' output levels summed so far for the current sample, and how many there are.
+    sample_sum: f32,
+    sample_cycles: u32,
+    #[serde(skip)]
+    samples: Vec<f32>,
+    #[serde(skip)]
+    filters: FilterChain,
+    #[serde(skip)]
+    volumes: Volumes,
+    /// The cartridge's expansion audio level, fed in by the bus every cycle.
+    expansion: f32,
+}
+
+impl Apu {
+    pub fn new() -> Self {
+        Self::with_region(Region::default())
+    }
+
+    /// An APU with `region`'s frame counter rate and period tables.
+    pub fn with_region(region: Region) -> Self {
+        let timing = region.timing();
+        Self {
+            timing,
+            pulses: [Pulse::new(true), Pulse::new(false)],
+            triangle: Triangle::default(),
+            noise: Noise::new(timing),
+            dmc: Dmc::new(timing),
+            odd_cycle: false,
+            frame_cycle: 0,
+            five_step: false,
+            irq_inhibit: false,
+            frame_irq: false,
+            frame_reset_delay: None,
+            sample_rate: DEFAULT_SAMPLE_RATE,
+            sample_phase: 0,
+            sample_sum: 0.0,
+            sample_cycles: 0,
+            samples: Vec::new(),
+            filters: FilterChain::hardware(DEFAULT_SAMPLE_RATE),
+            volumes: Volumes::default(),
+            expansion: 0.0,
+        }
+    }
+
+    /// Writes an APU register, $4000-$4017.
+    pub fn write_register(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x4000..=0x4007 => {
+                let pulse = usize::from(addr - 0x4000) / 4;
+                self.pulses[pulse].write(addr & 0b11, data);
+            }
+            0x4008..=0x400B => self.triangle.write(addr & 0b11, data),
+            0x400C..=0x400F => self.noise.write(addr & 0b11, data),
+            0x4010..=0x4013 => self.dmc.write(addr & 0b11, data),
+            0x4015 => {
+                for (bit, pulse) in self.pulses.iter_mut().enumerate() {
+                    pulse.length.set_enabled(data & (1 << bit) != 0);
+                }
+                self.triangle.length.set_enabled(data & 0b0100 != 0);
+                self.noise.length.set_enabled(data & 0b1000 != 0);
+                self.dmc.set_enabled(data & 0b1_0000 != 0);
+            }
+            0x4017 => {
+                self.five_step = data & 0x80 != 0;
+                self.irq_inhibit = data & 0x40 != 0;
+                if self.irq_inhibit {
+                    self.frame_irq = false;
+                }
+                // Writes landing between APU cycles take a cycle longer.
+                self.frame_reset_delay = Some(if self.odd_cycle { 4 } else { 3 });
+            }
+            _ => trace!("write {data:02X} to unimplemented APU register {addr:04X}"),
+        }
+    }
+
+    /// The reset line: as if $4015 were written with 0, silencing every channel, and the frame
+    /// counter starts over in the mode it was in. The triangle goes back to the start of its
+    /// sequence and the DMC's level keeps only its lowest bit.
+    pub fn reset(&mut self) {
+        self.write_register(0x4015, 0);
+        self.triangle.step = 0;
+        self.dmc.level &= 1;
+        self.frame_irq = false;
+        self.frame_cycle = 0;
+        self.frame_reset_delay = None;
+    }
+
+    /// Reads $4015: bit n is set while channel n's length counter (or the DMC's sample) is
+    /// running, bit 6 while the frame IRQ is pending and bit 7 while the DMC's is. Reading
+    /// acknowledges the frame IRQ.
+    pub fn read_status(&mut self) -> u8 {
+        let mut status = 0;
+        for (bit, pulse) in self.pulses.iter().enumerate() {
+            if pulse.length.active() {
+                status |= 1 << bit;
+            }
+        }
+        if self.triangle.length.active() {
+            status |= 0b0100;
+        }
+        if self.noise.length.active() {
+            status |= 0b1000;
+        }
+        if self.dmc.bytes_remaining > 0 {
+            status |= 0b1_0000;
+        }
+        if core::mem::take(&mut self.frame_irq) {
+            status |= 0x40;
+        }
+        if self.dmc.irq {
+            status |= 0x80;
+        }
+        status
+    }
+
+    /// Advances the APU by one CPU cycle.
+    pub fn tick(&mut self) {
+        self.triangle.clock_timer();
+        self.noise.clock_timer();
+        self.dmc.clock_timer();
+        if self.odd_cycle {
+            for pulse in &mut self.pulses {
+                pulse.clock_timer();
+            }
+        }
+        self.odd_cycle = !self.odd_cycle;
+        self.clock_frame_sequence();
+        self.resample();
+    }
+
+    /// The rate, in Hz, of the samples [`take_samples`](Self::take_samples) returns.
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// Changes the host sample rate, typically 44100 or 48000 Hz.
+    pub fn set_sample_rate(&mut self, hz: u32) {
+        self.sample_rate = hz;
+        self.filters.set_sample_rate(hz);
+    }
+
+    /// Takes over the emulated state of `loaded`, an APU read from a save state, keeping this one's
+    /// host settings and uncollected samples.
+    pub(crate) fn restore(&mut self, mut loaded: Apu) {
+        loaded.sample_rate = self.sample_rate;
+        loaded.samples = mem::take(&mut self.samples);
+        loaded.filters = mem::take(&mut self.filters);
+        loaded.volumes = self.volumes;
+        *self = loaded;
+    }
+
+    /// Checks an APU read from a save state for values its registers can't produce and its timers
+    /// can't count down from.
+    pub(crate) fn validate(&self) -> Result<()> {
+        let last_step = if self.five_step {
+            self.timing.frame_counter_5_step
+        } else {
+            self.timing.frame_counter_steps[3]
+        };
+        let valid = self.pulses.iter().all(Pulse::is_valid)
+            && self.triangle.is_valid()
+            && self.noise.is_valid()
+            && self.dmc.is_valid()
+            && self.frame_cycle <= last_step
+            && self
+                .frame_reset_delay
+                .is_none_or(|delay| (1..=4).contains(&delay));
+        if !valid {
+            return Err(NesError::InvalidState(
+                "save state's APU registers are out of range".into(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// The filters applied to each output sample, the console's own ones to begin with. Clear the
+    /// chain for the raw mixer output.
+    pub fn filters_mut(&mut self) -> &mut FilterChain {
+        &mut self.filters
+    }
+
+    /// Replaces the filter chain. Its filters are set to the current sample rate.
+    pub fn set_filters(&mut self, mut filters: FilterChain) {
+        filters.set_sample_rate(self.sample_rate);
+        self.filters = filters;
+    }
+
+    pub fn volumes(&self) -> Volumes {
+        self.volumes
+    }
+
+    pub fn set_volumes(&mut self, volumes: Volumes) {
+        self.volumes = volumes;
+    }
+
+    /// Sets the cartridge's expansion audio level, as returned by
+    /// [`Mapper::expansion_audio`](crate::mapper::Mapper::expansion_audio).
+    pub fn set_expansion_audio(&mut self, level: f32) {
+        self.expansion = level;
+    }
+
+    /// Moves the samples produced since the last call onto the end of `samples`. They pile up until
+    /// taken, so call it every frame or so.
+    pub fn take_samples(&mut self, samples: &mut Vec<f32>) {
+        samples.append(&mut self.samples);
+    }
+
+    /// Each output sample is the average of the CPU cycles it covers, which also filters out most
+    /// of what the host rate can't represent.
+    fn resample(&mut self) {
+        self.sample_sum += self.output();
+        self.sample_cycles += 1;
+        self.sample_phase += u64::from(self.sample_rate) * u64::from(self.timing.cpu_divider);
+        let master_clock = u64::from(self.timing.master_clock_hz);
+        if self.sample_phase >= master_clock {
+            self.sample_phase -= master_clock;
+            let sample = self.sample_sum / self.sample_cycles as f32;
+            self.samples.push(self.filters.process(sample));
+            self.sample_sum = 0.0;
+            self.sample_cycles = 0;
+        }
+    }
+
+    /// Steps the frame sequence: a quarter frame at each step, a half frame at every other one.
+    fn clock_frame_sequence(&mut self) {
+        if let Some(delay) = self.frame_reset_delay.as_mut() {
+            *delay -= 1;
+            if *delay == 0 {
+                self.frame_reset_delay = None;
+                self.frame_cycle = 0;
+                // 5-step mode clocks everything straight away.
+                if self.five_step {
+                    self.quarter_frame();
+                    self.half_frame();
+                }
+                return;
+            }
+        }
+
+        self.frame_cycle += 1;
+        let steps = self.timing.frame_counter_steps;
+        let last = if self.five_step {
+            self.timing.frame_counter_5_step
+        } else {
+            steps[3]
+        };
+        let cycle = self.frame_cycle;
+        if cycle == steps[0] || cycle == steps[2] {
+            self.quarter_frame();
+        } else if cycle == steps[1] || cycle == last {
+            self.quarter_frame();
+            self.half_frame();
+        }
+        // The IRQ flag is set on the three cycles around the last step, so acknowledging it right
+        // at the end of the sequence doesn't stick.
+        if !self.five_step && !self.irq_inhibit && (last - 1..=last + 1).contains(&cycle) {
+            self.frame_irq = true;
+        }
+        // The cycle after the last step is the first of the next sequence.
+        if cycle == last + 1 {
+            self.frame_cycle = 0;
+        }
+    }
+
+    /// The IRQ line to the CPU.
+    pub fn irq(&self) -> bool {
+        self.frame_irq || self.dmc.irq
+    }
+
+    /// The address of the next DMC sample byte, when the DMC needs one. Reading it stalls the CPU
+    /// for [`DMC_STALL_CYCLES`]; hand the byte back through [`dmc_fill`](Self::dmc_fill).
+    pub fn dmc_request(&self) -> Option<u16> {
+        (self.dmc.buffer.is_none() && self.dmc.bytes_remaining > 0).then_some(self.dmc.address)
+    }
+
+    /// Delivers a sample byte asked for with [`dmc_request`](Self::dmc_request).
+    pub fn dmc_fill(&mut self, data: u8) {
+        self.dmc.fill(data);
+    }
+
+    /// Clocks the envelopes and the triangle's linear counter.
+    fn quarter_frame(&mut self) {
+        for pulse in &mut self.pulses {
+            pulse.envelope.clock();
+        }
+        self.triangle.clock_linear();
+        self.noise.envelope.clock();
+    }
+
+    /// Clocks the length counters and sweep units.
+    fn half_frame(&mut self) {
+        for pulse in &mut self.pulses {
+            pulse.length.clock();
+            pulse.clock_sweep();
+        }
+        self.triangle.length.clock();
+        self.noise.length.clock();
+    }
+
+    /// The mixed output level, from 0.0 to roughly 1.0.
+    pub fn output(&self) -> f32 {
+        let volumes = &self.volumes;
+        let pulses = f32::from(self.pulses[0].output()) * volumes.pulse1
+            + f32::from(self.pulses[1].output()) * volumes.pulse2;
+        let tnd = 3.0 * f32::from(self.triangle.output()) * volumes.triangle
+            + 2.0 * f32::from(self.noise.output()) * volumes.noise
+            + f32::from(self.dmc.level) * volumes.dmc;
+        lookup(&PULSE_TABLE, pulses) + lookup(&TND_TABLE, tnd) + self.expansion * volumes.expansion
+    }
+}
+
+impl Default for Apu {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Counts a channel's note length down to silence, once per half frame.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+struct LengthCounter {
+    enabled: bool,
+    halted: bool,
+    counter: u8,
+}
+
+impl LengthCounter {
+    fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        if !enabled {
+            self.counter = 0;
+        }
+    }
+
+    /// Loads the counter from `LENGTH_TABLE`, if the channel is enabled.
+    fn load(&mut self, index: u8) {
+        if self.enabled {
+            self.counter = LENGTH_TABLE[usize::from(index & 0x1F)];
+        }
+    }
+
+    fn clock(&mut self) {
+        if !self.halted && self.counter > 0 {
+            self.counter -= 1;
+        }
+    }
+
+    fn active(&self) -> bool {
+        self.counter > 0
+    }
+}
+
+/// A volume that either stays constant or decays from 15 to 0, once every `period + 1` quarter
+/// frames, optionally looping.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+struct Envelope {
+    start: bool,
+    looping: bool,
+    constant: bool,
+    /// The constant volume, or the decay period.
+    period: u8,
+    divider: u8,
+    decay: u8,
+}
+
+impl Envelope {
+    /// Sets up the envelope from a channel's first register: ..LC VVVV.
+    fn write(&mut self, data: u8) {
+        self.looping = data & 0x20 != 0;
+        self.constant = data & 0x10 != 0;
+        self.period = data & 0x0F;
+    }
+
+    fn clock(&mut self) {
+        if self.start {
+            self.start = false;
+            self.decay = 15;
+            self.divider = self.period;
+        } else if self.divider > 0 {
+            self.divider -= 1;
+        } else {
+            self.divider = self.period;
+            if self.decay > 0 {
+                self.decay -= 1;
+            } else if self.looping {
+                self.decay = 15;
+            }
+        }
+    }
+
+    fn is_valid(&self) -> bool {
+        self.period <= 15 && self.divider <= 15 && self.decay <= 15
+    }
+
+    fn volume(&self) -> u8 {
+        if self.constant {
+            self.period
+        } else {
+            self.decay
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+struct Pulse {
+    /// Pulse 1 negates its sweep with one's complement, so it sweeps down one further than pulse 2.
+    ones_complement: bool,
+    duty: u8,
+    step: u8,
+    period: u16,
+    timer: u16,
+    envelope: Envelope,
+    length: LengthCounter,
+    sweep_enabled: bool,
+    sweep_period: u8,
+    sweep_negate: bool,
+    sweep_shift: u8,
+    sweep_divider: u8,
+    sweep_reload: bool,
+}
+
+impl Pulse {
+    fn new(ones_complement: bool) -> Self {
+        Self {
+            ones_complement,
+            duty: 0,
+            step: 0,
+            period: 0,
+            timer: 0,
+            envelope: Envelope::default(),
+            length: LengthCounter::default(),
+            sweep_enabled: false,
+            sweep_period: 0,
+            sweep_negate: false,
+            sweep_shift: 0,
+            sweep_divider: 0,
+            sweep_reload: false,
+        }
+    }
+
+    fn write(&mut self, reg: u16, data: u8) {
+        match reg {
+            0 => {
+                self.duty = data >> 6;
+                self.length.halted = data & 0x20 != 0;
+                self.envelope.write(data);
+            }
+            1 => {
+                self.sweep_enabled = data & 0x80 != 0;
+                self.sweep_period = (data >> 4) & 0b111;
+                self.sweep_negate = data & 0x08 != 0;
+                self.sweep_shift = data & 0b111;
+                self.sweep_reload = true;
+            }
+            2 => self.period = (self.period & 0x0700) | u16::from(data),
+            _ => {
+                self.period = (self.period & 0x00FF) | (u16::from(data & 0b111) << 8);
+                self.length.load(data >> 3);
+                self.step = 0;
+                self.envelope.start = true;
+            }
+        }
+    }
+
+    fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.period;
+            self.step = (self.step + 1) & 7;
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    /// The period the sweep unit is heading for. The sweep mutes the channel while this is out of
+    /// range, even if it's disabled.
+    fn sweep_target(&self) -> u16 {
+        let change = self.period >> self.sweep_shift;
+        if !self.sweep_negate {
+            self.period + change
+        } else if self.ones_complement {
+            self.period.saturating_sub(change + 1)
+        } else {
+            self.period.saturating_sub(change)
+        }
+    }
+
+    fn is_valid(&self) -> bool {
+        self.duty < 4
+            && self.step < 8
+            && self.period <= 0x07FF
+            && self.sweep_shift <= 7
+            && self.sweep_period <= 7
+            && self.envelope.is_valid()
+    }
+
+    fn muted(&self) -> bool {
+        self.period < 8 || self.sweep_target() > 0x07FF
+    }
+
+    fn clock_sweep(&mut self) {
+        if self.sweep_divider == 0 && self.sweep_enabled && self.sweep_shift > 0 && !self.muted() {
+            self.period = self.sweep_target();
+        }
+        if self.sweep_divider == 0 || self.sweep_reload {
+            self.sweep_divider = self.sweep_period;
+            self.sweep_reload = false;
+        } else {
+            self.sweep_divider -= 1;
+        }
+    }
+
+    fn output(&self) -> u8 {
+        if !self.length.active()
+            || self.muted()
+            || DUTY_TABLE[usize::from(self.duty)][usize::from(self.step)] == 0
+        {
+            0
+        } else {
+            self.envelope.volume()
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+struct Triangle {
+    period: u16,
+    timer: u16,
+    step: u8,
+    length: LengthCounter,
+    /// A second length counter clocked every quarter frame. Both have to be running for the
+    /// sequencer to move.
+    linear_counter: u8,
+    linear_reload_value: u8,
+    linear_reload: bool,
+    /// Holds the linear counter reload flag set, and halts the length counter.
+    control: bool,
+}
+
+impl Triangle {
+    fn write(&mut self, reg: u16, data: u8) {
+        match reg {
+            0 => {
+                self.control = data & 0x80 != 0;
+                self.length.halted = self.control;
+                self.linear_reload_value = data & 0x7F;
+            }
+            1 => trace!("write {data:02X} to unused APU register $4009"),
+            2 => self.period = (self.period & 0x0700) | u16::from(data),
+            _ => {
+                self.period = (self.period & 0x00FF) | (u16::from(data & 0b111) << 8);
+                self.length.load(data >> 3);
+                self.linear_reload = true;
+            }
+        }
+    }
+
+    /// The triangle's timer runs at the full CPU rate, twice as fast as the others.
+    fn clock_timer(&mut self) {
+        if self.timer > 0 {
+            self.timer -= 1;
+            return;
+        }
+        self.timer = self.period;
+        // Periods under 2 run the sequencer at an ultrasonic 28-56kHz, which is inaudible except
+        // as a pop when it starts. Hold the current level instead, which is what the console's
+        // output filtering leaves of it anyway.
+        if self.length.active() && self.linear_counter > 0 && self.period >= 2 {
+            self.step = (self.step + 1) & 0x1F;
+        }
+    }
+
+    fn clock_linear(&mut self) {
+        if self.linear_reload {
+            self.linear_counter = self.linear_reload_value;
+        } else if self.linear_counter > 0 {
+            self.linear_counter -= 1;
+        }
+        if !self.control {
+            self.linear_reload = false;
+        }
+    }
+
+    fn is_valid(&self) -> bool {
+        self.step < 32 && self.period <= 0x07FF
+    }
+
+    /// The triangle is never muted by its counters; it stops where it is and keeps putting out
+    /// that level.
+    fn output(&self) -> u8 {
+        TRIANGLE_SEQUENCE[usize::from(self.step)]
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+struct Noise {
+    /// Taps bit 6 instead of bit 1, for a sequence 93 steps long instead of 32767.
+    short_mode: bool,
+    period: u16,
+    timer: u16,
+    /// 15-bit linear feedback shift register. The channel is silent while bit 0 is set.
+    shift: u16,
+    envelope: Envelope,
+    length: LengthCounter,
+    /// For the region's period table.
+    #[serde(with = "state::timing")]
+    timing: &'static Timing,
+}
+
+impl Noise {
+    fn new(timing: &'static Timing) -> Self {
+        Self {
+            short_mode: false,
+            period: timing.noise_periods[0],
+            timer: 0,
+            shift: 1,
+            envelope: Envelope::default(),
+            length: LengthCounter::default(),
+            timing,
+        }
+    }
+
+    fn write(&mut self, reg: u16, data: u8) {
+        match reg {
+            0 => {
+                self.length.halted = data & 0x20 != 0;
+                self.envelope.write(data);
+            }
+            1 => trace!("write {data:02X} to unused APU register $400D"),
+            2 => {
+                self.short_mode = data & 0x80 != 0;
+                self.period = self.timing.noise_periods[usize::from(data & 0x0F)];
+            }
+            _ => {
+                self.length.load(data >> 3);
+                self.envelope.start = true;
+            }
+        }
+    }
+
+    fn clock_timer(&mut self) {
+        if self.timer > 0 {
+            self.timer -= 1;
+            return;
+        }
+        self.timer = self.period - 1;
+        let tap = if self.short_mode { 6 } else { 1 };
+        let feedback = (self.shift ^ (self.shift >> tap)) & 1;
+        self.shift = (self.shift >> 1) | (feedback << 14);
+    }
+
+    fn is_valid(&self) -> bool {
+        self.timing.noise_periods.contains(&self.period) && self.envelope.is_valid()
+    }
+
+    fn output(&self) -> u8 {
+        if !self.length.active() || self.shift & 1 != 0 {
+            0
+        } else {
+            self.envelope.volume()
+        }
+    }
+}
+
+/// The delta modulation channel: 1-bit delta-encoded samples read from CPU memory, each bit moving
+/// a 7-bit output level up or down by 2.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+struct Dmc {
+    irq_enabled: bool,
+    looping: bool,
+    period: u16,
+    timer: u16,
+    level: u8,
+    sample_address: u16,
+    sample_length: u16,
+    /// The memory reader: the next byte to fetch and how many are left.
+    address: u16,
+    bytes_remaining: u16,
+    /// The fetched byte waiting to be played.
+    buffer: Option<u8>,
+    /// The output unit: the byte being played, bit by bit. With no byte to play it goes silent
+    /// for a byte's worth of bits.
+    shift: u8,
+    bits_remaining: u8,
+    silence: bool,
+    irq: bool,
+    /// For the region's rate table.
+    #[serde(with = "state::timing")]
+    timing: &'static Timing,
+}
+
+impl Dmc {
+    fn new(timing: &'static Timing) -> Self {
+        Self {
+            irq_enabled: false,
+            looping: false,
+            period: timing.dmc_rates[0],
+            timer: 0,
+            level: 0,
+            sample_address: 0xC000,
+            sample_length: 1,
+            address: 0xC000,
+            bytes_remaining: 0,
+            buffer: None,
+            shift: 0,
+            bits_remaining: 8,
+            silence: true,
+            irq: false,
+            timing,
+        }
+    }
+
+    fn write(&mut self, reg: u16, data: u8) {
+        match reg {
+            0 => {
+                self.irq_enabled = data & 0x80 != 0;
+                self.looping = data & 0x40 != 0;
+                self.period = self.timing.dmc_rates[usize::from(data & 0x0F)];
+                if !self.irq_enabled {
+                    self.irq = false;
+                }
+            }
+            1 => self.level = data & 0x7F,
+            2 => self.sample_address = 0xC000 | u16::from(data) << 6,
+            _ => self.sample_length = u16::from(data) << 4 | 1,
+        }
+    }
+
+    /// Writing $4015 acknowledges the IRQ, and starts the sample over if it had finished.
+    fn set_enabled(&mut self, enabled: bool) {
+        self.irq = false;
+        if !enabled {
+            self.bytes_remaining = 0;
+        } else if self.bytes_remaining == 0 {
+            self.restart();
+        }
+    }
+
+    fn restart(&mut self) {
+        self.address = self.sample_address;
+        self.bytes_remaining = self.sample_length;
+    }
+
+    fn fill(&mut self, data: u8) {
+        self.buffer = Some(data);
+        // The address wraps from $FFFF around to $8000.
+        self.address = self.address.checked_add(1).unwrap_or(0x8000);
+        self.bytes_remaining -= 1;
+        if self.bytes_remaining == 0 {
+            if self.looping {
+                self.restart();
+            } else if self.irq_enabled {
+                self.irq = true;
+            }
+        }
+    }
+
+    fn is_valid(&self) -> bool {
+        self.timing.dmc_rates.contains(&self.period)
+            && (1..=8).contains(&self.bits_remaining)
+            && self.level <= 0x7F
+    }
+
+    fn clock_timer(&mut self) {
+        if self.timer > 0 {
+            self.timer -= 1;
+            return;
+        }
+        self.timer = self.period - 1;
+
+        if !self.silence {
+            if self.shift & 1 != 0 {
+                if self.level <= 125 {
+                    self.level += 2;
+                }
+            } else if self.level >= 2 {
+                self.level -= 2;
+            }
+        }
+        self.shift >>= 1;
+        self.bits_remaining -= 1;
+        if self.bits_remaining == 0 {
+            self.bits_remaining = 8;
+            match self.buffer.take() {
+                Some(data) => {
+                    self.shift = data;
+                    self.silence = false;
+                }
+                None => self.silence = true,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pulse_duty_and_period() {
+        let mut apu = Apu::new();
+        apu.write_register(0x4015, 0b01);
+        // 25% duty, constant volume 9, period 8.
+        apu.write_register(0x4000, 0b0101_1001);
+        apu.write_register(0x4002, 8);
+        apu.write_register(0x4003, 0);
+
+        // Each step lasts 9 timer clocks, 18 CPU cycles.
+        let mut levels = [0; 8];
+        for level in &mut levels {
+            *level = apu.pulses[0].output();
+            for _ in 0..18 {
+                apu.tick();
+            }
+        }
+        assert_eq!(levels, [0, 9, 9, 0, 0, 0, 0, 0])
+    }
+
+    #[test]
+    fn test_frame_sequence() {
+        let mut apu = Apu::new();
+        apu.write_register(0x4015, 0b01);
+        apu.write_register(0x4003, 0b0001_1000);
+        assert_eq!(apu.pulses[0].length.counter, 2);
+
+        // Two half frames in a sequence of four quarter frames.
+        for _ in 0..14913 {
+            apu.tick();
+        }
+        assert_eq!(apu.pulses[0].length.counter, 1);
+        for _ in 14913..29829 {
+            apu.tick();
+        }
+        assert_eq!(apu.read_status() & 0b1, 0)
+    }
+
+    fn run(apu: &mut Apu, cycles: u32) {
+        for _ in 0..cycles {
+            apu.tick();
+        }
+    }
+
+    #[test]
+    fn test_mixer_tables() {
+        assert_eq!(PULSE_TABLE[0], 0.0);
+        assert!((PULSE_TABLE[30] - 0.2575).abs() < 1e-4);
+        assert!((TND_TABLE[202] - 0.7424).abs() < 1e-4);
+        assert_eq!(lookup(&PULSE_TABLE, 2.0), PULSE_TABLE[2]);
+        assert_eq!(
+            lookup(&PULSE_TABLE, 2.5),
+            (PULSE_TABLE[2] + PULSE_TABLE[3]) / 2.0
+        );
+        assert_eq!(lookup(&PULSE_TABLE, 40.0), PULSE_TABLE[30])
+    }
+
+    #[test]
+    fn test_mixer_is_non_linear() {
+        let mut apu = Apu::new();
+        // Only the DMC: the triangle would sit at the top of its sequence and add to the group.
+        let only_dmc = Volumes {
+            triangle: 0.0,
+            ..Volumes::default()
+        };
+        apu.set_volumes(only_dmc);
+        apu.write_register(0x4011, 0x7E);
+        let full = apu.output();
+        assert!((full - TND_TABLE[126]).abs() < 1e-6);
+        apu.write_register(0x4011, 0x3F);
+        // Half the level is more than half as loud.
+        assert!(apu.output() > full / 2.0);
+
+        apu.write_register(0x4011, 0x7E);
+        apu.set_volumes(Volumes {
+            dmc: 0.5,
+            ..only_dmc
+        });
+        assert!((apu.output() - TND_TABLE[63]).abs() < 1e-6);
+
+        apu.set_expansion_audio(0.25);
+        apu.set_volumes(Volumes {
+            dmc: 0.0,
+            expansion: 0.5,
+            ..only_dmc
+        });
+        assert_eq!(apu.output(), 0.125)
+    }
+
+    #[test]
+    fn test_sample_rate() {
+        let mut apu = Apu::new();
+        let mut samples = Vec::new();
+        // One second of CPU cycles.
+        run(&mut apu, 1_789_773);
+        apu.take_samples(&mut samples);
+        assert_eq!(samples.len(), 44_100);
+        apu.take_samples(&mut samples);
+        assert_eq!(samples.len(), 44_100);
+
+        apu.set_sample_rate(48_000);
+        samples.clear();
+        run(&mut apu, 1_789_773);
+        apu.take_samples(&mut samples);
+        assert!((47_999..=48_000).contains(&samples.len()))
+    }
+
+    #[test]
+    fn test_samples_average_the_output() {
+        let mut apu = Apu::new();
+        apu.set_sample_rate(1_789_772 / 4);
+        apu.filters_mut().clear();
+        apu.write_register(0x4011, 100);
+        let mut samples = Vec::new();
+        run(&mut apu, 40);
+        apu.take_samples(&mut samples);
+        assert!((9..=10).contains(&samples.len()));
+        assert!(samples
+            .iter()
+            .all(|&sample| (sample - apu.output()).abs() < 1e-6))
+    }
+
+    #[test]
+    fn test_output_is_filtered() {
+        let mut apu = Apu::new();
+        apu.write_register(0x4011, 100);
+        let mut samples = Vec::new();
+        run(&mut apu, 1_789_773);
+        apu.take_samples(&mut samples);
+        // The high-pass filters take out the DMC's constant level.
+        assert!(samples[0] > 0.1);
+        assert!(samples.last().unwrap().abs() < 1e-3)
+    }
+
+    #[test]
+    fn test_frame_irq() {
+        let mut apu = Apu::new();
+        run(&mut apu, 29827);
+        assert!(!apu.irq());
+        run(&mut apu, 1);
+        assert!(apu.irq());
+        // Still being set for two more cycles, so the acknowledgement doesn't take yet.
+        assert_eq!(apu.read_status(), 0x40);
+        run(&mut apu, 1);
+        assert!(apu.irq());
+        run(&mut apu, 1);
+        assert_eq!(apu.read_status(), 0x40);
+        assert_eq!(apu.read_status(), 0);
+        assert!(!apu.irq());
+
+        // The next sequence starts on the cycle after the last step.
+        run(&mut apu, 29827);
+        assert!(!apu.irq());
+        run(&mut apu, 1);
+        assert!(apu.irq());
+
+        // Inhibiting acknowledges it, and keeps it from coming back.
+        apu.write_register(0x4017, 0x40);
+        assert!(!apu.irq());
+        run(&mut apu, 2 * 29830);
+        assert!(!apu.irq())
+    }
+
+    #[test]
+    fn test_five_step_mode() {
+        let mut apu = Apu::new();
+        apu.write_register(0x4015, 0b01);
+        apu.write_register(0x4003, 0b0001_1000);
+        assert_eq!(apu.pulses[0].length.counter, 2);
+        // Even cycle: the sequence restarts three cycles later, clocking a half frame at once.
+        apu.write_register(0x4017, 0x80);
+        run(&mut apu, 2);
+        assert_eq!(apu.pulses[0].length.counter, 2);
+        run(&mut apu, 1);
+        assert_eq!(apu.pulses[0].length.counter, 1);
+
+        // The next half frames are at the second and fifth steps, with no IRQ at all.
+        run(&mut apu, 14913);
+        assert_eq!(apu.pulses[0].length.counter, 0);
+        apu.write_register(0x4003, 0b0001_1000);
+        run(&mut apu, 37281 - 14913 - 1);
+        assert_eq!(apu.pulses[0].length.counter, 2);
+        run(&mut apu, 1);
+        assert_eq!(apu.pulses[0].length.counter, 1);
+        assert!(!apu.irq())
+    }
+
+    #[test]
+    fn test_frame_counter_reset_delay() {
+        let mut apu = Apu::new();
+        run(&mut apu, 1);
+        // Odd cycle: four cycles.
+        apu.write_register(0x4017, 0x00);
+        run(&mut apu, 3);
+        assert!(apu.frame_reset_delay.is_some());
+        run(&mut apu, 1);
+        assert_eq!(apu.frame_reset_delay, None);
+        assert_eq!(apu.frame_cycle, 0)
+    }
+
+    /// Enables the triangle with `period` and the longest linear counter.
+    fn start_triangle(apu: &mut Apu, period: u16) {
+        apu.write_register(0x4015, 0b100);
+        apu.write_register(0x4008, 0x7F);
+        apu.write_register(0x400A, period as u8);
+        apu.write_register(0x400B, (period >> 8) as u8);
+        apu.quarter_frame();
+    }
+
+    #[test]
+    fn test_triangle_sequence() {
+        let mut apu = Apu::new();
+        start_triangle(&mut apu, 3);
+        assert_eq!(apu.read_status(), 0b100);
+
+        // One step every period + 1 CPU cycles.
+        let mut levels = [0; 18];
+        for level in &mut levels {
+            for _ in 0..4 {
+                apu.triangle.clock_timer();
+            }
+            *level = apu.triangle.output();
+        }
+        assert_eq!(
+            levels,
+            [14, 13, 12, 11, 10, 9, 8, 7, 6, 5, 4, 3, 2, 1, 0, 0, 1, 2]
+        )
+    }
+
+    #[test]
+    fn test_triangle_linear_counter() {
+        let mut apu = Apu::new();
+        start_triangle(&mut apu, 3);
+        // Without the control flag, the reload flag clears and the counter runs down.
+        apu.write_register(0x4008, 0x02);
+        apu.write_register(0x400B, 0);
+        apu.quarter_frame();
+        assert_eq!(apu.triangle.linear_counter, 2);
+        apu.quarter_frame();
+        apu.quarter_frame();
+        assert_eq!(apu.triangle.linear_counter, 0);
+
+        // The sequencer stops but the output holds.
+        let level = apu.triangle.output();
+        for _ in 0..64 {
+            apu.triangle.clock_timer();
+        }
+        assert_eq!(apu.triangle.output(), level);
+        // It's still playing as far as the length counter is concerned.
+        assert_eq!(apu.read_status(), 0b100)
+    }
+
+    #[test]
+    fn test_triangle_ultrasonic_periods_hold() {
+        let mut apu = Apu::new();
+        start_triangle(&mut apu, 1);
+        for _ in 0..64 {
+            apu.triangle.clock_timer();
+        }
+        assert_eq!(apu.triangle.output(), 15)
+    }
+
+    /// Steps the noise shift register once.
+    fn step_noise(noise: &mut Noise) {
+        for _ in 0..noise.period {
+            noise.clock_timer();
+        }
+    }
+
+    #[test]
+    fn test_noise_sequence_lengths() {
+        let mut noise = Noise::new(Region::Ntsc.timing());
+        let mut length = 0;
+        loop {
+            step_noise(&mut noise);
+            length += 1;
+            if noise.shift == 1 {
+                break;
+            }
+        }
+        assert_eq!(length, 32767);
+
+        // Short mode from the same seed.
+        noise.write(2, 0x80);
+        for _ in 0..93 {
+            step_noise(&mut noise);
+        }
+        assert_eq!(noise.shift, 1)
+    }
+
+    #[test]
+    fn test_noise_output() {
+        let mut apu = Apu::new();
+        apu.write_register(0x4015, 0b1000);
+        apu.write_register(0x400C, 0x1A);
+        apu.write_register(0x400E, 0x00);
+        apu.write_register(0x400F, 0x08);
+        assert_eq!(apu.read_status(), 0b1000);
+
+        // The seed has bit 0 set. The first shift feeds a 1 into bit 14 and leaves bit 0 clear.
+        assert_eq!(apu.noise.output(), 0);
+        step_noise(&mut apu.noise);
+        assert_eq!(apu.noise.shift, 0x4000);
+        assert_eq!(apu.noise.output(), 10);
+
+        apu.write_register(0x4015, 0);
+        assert_eq!(apu.noise.output(), 0)
+    }
+
+    /// Serves DMC fetches from `memory`, which stands in for $C000 onwards.
+    fn run_dmc(apu: &mut Apu, memory: &[u8], cycles: u32) {
+        for _ in 0..cycles {
+            apu.tick();
+            if let Some(addr) = apu.dmc_request() {
+                apu.dmc_fill(memory[usize::from(addr - 0xC000)]);
+            }
+        }
+    }
+
+    #[test]
+    fn test_dmc_playback() {
+        let mut apu = Apu::new();
+        // Fastest rate, 17 bytes at $C040.
+        apu.write_register(0x4010, 0x0F);
+        apu.write_register(0x4011, 0x40);
+        apu.write_register(0x4012, 0x01);
+        apu.write_register(0x4013, 0x01);
+        assert_eq!(apu.dmc_request(), None);
+        apu.write_register(0x4015, 0x10);
+        assert_eq!(apu.dmc_request(), Some(0xC040));
+
+        let mut memory = [0; 0x51];
+        memory[0x40] = 0xFF;
+        // The first byte goes to the output unit at the end of its silent first byte, then each
+        // bit takes one period.
+        run_dmc(&mut apu, &memory, 8 * 54);
+        assert_eq!(apu.dmc.level, 0x40);
+        run_dmc(&mut apu, &memory, 8 * 54);
+        assert_eq!(apu.dmc.level, 0x40 + 16);
+        run_dmc(&mut apu, &memory, 8 * 54);
+        assert_eq!(apu.dmc.level, 0x40);
+        assert_eq!(apu.read_status() & 0x10, 0x10);
+
+        run_dmc(&mut apu, &memory, 14 * 8 * 54);
+        assert_eq!(apu.read_status() & 0x10, 0);
+        assert_eq!(apu.dmc_request(), None)
+    }
+
+    #[test]
+    fn test_dmc_irq_and_loop() {
+        let mut apu = Apu::new();
+        apu.write_register(0x4010, 0x8F);
+        apu.write_register(0x4015, 0x10);
+        run_dmc(&mut apu, &[0x55], 1);
+        assert!(apu.irq());
+        assert_eq!(apu.read_status() & 0x80, 0x80);
+        // Writing $4015 acknowledges it.
+        apu.write_register(0x4015, 0x00);
+        assert!(!apu.irq());
+
+        // Looping samples restart instead of raising the IRQ.
+        apu.write_register(0x4010, 0xCF);
+        apu.write_register(0x4015, 0x10);
+        run_dmc(&mut apu, &[0x55], 54 * 8 * 3);
+        assert!(!apu.irq());
+        assert_eq!(apu.read_status() & 0x10, 0x10)
+    }
+
+    #[test]
+    fn test_dmc_address_wraps() {
+        let mut dmc = Dmc::new(Region::Ntsc.timing());
+        dmc.address = 0xFFFF;
+        dmc.bytes_remaining = 2;
+        dmc.fill(0);
+        assert_eq!(dmc.address, 0x8000)
+    }
+
+    #[test]
+    fn test_pal_periods() {
+        let mut apu = Apu::with_region(Region::Pal);
+        apu.write_register(0x400E, 0x0F);
+        assert_eq!(apu.noise.period, 3778);
+        apu.write_register(0x4010, 0x0F);
+        assert_eq!(apu.dmc.period, 50);
+        // The frame IRQ comes at the end of PAL's longer sequence.
+        run(&mut apu, 33251);
+        assert!(!apu.irq());
+        run(&mut apu, 1);
+        assert!(apu.irq())
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(Apu::new().validate().is_ok());
+        let corruptions: [fn(&mut Apu); 6] = [
+            |apu| apu.triangle.step = 32,
+            |apu| apu.pulses[1].duty = 4,
+            |apu| apu.pulses[0].step = 8,
+            |apu| apu.noise.period = 0,
+            |apu| apu.dmc.bits_remaining = 0,
+            |apu| apu.frame_reset_delay = Some(0),
+        ];
+        for corrupt in corruptions {
+            let mut apu = Apu::new();
+            corrupt(&mut apu);
+            assert!(matches!(apu.validate(), Err(NesError::InvalidState(_))));
+        }
+    }
+
+    #[test]
+    fn test_length_counter() {
+        let mut apu = Apu::new();
+        // Loading while disabled does nothing.
+        apu.write_register(0x4003, 0b0000_1000);
+        assert_eq!(apu.read_status(), 0);
+
+        apu.write_register(0x4015, 0b11);
+        apu.write_register(0x4007, 0b0000_1000);
+        assert_eq!(apu.read_status(), 0b10);
+        // Index 1 is 254 half frames.
+        for _ in 0..253 {
+            apu.half_frame();
+        }
+        assert_eq!(apu.read_status(), 0b10);
+        apu.half_frame();
+        assert_eq!(apu.read_status(), 0);
+
+        // Halted counters hold, and disabling clears them.
+        apu.write_register(0x4004, 0x20);
+        apu.write_register(0x4007, 0b0000_1000);
+        apu.half_frame();
+        assert_eq!(apu.pulses[1].length.counter, 254);
+        apu.write_register(0x4015, 0);
+        assert_eq!(apu.read_status(), 0)
+    }
+
+    #[test]
+    fn test_envelope_decay() {
+        let mut envelope = Envelope::default();
+        envelope.write(0x02);
+        envelope.start = true;
+        envelope.clock();
+        assert_eq!(envelope.volume(), 15);
+        // One step down every period + 1 clocks.
+        for _ in 0..3 {
+            envelope.clock();
+        }
+        assert_eq!(envelope.volume(), 14);
+        for _ in 0..14 * 3 {
+            envelope.clock();
+        }
+        assert_eq!(envelope.volume(), 0);
+        for _ in 0..3 {
+            envelope.clock();
+        }
+        assert_eq!(envelope.volume(), 0);
+
+        envelope.write(0x22);
+        for _ in 0..3 {
+            envelope.clock();
+        }
+        assert_eq!(envelope.volume(), 15)
+    }
+
+    #[test]
+    fn test_sweep() {
+        let mut pulse = Pulse::new(true);
+        pulse.write(2, 0x00);
+        pulse.write(3, 0x01);
+        // Enabled, period 0, shift 1, upward.
+        pulse.write(1, 0b1000_0001);
+        pulse.clock_sweep();
+        assert_eq!(pulse.period, 0x180);
+
+        // Downward: pulse 1 subtracts one more than pulse 2.
+        pulse.write(1, 0b1000_1001);
+        pulse.clock_sweep();
+        assert_eq!(pulse.period, 0x180 - 0xC0 - 1);
+        let mut pulse = Pulse::new(false);
+        pulse.write(2, 0x80);
+        pulse.write(1, 0b1000_1001);
+        pulse.clock_sweep();
+        assert_eq!(pulse.period, 0x40)
+    }
+
+    #[test]
+    fn test_sweep_mutes() {
+        let mut pulse = Pulse::new(false);
+        pulse.length.set_enabled(true);
+        pulse.write(0, 0b1001_1111);
+        pulse.write(3, 0b0000_1001);
+        pulse.step = 1;
+        assert_eq!(pulse.output(), 15);
+
+        // A target past $7FF mutes even with the sweep disabled. With a shift of 0 the target is
+        // double the period.
+        pulse.write(3, 0b0000_1100);
+        assert_eq!(pulse.output(), 0);
+        // So does a period under 8.
+        pulse.write(3, 0);
+        pulse.write(2, 7);
+        assert_eq!(pulse.output(), 0)
+    }
+}
@@ -0,0 +1,735 @@
+//! The CPU's view of the NES address space.
+//!
+//! ```text
+//! $0000-$07FF  2KB internal RAM
+//! $0800-$1FFF  mirrors of $0000-$07FF
+//! $2000-$2007  PPU registers
+//! $2008-$3FFF  mirrors of $2000-$2007, every 8 bytes
+//! $4000-$4017  APU and I/O registers
+//! $4018-$401F  normally disabled APU/I/O test mode
+//! $4020-$FFFF  cartridge space: PRG ROM, PRG RAM and mapper registers
+//! ```
+//!
+//! The CPU runs a whole instruction before [`Bus::tick`] lets the PPU, APU and cartridge catch up
+//! with it, so they are only in step between instructions. A register access partway through an
+//! instruction sees them as they were when it started: the PPUSTATUS read in an `LDA $2002` finds
+//! the PPU 9 dots behind where it would be on hardware, more for slower instructions. Games that
+//! poll for vblank or sprite 0 don't notice, but the dot-exact races around the start of vblank
+//! land that much off.
+
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::any::Any;
+
+use crate::apu::{self, Apu};
+use crate::cartridge::Cartridge;
+use crate::cheats::Cheats;
+use crate::clock::MasterClock;
+use crate::error::{NesError, Result};
+use crate::input::{ControllerPort, Joypad};
+use crate::mapper::{self, Mapper};
+use crate::mem::Mem;
+use crate::ppu::{self, Ppu};
+use crate::region::Region;
+use crate::state::{StateReader, StateWriter};
+
+/// Size of the console's internal work RAM.
+pub const RAM_SIZE: usize = 0x0800;
+
+const RAM: u16 = 0x0000;
+const RAM_MIRRORS_END: u16 = 0x1FFF;
+const PPU_REGISTERS: u16 = 0x2000;
+const PPU_REGISTERS_MIRRORS_END: u16 = 0x3FFF;
+const APU_REGISTERS: u16 = 0x4000;
+const APU_REGISTERS_END: u16 = 0x4013;
+const OAM_DMA: u16 = 0x4014;
+const APU_STATUS: u16 = 0x4015;
+/// Reads return controller 1; writes strobe both controller ports.
+const JOY1: u16 = 0x4016;
+/// Reads return controller 2; writes go to the APU frame counter.
+const JOY2: u16 = 0x4017;
+const TEST_MODE: u16 = 0x4018;
+const TEST_MODE_END: u16 = 0x401F;
+const CARTRIDGE: u16 = 0x4020;
+
+/// The number of controller ports, $4016 and $4017.
+pub const CONTROLLER_PORTS: usize = 2;
+
+/// The cartridge's part of a save state: its PRG RAM, its CHR RAM if it has any, and the board's
+/// own state.
+type CartridgeState = (Vec<u8>, Option<Vec<u8>>, Vec<u8>);
+
+/// CPU cycles an OAM DMA holds the CPU for, plus one more when it starts on an odd cycle.
+const OAM_DMA_CYCLES: u32 = 513;
+
+pub struct Bus {
+    cpu_ram: [u8; RAM_SIZE],
+    /// The last value driven on the data bus. Reads from unmapped addresses see it again.
+    open_bus: u8,
+    mapper: Option<Box<dyn Mapper>>,
+    ppu: Ppu,
+    apu: Apu,
+    /// What's plugged into each controller port.
+    controllers: [Option<Box<dyn ControllerPort>>; CONTROLLER_PORTS],
+    region: Region,
+    /// Paces the PPU against the CPU.
+    clock: MasterClock,
+    /// CPU cycles run since power-on, counting DMA stalls.
+    cycles: u64,
+    /// An OAM DMA was started and the CPU hasn't been stalled for it yet.
+    dma_pending: bool,
+    /// Codes patching what the CPU reads from the cartridge.
+    cheats: Cheats,
+}
+
+impl Bus {
+    /// A bus with an empty cartridge slot.
+    pub fn new() -> Self {
+        Self {
+            cpu_ram: [0; RAM_SIZE],
+            open_bus: 0,
+            mapper: None,
+            ppu: Ppu::new(),
+            apu: Apu::new(),
+            controllers: [Some(Box::new(Joypad::new())), Some(Box::new(Joypad::new()))],
+            region: Region::default(),
+            clock: MasterClock::new(Region::default().timing()),
+            cycles: 0,
+            dma_pending: false,
+            cheats: Cheats::new(),
+        }
+    }
+
+    /// A bus with `cart` inserted, timed for the region its header asks for.
+    ///
+    /// # Errors
+    /// Returns [`NesError::UnsupportedMapper`](crate::NesError::UnsupportedMapper) when the
+    /// cartridge's board isn't implemented.
+    pub fn with_cartridge(cart: Cartridge) -> Result<Self> {
+        let region = cart.region;
+        Self::with_region(cart, region)
+    }
+
+    /// A bus with `cart` inserted, in a `region` console whatever the cartridge says.
+    ///
+    /// # Errors
+    /// Returns [`NesError::UnsupportedMapper`](crate::NesError::UnsupportedMapper) when the
+    /// cartridge's board isn't implemented.
+    pub fn with_region(cart: Cartridge, region: Region) -> Result<Self> {
+        Ok(Self {
+            mapper: Some(mapper::from_cartridge(cart)?),
+            ppu: Ppu::with_region(region),
+            apu: Apu::with_region(region),
+            clock: MasterClock::new(region.timing()),
+            region,
+            ..Self::new()
+        })
+    }
+
+    pub fn region(&self) -> Region {
+        self.region
+    }
+
+    /// The inserted cartridge, if any.
+    pub fn cartridge(&self) -> Option<&Cartridge> {
+        self.mapper.as_deref().map(Mapper::cartridge)
+    }
+
+    pub fn cartridge_mut(&mut self) -> Option<&mut Cartridge> {
+        self.mapper.as_deref_mut().map(Mapper::cartridge_mut)
+    }
+
+    pub fn cheats(&self) -> &Cheats {
+        &self.cheats
+    }
+
+    pub fn cheats_mut(&mut self) -> &mut Cheats {
+        &mut self.cheats
+    }
+
+    pub fn ppu(&self) -> &Ppu {
+        &self.ppu
+    }
+
+    pub fn ppu_mut(&mut self) -> &mut Ppu {
+        &mut self.ppu
+    }
+
+    pub fn apu(&self) -> &Apu {
+        &self.apu
+    }
+
+    pub fn apu_mut(&mut self) -> &mut Apu {
+        &mut self.apu
+    }
+
+    /// Plugs `device` into `port` (0 or 1), or leaves the port empty with `None`. Returns what was
+    /// plugged in before.
+    ///
+    /// # Panics
+    /// If `port` is out of range.
+    pub fn set_controller(
+        &mut self,
+        port: usize,
+        device: Option<Box<dyn ControllerPort>>,
+    ) -> Option<Box<dyn ControllerPort>> {
+        core::mem::replace(&mut self.controllers[port], device)
+    }
+
+    /// The device in `port`, if it is a `T`.
+    ///
+    /// # Panics
+    /// If `port` is out of range.
+    pub fn controller<T: ControllerPort>(&self, port: usize) -> Option<&T> {
+        let device: &dyn Any = self.controllers[port].as_deref()?;
+        device.downcast_ref()
+    }
+
+    pub fn controller_mut<T: ControllerPort>(&mut self, port: usize) -> Option<&mut T> {
+        let device: &mut dyn Any = self.controllers[port].as_deref_mut()?;
+        device.downcast_mut()
+    }
+
+    /// The standard controller in `port`, for frontends to feed button state into. `None` if
+    /// something else is plugged in.
+    pub fn joypad_mut(&mut self, port: usize) -> Option<&mut Joypad> {
+        self.controller_mut(port)
+    }
+
+    /// Passes the reset line on to the PPU, APU and cartridge. RAM, VRAM and the cartridge's memory
+    /// are kept.
+    pub fn reset(&mut self) {
+        self.ppu.reset();
+        self.apu.reset();
+        if let Some(mapper) = self.mapper.as_deref_mut() {
+            mapper.reset();
+        }
+        self.dma_pending = false;
+    }
+
+    /// Switches the console off and on again: everything starts over from its power-on state,
+    /// the cartridge's board included. Only battery-backed PRG RAM keeps its contents. Host
+    /// settings, the devices in the controller ports and cheats stay as they are. Work RAM is
+    /// left for the CPU to fill.
+    pub fn power_cycle(&mut self) {
+        if let Some(mut mapper) = self.mapper.take() {
+            let mut cart = core::mem::take(mapper.cartridge_mut());
+            if !cart.battery {
+                cart.prg_ram.fill(0);
+            }
+            if cart.chr_ram {
+                cart.chr_rom.fill(0);
+            }
+            self.mapper =
+                Some(mapper::from_cartridge(cart).expect("the board was built from it before"));
+        }
+        self.open_bus = 0;
+        self.ppu.restore(Ppu::with_region(self.region));
+        self.apu.restore(Apu::with_region(self.region));
+        self.clock = MasterClock::new(self.region.timing());
+        self.cycles = 0;
+        self.dma_pending = false;
+    }
+
+    /// Lets the controller port devices know a frame has gone by.
+    pub fn end_frame(&mut self) {
+        for device in self.controllers.iter_mut().flatten() {
+            device.end_frame();
+        }
+    }
+
+    /// The PPU along with the cartridge board it reads through, for the PPU's debug views. `None`
+    /// with no cartridge inserted.
+    pub fn ppu_and_mapper(&mut self) -> Option<(&Ppu, &mut dyn Mapper)> {
+        let mapper = self.mapper.as_deref_mut()?;
+        Some((&self.ppu, mapper))
+    }
+
+    /// Writes the RAM, the PPU and APU, the cartridge board and the controller port devices to a
+    /// save state.
+    pub(crate) fn save_state(&self, writer: &mut StateWriter) {
+        writer.write(&self.cpu_ram[..]);
+        writer.write(&self.open_bus);
+        writer.write(&self.ppu);
+        writer.write(&self.apu);
+        writer.write(&self.clock);
+        writer.write(&(self.cycles, self.dma_pending));
+        let cart = self.mapper.as_deref().map(|mapper| {
+            let cart = mapper.cartridge();
+            let chr_ram = cart.chr_ram.then_some(&cart.chr_rom[..]);
+            (&cart.prg_ram[..], chr_ram, mapper.save_state())
+        });
+        writer.write(&cart);
+        let devices = self
+            .controllers
+            .each_ref()
+            .map(|device| device.as_deref().map(ControllerPort::save_state));
+        writer.write(&devices);
+    }
+
+    /// Reads back what [`Bus::save_state`] wrote. The same kind of cartridge board and controller
+    /// devices have to be plugged in.
+    pub(crate) fn load_state(&mut self, reader: &mut StateReader) -> Result<()> {
+        let mut cpu_ram = [0; RAM_SIZE];
+        reader.read_into(&mut cpu_ram)?;
+        let open_bus = reader.read()?;
+        let ppu: Ppu = reader.read()?;
+        let apu: Apu = reader.read()?;
+        let clock: MasterClock = reader.read()?;
+        let (cycles, dma_pending) = reader.read()?;
+        let cart: Option<CartridgeState> = reader.read()?;
+        let devices: [Option<Vec<u8>>; CONTROLLER_PORTS] = reader.read()?;
+        ppu.validate()?;
+        apu.validate()?;
+        clock.validate()?;
+
+        match (self.mapper.as_deref_mut(), cart) {
+            (Some(mapper), Some((prg_ram, chr_ram, board))) => {
+                let cartridge = mapper.cartridge();
+                let chr_ram_fits = match &chr_ram {
+                    Some(chr_ram) => cartridge.chr_ram && chr_ram.len() == cartridge.chr_rom.len(),
+                    None => !cartridge.chr_ram,
+                };
+                if prg_ram.len() != cartridge.prg_ram.len() || !chr_ram_fits {
+                    return Err(NesError::InvalidState(
+                        "save state's cartridge RAM doesn't fit this cartridge".into(),
+                    ));
+                }
+                mapper.load_state(&board)?;
+                let cartridge = mapper.cartridge_mut();
+                cartridge.prg_ram = prg_ram;
+                if let Some(chr_ram) = chr_ram {
+                    cartridge.chr_rom = chr_ram;
+                }
+            }
+            (None, None) => {}
+            _ => {
+                return Err(NesError::InvalidState(
+                    "save state doesn't match the cartridge slot".into(),
+                ))
+            }
+        }
+        for (port, (device, state)) in self.controllers.iter_mut().zip(devices).enumerate() {
+            match (device, state) {
+                (Some(device), Some(state)) => device.load_state(&state)?,
+                (None, None) => {}
+                _ => {
+                    return Err(NesError::InvalidState(alloc::format!(
+                        "save state has a different device in controller port {}",
+                        port + 1
+                    )))
+                }
+            }
+        }
+
+        self.cpu_ram = cpu_ram;
+        self.open_bus = open_bus;
+        self.ppu.restore(ppu);
+        self.apu.restore(apu);
+        self.clock = clock;
+        self.cycles = cycles;
+        self.dma_pending = dma_pending;
+        Ok(())
+    }
+
+    pub fn clock(&self) -> &MasterClock {
+        &self.clock
+    }
+
+    /// CPU cycles run since power-on, including DMA stalls.
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    /// Whether anything is holding the CPU's IRQ line: the APU or the cartridge.
+    pub fn irq(&self) -> bool {
+        self.apu.irq() || self.mapper.as_deref().is_some_and(Mapper::irq)
+    }
+
+    /// Lets everything clocked alongside the CPU catch up with an instruction that took `cycles`
+    /// CPU cycles, once it has run. If the instruction started an OAM DMA, the CPU is halted for the 513 or 514
+    /// cycles it takes as well, and DMC sample fetches along the way steal a few more.
+    ///
+    /// Returns the cycles run, stalls included.
+    pub fn tick(&mut self, cycles: u8) -> u32 {
+        let mut cycles = self.run(u32::from(cycles));
+        if core::mem::take(&mut self.dma_pending) {
+            let stall = OAM_DMA_CYCLES + (self.cycles & 1) as u32;
+            cycles += self.run(stall);
+        }
+        cycles
+    }
+
+    /// Runs `cycles` CPU cycles, plus whatever the DMC steals. Returns the total.
+    fn run(&mut self, cycles: u32) -> u32 {
+        let mut remaining = cycles;
+        let mut total = 0;
+        while remaining > 0 {
+            remaining -= 1;
+            total += 1;
+            self.cycles += 1;
+            let dots = self.clock.cpu_cycle();
+            if let Some(mapper) = self.mapper.as_deref_mut() {
+                mapper.cpu_clock();
+                self.apu.set_expansion_audio(mapper.expansion_audio());
+                for _ in 0..dots {
+                    self.ppu.tick(mapper);
+                }
+            }
+            self.apu.tick();
+            if let Some(addr) = self.apu.dmc_request() {
+                let data = self.read(addr);
+                self.apu.dmc_fill(data);
+                remaining += apu::DMC_STALL_CYCLES;
+            }
+        }
+        total
+    }
+}
+
+impl Default for Bus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Bus {
+    /// Collapses an address in $2000-$3FFF onto the eight PPU registers.
+    fn ppu_register(addr: u16) -> u16 {
+        PPU_REGISTERS | (addr & 0x0007)
+    }
+
+    /// The PPU fetches through the cartridge, so without one it can't be reached at all.
+    fn ppu_read(&mut self, reg: u16) -> u8 {
+        match self.mapper.as_deref_mut() {
+            Some(mapper) => self.ppu.read_register(reg, mapper),
+            None => {
+                trace!("read from PPU register {reg:04X}, no cartridge inserted");
+                self.open_bus
+            }
+        }
+    }
+
+    fn ppu_write(&mut self, reg: u16, data: u8) {
+        match self.mapper.as_deref_mut() {
+            Some(mapper) => {
+                mapper.ppu_register_write(reg, data);
+                self.ppu.write_register(reg, data, mapper);
+            }
+            None => trace!("write {data:02X} to PPU register {reg:04X}, no cartridge inserted"),
+        }
+    }
+
+    /// Copies the 256-byte page `$XX00-$XXFF` into OAM through OAMDATA. The copy happens at once;
+    /// the time it takes is paid in the next [`tick`](Self::tick).
+    fn oam_dma(&mut self, page: u8) {
+        self.dma_pending = true;
+        let start = u16::from(page) << 8;
+        for offset in 0..=0xFF {
+            let data = self.read(start | offset);
+            self.ppu_write(ppu::OAMDATA, data);
+        }
+    }
+
+    /// $4015 drives every bit but bit 5, which floats.
+    fn apu_read(&mut self) -> u8 {
+        self.apu.read_status() | (self.open_bus & 0x20)
+    }
+
+    /// Controllers only drive the low five bits; the rest float.
+    fn joypad_read(&mut self, port: usize) -> u8 {
+        let data = match self.controllers[port].as_deref_mut() {
+            Some(device) => {
+                device.observe(&self.ppu);
+                device.read() & 0x1F
+            }
+            None => {
+                trace!("read from controller port {port}, nothing plugged in");
+                0
+            }
+        };
+        data | (self.open_bus & 0xE0)
+    }
+
+    fn joypad_strobe(&mut self, data: u8) {
+        for device in self.controllers.iter_mut().flatten() {
+            device.write(data);
+        }
+    }
+}
+
+impl Mem for Bus {
+    fn read(&mut self, addr: u16) -> u8 {
+        let data = match addr {
+            RAM..=RAM_MIRRORS_END => self.cpu_ram[(addr & 0x07FF) as usize],
+            PPU_REGISTERS..=PPU_REGISTERS_MIRRORS_END => self.ppu_read(Self::ppu_register(addr)),
+            APU_STATUS => self.apu_read(),
+            JOY1 => self.joypad_read(0),
+            JOY2 => self.joypad_read(1),
+            // The remaining APU registers and OAMDMA are write-only.
+            APU_REGISTERS..=APU_REGISTERS_END | OAM_DMA | TEST_MODE..=TEST_MODE_END => {
+                trace!("read from write-only or disabled register {addr:04X}");
+                self.open_bus
+            }
+            CARTRIDGE..=0xFFFF => match &mut self.mapper {
+                Some(mapper) => {
+                    let data = mapper.cpu_read(addr).unwrap_or(self.open_bus);
+                    self.cheats.apply(addr, data)
+                }
+                None => {
+                    trace!("read from cartridge space {addr:04X}, no cartridge inserted");
+                    self.open_bus
+                }
+            },
+        };
+        self.open_bus = data;
+        data
+    }
+
+    fn write(&mut self, addr: u16, data: u8) {
+        self.open_bus = data;
+        match addr {
+            RAM..=RAM_MIRRORS_END => self.cpu_ram[(addr & 0x07FF) as usize] = data,
+            PPU_REGISTERS..=PPU_REGISTERS_MIRRORS_END => {
+                self.ppu_write(Self::ppu_register(addr), data)
+            }
+            APU_REGISTERS..=APU_REGISTERS_END | APU_STATUS | JOY2 => {
+                self.apu.write_register(addr, data)
+            }
+            OAM_DMA => self.oam_dma(data),
+            JOY1 => self.joypad_strobe(data),
+            TEST_MODE..=TEST_MODE_END => {
+                trace!("write {data:02X} to disabled test register {addr:04X}");
+            }
+            CARTRIDGE..=0xFFFF => match &mut self.mapper {
+                Some(mapper) => mapper.cpu_write(addr, data),
+                None => {
+                    trace!("write {data:02X} to cartridge space {addr:04X}, no cartridge inserted");
+                }
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::test_image;
+    use crate::cpu::CPU;
+    use crate::input::{Button, FourScore};
+
+    #[test]
+    fn test_ram_is_mirrored() {
+        let mut bus = Bus::new();
+        bus.write(0x0012, 0x34);
+
+        assert_eq!(bus.read(0x0812), 0x34);
+        assert_eq!(bus.read(0x1012), 0x34);
+        assert_eq!(bus.read(0x1812), 0x34);
+
+        bus.write(0x1fff, 0x56);
+        assert_eq!(bus.read(0x07ff), 0x56)
+    }
+
+    #[test]
+    fn test_ppu_registers_are_mirrored_every_8_bytes() {
+        assert_eq!(Bus::ppu_register(0x2000), 0x2000);
+        assert_eq!(Bus::ppu_register(0x2008), 0x2000);
+        assert_eq!(Bus::ppu_register(0x3456), 0x2006);
+        assert_eq!(Bus::ppu_register(0x3fff), 0x2007)
+    }
+
+    #[test]
+    fn test_unmapped_reads_return_open_bus() {
+        let mut bus = Bus::new();
+        bus.write(0x0000, 0x5a);
+        assert_eq!(bus.read(0x4018), 0x5a);
+
+        bus.write(0x0000, 0xff);
+        assert_eq!(bus.read(0x4016) & 0xe0, 0xe0)
+    }
+
+    #[test]
+    fn test_joypads() {
+        let mut bus = Bus::new();
+        bus.joypad_mut(0).unwrap().set_button(Button::B, true);
+        bus.joypad_mut(1).unwrap().set_button(Button::A, true);
+        bus.write(0x4016, 1);
+        bus.write(0x4016, 0);
+        assert_eq!(bus.read(0x4016) & 0x1F, 0);
+        assert_eq!(bus.read(0x4016) & 0x1F, 1);
+        assert_eq!(bus.read(0x4017) & 0x1F, 1);
+        assert_eq!(bus.read(0x4017) & 0x1F, 0)
+    }
+
+    /// Counts the strobes it sees and reads back as all five lines high.
+    struct Probe(u8);
+
+    impl ControllerPort for Probe {
+        fn write(&mut self, _data: u8) {
+            self.0 += 1;
+        }
+
+        fn read(&mut self) -> u8 {
+            0xFF
+        }
+    }
+
+    #[test]
+    fn test_controller_ports() {
+        let mut bus = Bus::new();
+        let joypad = bus.set_controller(1, Some(Box::new(Probe(0))));
+        assert!(joypad.is_some());
+        assert!(bus.joypad_mut(1).is_none());
+
+        bus.write(0x0000, 0x00);
+        assert_eq!(bus.read(0x4017), 0x1F);
+        bus.write(0x4016, 1);
+        assert_eq!(bus.controller::<Probe>(1).unwrap().0, 1);
+
+        bus.set_controller(0, None);
+        bus.write(0x0000, 0xFF);
+        assert_eq!(bus.read(0x4016), 0xE0)
+    }
+
+    #[test]
+    fn test_four_score() {
+        let mut bus = Bus::new();
+        bus.set_controller(0, Some(Box::new(FourScore::port1())));
+        bus.set_controller(1, Some(Box::new(FourScore::port2())));
+        let four_score = bus.controller_mut::<FourScore>(1).unwrap();
+        four_score.joypad_mut(1).set_button(Button::A, true);
+
+        bus.write(0x4016, 1);
+        bus.write(0x4016, 0);
+        let bits: [u8; 24] = core::array::from_fn(|_| bus.read(0x4017) & 1);
+        // Controller 4's A, then port 2's signature.
+        assert_eq!(bits[8], 1);
+        assert_eq!(bits[18], 1);
+        assert_eq!(bits.iter().filter(|&&bit| bit == 1).count(), 2)
+    }
+
+    #[test]
+    fn test_cpu_sees_operand_high_byte_as_open_bus() {
+        let mut cpu = CPU::with_bus(Bus::new());
+        // LDA $5123
+        for (i, byte) in [0xad, 0x23, 0x51, 0x00].into_iter().enumerate() {
+            cpu.mem_write(0x0600 + i as u16, byte);
+        }
+        cpu.pc = 0x0600;
+        cpu.run().unwrap();
+
+        assert_eq!(cpu.reg_a, 0x51)
+    }
+
+    #[test]
+    fn test_cpu_runs_from_ram_through_bus() {
+        let mut cpu = CPU::with_bus(Bus::new());
+        // LDA #$42; STA $0810; BRK
+        for (i, byte) in [0xa9, 0x42, 0x8d, 0x10, 0x08, 0x00].into_iter().enumerate() {
+            cpu.mem_write(0x0600 + i as u16, byte);
+        }
+        cpu.pc = 0x0600;
+        cpu.run().unwrap();
+
+        assert_eq!(cpu.mem_read(0x0010), 0x42)
+    }
+
+    #[test]
+    fn test_cheats_patch_cartridge_reads() {
+        let cart = Cartridge::new(&test_image(0, 1, 0, 0)).unwrap();
+        let mut bus = Bus::with_cartridge(cart).unwrap();
+        // SXIOPO patches $91D9.
+        bus.cheats_mut().add("SXIOPO").unwrap();
+        assert_eq!(bus.read(0x91D9), 0xAD);
+        assert_eq!(bus.read(0x91DA), 0x00);
+        // Only cartridge reads: RAM at the same low bits is untouched.
+        bus.write(0x01D9, 0x12);
+        assert_eq!(bus.read(0x01D9), 0x12);
+
+        bus.cheats_mut().set_enabled(0, false);
+        assert_eq!(bus.read(0x91D9), 0x00)
+    }
+
+    #[test]
+    fn test_ppu_registers_and_oam_dma() {
+        let cart = Cartridge::new(&test_image(0, 1, 0, 0)).unwrap();
+        let mut bus = Bus::with_cartridge(cart).unwrap();
+        // PPUADDR through a mirror, then PPUDATA.
+        bus.write(0x3ffe, 0x21);
+        bus.write(0x3ffe, 0x00);
+        bus.write(0x2007, 0x42);
+        assert_eq!(bus.ppu().oam()[0], 0);
+
+        for i in 0..=0xff {
+            bus.write(0x0200 + i, i as u8);
+        }
+        bus.write(0x4014, 0x02);
+        assert_eq!(bus.ppu().oam()[0x80], 0x80);
+
+        bus.write(0x2006, 0x21);
+        bus.write(0x2006, 0x00);
+        bus.read(0x2007);
+        assert_eq!(bus.read(0x2007), 0x42)
+    }
+
+    #[test]
+    fn test_oam_dma_stalls_the_cpu() {
+        let cart = Cartridge::new(&test_image(0, 1, 0, 0)).unwrap();
+        let mut bus = Bus::with_cartridge(cart).unwrap();
+        assert_eq!(bus.tick(2), 2);
+
+        bus.write(0x4014, 0x02);
+        assert_eq!(bus.tick(4), 4 + 513);
+        assert_eq!(bus.cycles(), 519);
+        // Starting on an odd cycle takes one more to line up.
+        bus.write(0x4014, 0x02);
+        assert_eq!(bus.tick(4), 4 + 514);
+        assert_eq!(bus.tick(2), 2);
+        assert_eq!(bus.ppu().dot(), (1039 * 3 % 341) as u16);
+        assert_eq!(bus.clock().ticks(), 1039 * 12)
+    }
+
+    #[test]
+    fn test_pal_runs_16_dots_every_5_cycles() {
+        let cart = Cartridge::new(&test_image(0, 1, 0, 0)).unwrap();
+        let mut bus = Bus::with_region(cart, Region::Pal).unwrap();
+        assert_eq!(bus.region(), Region::Pal);
+        bus.tick(5);
+        assert_eq!(bus.ppu().dot(), 16);
+        bus.tick(2);
+        assert_eq!(bus.ppu().dot(), 22)
+    }
+
+    #[test]
+    fn test_dmc_fetches_stall_the_cpu() {
+        let mut raw = test_image(0, 1, 0, 0);
+        // With 16KB of PRG ROM, $C000 is the start of the bank.
+        raw[16] = 0xAA;
+        let mut bus = Bus::with_cartridge(Cartridge::new(&raw).unwrap()).unwrap();
+        bus.write(0x4010, 0x8F);
+        bus.write(0x4015, 0x10);
+        assert!(!bus.irq());
+
+        assert_eq!(bus.tick(2), 2 + apu::DMC_STALL_CYCLES);
+        // The one-byte sample is done.
+        assert!(bus.irq());
+        assert_eq!(bus.read(0x4015) & 0x90, 0x80);
+        assert_eq!(bus.tick(2), 2)
+    }
+
+    #[test]
+    fn test_cpu_runs_from_cartridge() {
+        let mut raw = test_image(0, 1, 1, 0);
+        // LDA #$42; STA $10; BRK, with the reset vector pointing at it.
+        raw[16..21].copy_from_slice(&[0xa9, 0x42, 0x85, 0x10, 0x00]);
+        raw[16 + 0x3ffc..16 + 0x3ffe].copy_from_slice(&[0x00, 0x80]);
+        let bus = Bus::with_cartridge(Cartridge::new(&raw).unwrap()).unwrap();
+
+        let mut cpu = CPU::with_bus(bus);
+        assert_eq!(cpu.pc, 0x8000);
+        cpu.run().unwrap();
+
+        assert_eq!(cpu.mem_read(0x0010), 0x42)
+    }
+}
@@ -0,0 +1,530 @@
+//! iNES cartridge images.
+//!
+//! ```text
+//! 0-3    "NES" followed by $1A
+//! 4      PRG ROM size in 16KB units
+//! 5      CHR ROM size in 8KB units, 0 means the board has 8KB of CHR RAM instead
+//! 6      NNNN FTBM: mapper low nibble, four-screen, trainer, battery, mirroring (1 = vertical)
+//! 7      NNNN 10xx: mapper high nibble, `10` marks a NES 2.0 header
+//! 8      NES 2.0 only: mapper bits 8-11 in the low nibble, submapper in the high nibble
+//! 9      iNES only: bit 0 set for PAL
+//! 12     NES 2.0 only: timing in bits 0-1, NTSC, PAL, multi-region or Dendy
+//! ```
+//!
+//! The header is followed by an optional 512-byte trainer, then PRG ROM, then CHR ROM.
+
+use alloc::format;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::{NesError, Result};
+use crate::region::Region;
+
+const NES_TAG: [u8; 4] = [b'N', b'E', b'S', 0x1A];
+const HEADER_SIZE: usize = 16;
+const TRAINER_SIZE: usize = 0x0200;
+/// Trainers are loaded at $7000-$71FF, this far into PRG RAM.
+const TRAINER_OFFSET: usize = 0x1000;
+
+pub const PRG_ROM_PAGE_SIZE: usize = 0x4000;
+pub const CHR_ROM_PAGE_SIZE: usize = 0x2000;
+/// Work RAM at $6000-$7FFF. Boards without it simply never see it accessed.
+pub const PRG_RAM_SIZE: usize = 0x2000;
+
+/// How the PPU's four logical nametables map onto its 2KB of VRAM.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Mirroring {
+    /// $2000 = $2400 and $2800 = $2C00. Used by vertically scrolling games.
+    #[default]
+    Horizontal,
+    /// $2000 = $2800 and $2400 = $2C00. Used by horizontally scrolling games.
+    Vertical,
+    /// The cartridge supplies an extra 2KB so every nametable is distinct.
+    FourScreen,
+    /// Every nametable shows the first 1KB of VRAM. Only selectable by the mapper.
+    SingleScreenLower,
+    /// Every nametable shows the second 1KB of VRAM. Only selectable by the mapper.
+    SingleScreenUpper,
+}
+
+impl Mirroring {
+    /// Maps a nametable address ($2000-$3EFF) to an offset into nametable memory. Everything but
+    /// [`Mirroring::FourScreen`] stays within the console's 2KB.
+    pub fn nametable_offset(self, addr: u16) -> usize {
+        let addr = usize::from(addr & 0x0FFF);
+        let table = addr / 0x400;
+        let page = match self {
+            Mirroring::Horizontal => table / 2,
+            Mirroring::Vertical => table % 2,
+            Mirroring::FourScreen => table,
+            Mirroring::SingleScreenLower => 0,
+            Mirroring::SingleScreenUpper => 1,
+        };
+        page * 0x400 + addr % 0x400
+    }
+}
+
+/// Which header an image has.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RomFormat {
+    #[default]
+    INes,
+    Nes2,
+}
+
+/// What the header says about an image, and hashes of its ROM for looking it up in databases.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RomInfo {
+    pub format: RomFormat,
+    pub mapper: u16,
+    /// The board variant within the mapper. Only NES 2.0 headers have one; it's 0 otherwise.
+    pub submapper: u8,
+    /// PRG ROM size in bytes.
+    pub prg_rom_size: usize,
+    /// CHR ROM size in bytes, 0 for boards with CHR RAM instead.
+    pub chr_rom_size: usize,
+    pub mirroring: Mirroring,
+    pub battery: bool,
+    pub trainer: bool,
+    pub region: Region,
+    /// CRC-32 of PRG ROM followed by CHR ROM, without the header or trainer. The hash ROM
+    /// databases key their entries on.
+    pub crc32: u32,
+    /// CRC-32 of PRG ROM alone.
+    pub prg_crc32: u32,
+}
+
+impl fmt::Display for RomInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let format = match self.format {
+            RomFormat::INes => "iNES",
+            RomFormat::Nes2 => "NES 2.0",
+        };
+        writeln!(f, "format:     {format}")?;
+        writeln!(f, "mapper:     {}.{}", self.mapper, self.submapper)?;
+        writeln!(f, "PRG ROM:    {}KB", self.prg_rom_size / 1024)?;
+        if self.chr_rom_size == 0 {
+            writeln!(f, "CHR ROM:    none, 8KB CHR RAM")?;
+        } else {
+            writeln!(f, "CHR ROM:    {}KB", self.chr_rom_size / 1024)?;
+        }
+        writeln!(f, "mirroring:  {:?}", self.mirroring)?;
+        writeln!(f, "battery:    {}", if self.battery { "yes" } else { "no" })?;
+        writeln!(f, "trainer:    {}", if self.trainer { "yes" } else { "no" })?;
+        writeln!(f, "region:     {:?}", self.region)?;
+        writeln!(f, "CRC-32:     {:08X}", self.crc32)?;
+        write!(f, "PRG CRC-32: {:08X}", self.prg_crc32)
+    }
+}
+
+/// CRC-32 (IEEE) over `parts` one after the other.
+fn crc32(parts: &[&[u8]]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in parts.iter().flat_map(|part| part.iter()) {
+        crc ^= u32::from(byte);
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                crc >> 1 ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+/// A parsed cartridge: its ROM contents and the board it expects. The default is an empty one,
+/// with no ROM at all.
+#[derive(Debug, Clone, Default)]
+pub struct Cartridge {
+    pub prg_rom: Vec<u8>,
+    /// CHR ROM, or the board's CHR RAM when the header reports no CHR ROM.
+    pub chr_rom: Vec<u8>,
+    /// Whether `chr_rom` is actually writable CHR RAM.
+    pub chr_ram: bool,
+    pub prg_ram: Vec<u8>,
+    pub mapper: u16,
+    pub mirroring: Mirroring,
+    /// The PRG RAM is battery-backed and should outlive the session.
+    pub battery: bool,
+    pub region: Region,
+    /// The header and hashes, as loaded.
+    info: RomInfo,
+}
+
+impl Cartridge {
+    /// Parses an iNES (or NES 2.0) image.
+    ///
+    /// # Errors
+    /// Returns [`NesError::RomParse`] when the header is missing or the image is shorter than it
+    /// claims.
+    pub fn new(raw: &[u8]) -> Result<Cartridge> {
+        if raw.len() < HEADER_SIZE || raw[0..4] != NES_TAG {
+            return Err(NesError::RomParse("file is not in iNES format".into()));
+        }
+
+        let flags6 = raw[6];
+        let flags7 = raw[7];
+        let nes2 = flags7 & 0b1100 == 0b1000;
+
+        let mut mapper = u16::from(flags7 & 0xF0) | u16::from(flags6 >> 4);
+        let mut submapper = 0;
+        if nes2 {
+            mapper |= u16::from(raw[8] & 0x0F) << 8;
+            submapper = raw[8] >> 4;
+        }
+
+        let region = match (nes2, raw[9] & 0b1, raw[12] & 0b11) {
+            (true, _, 1) => Region::Pal,
+            (true, _, 3) => Region::Dendy,
+            (false, 1, _) => Region::Pal,
+            _ => Region::Ntsc,
+        };
+
+        let mirroring = match (flags6 & 0b1000 != 0, flags6 & 0b1 != 0) {
+            (true, _) => Mirroring::FourScreen,
+            (false, true) => Mirroring::Vertical,
+            (false, false) => Mirroring::Horizontal,
+        };
+
+        let trainer = flags6 & 0b100 != 0;
+        let prg_rom_size = usize::from(raw[4]) * PRG_ROM_PAGE_SIZE;
+        let chr_rom_size = usize::from(raw[5]) * CHR_ROM_PAGE_SIZE;
+        let prg_rom_start = HEADER_SIZE + if trainer { TRAINER_SIZE } else { 0 };
+        let chr_rom_start = prg_rom_start + prg_rom_size;
+
+        if prg_rom_size == 0 {
+            return Err(NesError::RomParse("image has no PRG ROM".into()));
+        }
+        if raw.len() < chr_rom_start + chr_rom_size {
+            return Err(NesError::RomParse(format!(
+                "image is {} bytes but the header needs {}",
+                raw.len(),
+                chr_rom_start + chr_rom_size
+            )));
+        }
+
+        let chr_ram = chr_rom_size == 0;
+        let chr_rom = if chr_ram {
+            vec![0; CHR_ROM_PAGE_SIZE]
+        } else {
+            raw[chr_rom_start..chr_rom_start + chr_rom_size].to_vec()
+        };
+
+        let mut prg_ram = vec![0; PRG_RAM_SIZE];
+        if trainer {
+            prg_ram[TRAINER_OFFSET..TRAINER_OFFSET + TRAINER_SIZE]
+                .copy_from_slice(&raw[HEADER_SIZE..prg_rom_start]);
+        }
+
+        info!(
+            "loaded {} image: mapper {mapper}, {}KB PRG ROM, {}KB CHR {}, {mirroring:?} mirroring, \
+             {region:?}{}{}",
+            if nes2 { "NES 2.0" } else { "iNES" },
+            prg_rom_size / 1024,
+            chr_rom.len() / 1024,
+            if chr_ram { "RAM" } else { "ROM" },
+            if flags6 & 0b10 != 0 { ", battery" } else { "" },
+            if trainer { ", trainer" } else { "" },
+        );
+        let prg_rom = raw[prg_rom_start..chr_rom_start].to_vec();
+        let info = RomInfo {
+            format: if nes2 {
+                RomFormat::Nes2
+            } else {
+                RomFormat::INes
+            },
+            mapper,
+            submapper,
+            prg_rom_size,
+            chr_rom_size,
+            mirroring,
+            battery: flags6 & 0b10 != 0,
+            trainer,
+            region,
+            crc32: crc32(&[&raw[prg_rom_start..chr_rom_start + chr_rom_size]]),
+            prg_crc32: crc32(&[&prg_rom]),
+        };
+        Ok(Cartridge {
+            prg_rom,
+            chr_rom,
+            chr_ram,
+            prg_ram,
+            mapper,
+            mirroring,
+            battery: info.battery,
+            region,
+            info,
+        })
+    }
+
+    /// What the image's header said and hashes of its ROM. Unlike the public fields, this doesn't
+    /// change if they are edited after loading.
+    pub fn info(&self) -> &RomInfo {
+        &self.info
+    }
+
+    /// The battery-backed PRG RAM, or `None` if the cartridge has no battery and nothing needs
+    /// saving. Frontends that manage their own storage persist this.
+    pub fn sram(&self) -> Option<&[u8]> {
+        self.battery.then_some(self.prg_ram.as_slice())
+    }
+
+    /// Restores battery-backed PRG RAM saved from [`Cartridge::sram`].
+    ///
+    /// # Errors
+    /// Returns [`NesError::InvalidState`] if the cartridge has no battery or `data` is the wrong
+    /// size.
+    pub fn load_sram(&mut self, data: &[u8]) -> Result<()> {
+        if !self.battery {
+            return Err(NesError::InvalidState(
+                "cartridge has no battery-backed RAM".into(),
+            ));
+        }
+        if data.len() != self.prg_ram.len() {
+            return Err(NesError::InvalidState(format!(
+                "save is {} bytes but the cartridge has {} bytes of RAM",
+                data.len(),
+                self.prg_ram.len()
+            )));
+        }
+        self.prg_ram.copy_from_slice(data);
+        Ok(())
+    }
+}
+
+#[cfg(feature = "std")]
+impl Cartridge {
+    /// Reads and parses an iNES file.
+    ///
+    /// # Errors
+    /// Returns [`NesError::Io`] if the file can't be read, or [`NesError::RomParse`] if it isn't a
+    /// valid image.
+    pub fn from_file(path: impl AsRef<std::path::Path>) -> Result<Cartridge> {
+        Cartridge::new(&std::fs::read(path)?)
+    }
+
+    /// Where the battery save for the ROM at `rom_path` lives: next to it, with a `.sav`
+    /// extension.
+    pub fn sav_path(rom_path: impl AsRef<std::path::Path>) -> std::path::PathBuf {
+        rom_path.as_ref().with_extension("sav")
+    }
+
+    /// Writes the battery-backed RAM to `path`. Does nothing for cartridges without a battery.
+    ///
+    /// # Errors
+    /// Returns [`NesError::Io`] if the file can't be written.
+    pub fn save_sram_file(&self, path: impl AsRef<std::path::Path>) -> Result<()> {
+        if let Some(sram) = self.sram() {
+            std::fs::write(path, sram)?;
+        }
+        Ok(())
+    }
+
+    /// Restores battery-backed RAM from `path`. A missing file is not an error, it just means the
+    /// game hasn't been saved yet.
+    ///
+    /// # Errors
+    /// Returns [`NesError::Io`] if the file exists but can't be read, or
+    /// [`NesError::InvalidState`] if it doesn't fit the cartridge.
+    pub fn load_sram_file(&mut self, path: impl AsRef<std::path::Path>) -> Result<()> {
+        if !self.battery {
+            return Ok(());
+        }
+        match std::fs::read(path) {
+            Ok(data) => self.load_sram(&data),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(err) => Err(err.into()),
+        }
+    }
+}
+
+/// Builds iNES images for tests. Each PRG bank is filled with its bank number and each CHR bank
+/// with its bank number plus $80, so bank switching is easy to observe.
+#[cfg(test)]
+pub(crate) fn test_image(mapper: u8, prg_banks: u8, chr_banks: u8, flags6: u8) -> Vec<u8> {
+    let mut raw = vec![
+        b'N',
+        b'E',
+        b'S',
+        0x1A,
+        prg_banks,
+        chr_banks,
+        (mapper << 4) | flags6,
+        mapper & 0xF0,
+    ];
+    raw.resize(HEADER_SIZE, 0);
+    for bank in 0..prg_banks {
+        raw.resize(raw.len() + PRG_ROM_PAGE_SIZE, bank);
+    }
+    for bank in 0..chr_banks {
+        raw.resize(raw.len() + CHR_ROM_PAGE_SIZE, 0x80 | bank);
+    }
+    raw
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_header() {
+        let cart = Cartridge::new(&test_image(0x42, 2, 1, 0b0011)).unwrap();
+
+        assert_eq!(cart.mapper, 0x42);
+        assert_eq!(cart.mirroring, Mirroring::Vertical);
+        assert!(cart.battery);
+        assert_eq!(cart.prg_rom.len(), 2 * PRG_ROM_PAGE_SIZE);
+        assert_eq!(cart.prg_rom[PRG_ROM_PAGE_SIZE], 1);
+        assert_eq!(cart.chr_rom.len(), CHR_ROM_PAGE_SIZE);
+        assert_eq!(cart.chr_rom[0], 0x80);
+        assert!(!cart.chr_ram)
+    }
+
+    #[test]
+    fn test_info() {
+        let mut raw = test_image(4, 2, 1, 0b0011);
+        raw[7] |= 0b1000;
+        raw[8] = 0x11;
+        let cart = Cartridge::new(&raw).unwrap();
+        let info = cart.info();
+
+        assert_eq!(info.format, RomFormat::Nes2);
+        assert_eq!((info.mapper, info.submapper), (0x104, 1));
+        assert_eq!((info.prg_rom_size, info.chr_rom_size), (0x8000, 0x2000));
+        assert_eq!(info.mirroring, Mirroring::Vertical);
+        assert!(info.battery && !info.trainer);
+        assert_eq!(info.crc32, crc32(&[&raw[HEADER_SIZE..]]));
+        assert_eq!(info.prg_crc32, crc32(&[&cart.prg_rom]));
+        assert!(format!("{info}").contains("mapper:     260.1"))
+    }
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(&[b"123456789"]), 0xCBF4_3926);
+        assert_eq!(crc32(&[b"1234", b"56789"]), 0xCBF4_3926)
+    }
+
+    #[test]
+    fn test_no_chr_rom_means_chr_ram() {
+        let cart = Cartridge::new(&test_image(0, 1, 0, 0b1000)).unwrap();
+
+        assert!(cart.chr_ram);
+        assert_eq!(cart.chr_rom.len(), CHR_ROM_PAGE_SIZE);
+        assert_eq!(cart.mirroring, Mirroring::FourScreen)
+    }
+
+    #[test]
+    fn test_trainer_is_loaded_at_7000() {
+        let mut raw = test_image(0, 1, 1, 0b100);
+        let trainer: Vec<u8> = (0..TRAINER_SIZE).map(|i| i as u8).collect();
+        raw.splice(HEADER_SIZE..HEADER_SIZE, trainer.iter().copied());
+        let cart = Cartridge::new(&raw).unwrap();
+
+        assert_eq!(
+            cart.prg_ram[TRAINER_OFFSET..TRAINER_OFFSET + TRAINER_SIZE],
+            trainer[..]
+        );
+        assert_eq!(cart.prg_rom.len(), PRG_ROM_PAGE_SIZE);
+        assert!(cart.prg_rom.iter().all(|&byte| byte == 0));
+        assert_eq!(cart.chr_rom[0], 0x80)
+    }
+
+    #[test]
+    fn test_region() {
+        let mut raw = test_image(0, 1, 1, 0);
+        assert_eq!(Cartridge::new(&raw).unwrap().region, Region::Ntsc);
+
+        raw[9] = 1;
+        assert_eq!(Cartridge::new(&raw).unwrap().region, Region::Pal);
+
+        raw[7] |= 0b1000;
+        raw[9] = 0;
+        raw[12] = 3;
+        assert_eq!(Cartridge::new(&raw).unwrap().region, Region::Dendy);
+
+        // Multi-region carts run as NTSC.
+        raw[12] = 2;
+        assert_eq!(Cartridge::new(&raw).unwrap().region, Region::Ntsc)
+    }
+
+    #[test]
+    fn test_nes2_mapper_number() {
+        let mut raw = test_image(0x15, 1, 1, 0);
+        raw[7] |= 0b1000;
+        raw[8] = 0x01;
+
+        assert_eq!(Cartridge::new(&raw).unwrap().mapper, 0x115)
+    }
+
+    #[test]
+    fn test_nametable_mirroring() {
+        let offsets = |mirroring: Mirroring| {
+            [0x2000, 0x2400, 0x2800, 0x2C00, 0x3005].map(|addr| mirroring.nametable_offset(addr))
+        };
+
+        assert_eq!(offsets(Mirroring::Horizontal), [0, 0, 0x400, 0x400, 5]);
+        assert_eq!(offsets(Mirroring::Vertical), [0, 0x400, 0, 0x400, 5]);
+        assert_eq!(offsets(Mirroring::FourScreen), [0, 0x400, 0x800, 0xC00, 5]);
+        assert_eq!(offsets(Mirroring::SingleScreenLower), [0, 0, 0, 0, 5]);
+        assert_eq!(
+            offsets(Mirroring::SingleScreenUpper),
+            [0x400, 0x400, 0x400, 0x400, 0x405]
+        )
+    }
+
+    #[test]
+    fn test_sram() {
+        let mut plain = Cartridge::new(&test_image(0, 1, 1, 0)).unwrap();
+        assert!(plain.sram().is_none());
+        assert!(plain.load_sram(&[0; PRG_RAM_SIZE]).is_err());
+
+        let mut battery = Cartridge::new(&test_image(0, 1, 1, 0b10)).unwrap();
+        battery.prg_ram[0] = 0x42;
+        let saved = battery.sram().unwrap().to_vec();
+        battery.prg_ram[0] = 0;
+
+        battery.load_sram(&saved).unwrap();
+        assert_eq!(battery.prg_ram[0], 0x42);
+        assert!(matches!(
+            battery.load_sram(&[0; 16]),
+            Err(NesError::InvalidState(_))
+        ))
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_sram_file_round_trip() {
+        let rom = std::env::temp_dir().join(std::format!("nes-sram-{}.nes", std::process::id()));
+        let sav = Cartridge::sav_path(&rom);
+        assert_eq!(sav.extension().unwrap(), "sav");
+
+        let mut cart = Cartridge::new(&test_image(0, 1, 1, 0b10)).unwrap();
+        cart.load_sram_file(&sav).unwrap();
+        cart.prg_ram[0x1FFF] = 0x99;
+        cart.save_sram_file(&sav).unwrap();
+
+        let mut restored = Cartridge::new(&test_image(0, 1, 1, 0b10)).unwrap();
+        restored.load_sram_file(&sav).unwrap();
+        std::fs::remove_file(&sav).unwrap();
+        assert_eq!(restored.prg_ram[0x1FFF], 0x99)
+    }
+
+    #[test]
+    fn test_rejects_bad_images() {
+        assert!(matches!(
+            Cartridge::new(b"not a rom at all"),
+            Err(NesError::RomParse(_))
+        ));
+
+        let mut truncated = test_image(0, 2, 1, 0);
+        truncated.truncate(HEADER_SIZE + PRG_ROM_PAGE_SIZE);
+        assert!(matches!(
+            Cartridge::new(&truncated),
+            Err(NesError::RomParse(_))
+        ))
+    }
+}
@@ -0,0 +1,326 @@
+//! Game Genie and Pro Action Rocky cheat codes.
+//!
+//! Both devices sit between the console and the cartridge and patch what the CPU reads from PRG
+//! ROM: when it reads the code's address, it gets the code's value instead. Codes with a compare
+//! value only patch the read if the ROM holds that value there, so they can target one bank of a
+//! bank-switched game without breaking the others.
+//!
+//! A Game Genie code is 6 or 8 letters, each standing for a nibble:
+//!
+//! ```text
+//! A P Z L G I T Y E O X U K S V N
+//! 0 1 2 3 4 5 6 7 8 9 A B C D E F
+//! ```
+//!
+//! and the nibbles' bits are shuffled into a 15-bit address (the top bit is always set), a value
+//! and, for 8-letter codes, a compare value. A Pro Action Rocky code is 8 hex digits, a 32-bit word
+//! encrypted with a running key; decrypted, its bits are shuffled into an address, a value and a
+//! compare value in the same way.
+
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
+use crate::error::{NesError, Result};
+
+const GAME_GENIE_LETTERS: &[u8; 16] = b"APZLGITYEOXUKSVN";
+
+/// Where each bit of a decrypted Pro Action Rocky code goes, from bit 30 of the encrypted code
+/// down to bit 0.
+const PAR_SHIFTS: [u8; 31] = [
+    3, 13, 14, 1, 6, 9, 5, 0, 12, 7, 2, 8, 10, 11, 4, 19, 21, 23, 22, 20, 17, 16, 18, 29, 31, 24,
+    26, 25, 30, 27, 28,
+];
+const PAR_KEY: u32 = 0xFCBD_D274;
+const PAR_FEEDBACK: u32 = 0xB830_9722;
+
+/// A decoded code: patch reads of `address` to return `value`, if the ROM byte there is `compare`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cheat {
+    pub address: u16,
+    pub value: u8,
+    pub compare: Option<u8>,
+}
+
+impl Cheat {
+    /// Decodes a 6- or 8-letter Game Genie code. Case doesn't matter.
+    ///
+    /// # Errors
+    /// Returns [`NesError::InvalidCheat`] for codes of any other length or with letters the Game
+    /// Genie doesn't use.
+    pub fn game_genie(code: &str) -> Result<Cheat> {
+        let bad = || NesError::InvalidCheat(format!("{code:?} is not a Game Genie code"));
+        if code.len() != 6 && code.len() != 8 {
+            return Err(bad());
+        }
+        let n = code
+            .bytes()
+            .map(|letter| {
+                let letter = letter.to_ascii_uppercase();
+                GAME_GENIE_LETTERS
+                    .iter()
+                    .position(|&l| l == letter)
+                    .map(|nibble| nibble as u16)
+            })
+            .collect::<Option<Vec<u16>>>()
+            .ok_or_else(bad)?;
+
+        let address = 0x8000
+            | (n[3] & 7) << 12
+            | (n[5] & 7) << 8
+            | (n[4] & 8) << 8
+            | (n[2] & 7) << 4
+            | (n[1] & 8) << 4
+            | (n[4] & 7)
+            | (n[3] & 8);
+        // The last letter's high bit finishes the value; in 8-letter codes the compare value
+        // comes between them.
+        let last = n[n.len() - 1];
+        let value = (n[1] & 7) << 4 | (n[0] & 8) << 4 | (n[0] & 7) | (last & 8);
+        let compare =
+            (n.len() == 8).then(|| (n[7] & 7) << 4 | (n[6] & 8) << 4 | (n[6] & 7) | (n[5] & 8));
+        Ok(Cheat {
+            address,
+            value: value as u8,
+            compare: compare.map(|compare| compare as u8),
+        })
+    }
+
+    /// Decodes an 8-digit Pro Action Rocky code.
+    ///
+    /// # Errors
+    /// Returns [`NesError::InvalidCheat`] if `code` isn't 8 hex digits.
+    pub fn pro_action_rocky(code: &str) -> Result<Cheat> {
+        let bad = || NesError::InvalidCheat(format!("{code:?} is not a Pro Action Rocky code"));
+        if code.len() != 8 {
+            return Err(bad());
+        }
+        let mut input = u32::from_str_radix(code, 16).map_err(|_| bad())?;
+        let mut key = PAR_KEY;
+        let mut output = 0u32;
+        for &shift in PAR_SHIFTS.iter().rev() {
+            if (input ^ key) & 0x8000_0000 != 0 {
+                output |= 1 << shift;
+                input ^= PAR_FEEDBACK;
+            }
+            input <<= 1;
+            key <<= 1;
+        }
+        Ok(Cheat {
+            address: 0x8000 | (output & 0x7FFF) as u16,
+            value: (output >> 24) as u8,
+            compare: Some((output >> 16) as u8),
+        })
+    }
+
+    /// What a read of `addr` returns with the cheat on, the ROM having `data` there.
+    fn patch(&self, addr: u16, data: u8) -> Option<u8> {
+        let hit = addr == self.address && self.compare.is_none_or(|compare| compare == data);
+        hit.then_some(self.value)
+    }
+}
+
+/// A code and whether it's switched on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CheatEntry {
+    /// The code as it was entered.
+    pub code: String,
+    pub cheat: Cheat,
+    pub enabled: bool,
+}
+
+/// The codes applied to cartridge reads, in the order they were added. When several patch the
+/// same address, the first enabled one wins.
+#[derive(Debug, Clone, Default)]
+pub struct Cheats {
+    entries: Vec<CheatEntry>,
+}
+
+impl Cheats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Decodes `code` and adds it, switched on. 6-letter codes and 8-letter ones using only Game
+    /// Genie letters are taken as Game Genie codes, other 8-character ones as Pro Action Rocky
+    /// codes. Returns the code's index.
+    ///
+    /// # Errors
+    /// Returns [`NesError::InvalidCheat`] if `code` is neither.
+    pub fn add(&mut self, code: &str) -> Result<usize> {
+        let code = code.trim();
+        let cheat = match Cheat::game_genie(code) {
+            Ok(cheat) => cheat,
+            Err(_) if code.len() == 8 => Cheat::pro_action_rocky(code).map_err(|_| {
+                NesError::InvalidCheat(format!("{code:?} is not a Game Genie or PAR code"))
+            })?,
+            Err(error) => return Err(error),
+        };
+        Ok(self.add_cheat(code, cheat))
+    }
+
+    /// Adds an already decoded cheat, switched on, under the name `code`. Returns its index.
+    pub fn add_cheat(&mut self, code: &str, cheat: Cheat) -> usize {
+        self.entries.push(CheatEntry {
+            code: code.to_string(),
+            cheat,
+            enabled: true,
+        });
+        self.entries.len() - 1
+    }
+
+    /// Removes the code at `index`, if there is one.
+    pub fn remove(&mut self, index: usize) -> Option<CheatEntry> {
+        (index < self.entries.len()).then(|| self.entries.remove(index))
+    }
+
+    /// Switches the code at `index` on or off. Returns `false` if there's no such code.
+    pub fn set_enabled(&mut self, index: usize, enabled: bool) -> bool {
+        match self.entries.get_mut(index) {
+            Some(entry) => {
+                entry.enabled = enabled;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn entries(&self) -> &[CheatEntry] {
+        &self.entries
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    /// What a CPU read of cartridge address `addr` returns, the cartridge having put `data` on the
+    /// bus.
+    pub(crate) fn apply(&self, addr: u16, data: u8) -> u8 {
+        self.entries
+            .iter()
+            .filter(|entry| entry.enabled)
+            .find_map(|entry| entry.cheat.patch(addr, data))
+            .unwrap_or(data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_game_genie() {
+        // Super Mario Bros. infinite lives.
+        assert_eq!(
+            Cheat::game_genie("SXIOPO").unwrap(),
+            Cheat {
+                address: 0x91D9,
+                value: 0xAD,
+                compare: None
+            }
+        );
+        assert_eq!(
+            Cheat::game_genie("sxiopo").unwrap(),
+            Cheat::game_genie("SXIOPO").unwrap()
+        );
+        // Same address and value, but only while the ROM holds $CE there.
+        assert_eq!(
+            Cheat::game_genie("SXIOPOVK").unwrap(),
+            Cheat {
+                address: 0x91D9,
+                value: 0xAD,
+                compare: Some(0xCE)
+            }
+        );
+        assert!(matches!(
+            Cheat::game_genie("SXIOP"),
+            Err(NesError::InvalidCheat(_))
+        ));
+        assert!(Cheat::game_genie("SXIOPB").is_err())
+    }
+
+    /// Encrypts the way the Pro Action Rocky's code book does, to check decoding against.
+    fn par_encode(cheat: Cheat) -> String {
+        let input = u32::from(cheat.address & 0x7FFF)
+            | u32::from(cheat.compare.unwrap()) << 16
+            | u32::from(cheat.value) << 24;
+        let mut key = PAR_KEY;
+        let mut output = 0;
+        for (i, &shift) in PAR_SHIFTS.iter().enumerate().rev() {
+            let bit = input >> shift & 1;
+            output |= ((key >> 31) ^ bit) << (i + 1);
+            if bit != 0 {
+                key ^= PAR_FEEDBACK;
+            }
+            key <<= 1;
+        }
+        format!("{output:08X}")
+    }
+
+    #[test]
+    fn test_pro_action_rocky() {
+        for cheat in [
+            Cheat {
+                address: 0x8000,
+                value: 0,
+                compare: Some(0),
+            },
+            Cheat {
+                address: 0x91D9,
+                value: 0xAD,
+                compare: Some(0x5B),
+            },
+            Cheat {
+                address: 0xFFFF,
+                value: 0xFF,
+                compare: Some(0xFF),
+            },
+        ] {
+            let code = par_encode(cheat);
+            assert_eq!(Cheat::pro_action_rocky(&code).unwrap(), cheat, "{code}");
+        }
+        assert!(Cheat::pro_action_rocky("1234567G").is_err());
+        assert!(Cheat::pro_action_rocky("1234567").is_err())
+    }
+
+    #[test]
+    fn test_cheats() {
+        let mut cheats = Cheats::new();
+        assert_eq!(cheats.add(" SXIOPO ").unwrap(), 0);
+        assert_eq!(cheats.entries()[0].code, "SXIOPO");
+        assert_eq!(cheats.apply(0x91D9, 0x12), 0xAD);
+        assert_eq!(cheats.apply(0x91DA, 0x12), 0x12);
+
+        cheats.set_enabled(0, false);
+        assert_eq!(cheats.apply(0x91D9, 0x12), 0x12);
+        assert!(!cheats.set_enabled(5, true));
+
+        // Only patches when the ROM holds the compare value.
+        cheats.add_cheat(
+            "banked",
+            Cheat {
+                address: 0xC000,
+                value: 0xEA,
+                compare: Some(0x20),
+            },
+        );
+        assert_eq!(cheats.apply(0xC000, 0x20), 0xEA);
+        assert_eq!(cheats.apply(0xC000, 0x21), 0x21);
+
+        assert!(cheats.add("ZZZZZZZZZ").is_err());
+        assert!(cheats.add("QQQQQQQQ").is_err());
+        assert!(cheats.add("0000ABCD").is_ok());
+        assert_eq!(cheats.len(), 3);
+        assert!(cheats.remove(0).is_some());
+        assert!(cheats.remove(9).is_none());
+        assert_eq!(cheats.len(), 2)
+    }
+}
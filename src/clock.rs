@@ -0,0 +1,103 @@
+//! The master clock everything on the console is divided down from.
+//!
+//! The CPU and the PPU run off the same crystal through different dividers. On NTSC the ratio
+//! happens to be a whole 3 dots per CPU cycle, but on PAL it is 3.2, so the PPU can't just be run a
+//! fixed number of dots per cycle. Keeping count in master clock ticks instead gives every
+//! component its exact share of time, and keeps their relative phase right from cycle to cycle.
+//!
+//! The [`Bus`](crate::bus::Bus) clocks the rest of the console once an instruction has finished,
+//! though, so the phase is only exact between instructions. See there for what that means for
+//! register timing.
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::{NesError, Result};
+use crate::region::Timing;
+use crate::state;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MasterClock {
+    #[serde(with = "state::timing")]
+    timing: &'static Timing,
+    /// Master clock ticks since power-on.
+    ticks: u64,
+    /// Master clock ticks the PPU has been run for. It trails `ticks` by less than a dot.
+    ppu_ticks: u64,
+}
+
+impl MasterClock {
+    pub fn new(timing: &'static Timing) -> Self {
+        Self {
+            timing,
+            ticks: 0,
+            ppu_ticks: 0,
+        }
+    }
+
+    pub fn timing(&self) -> &'static Timing {
+        self.timing
+    }
+
+    /// Master clock ticks since power-on.
+    pub fn ticks(&self) -> u64 {
+        self.ticks
+    }
+
+    /// Runs the clock for one CPU cycle. Returns how many PPU dots were completed in it.
+    pub fn cpu_cycle(&mut self) -> u32 {
+        self.ticks += u64::from(self.timing.cpu_divider);
+        let divider = u64::from(self.timing.ppu_divider);
+        let mut dots = 0;
+        while self.ppu_ticks + divider <= self.ticks {
+            self.ppu_ticks += divider;
+            dots += 1;
+        }
+        dots
+    }
+
+    /// Checks a clock read from a save state. The PPU has to trail the CPU by less than a dot, or
+    /// the next cycle would try to catch up on any number of them.
+    pub(crate) fn validate(&self) -> Result<()> {
+        let divider = u64::from(self.timing.ppu_divider);
+        if self.ppu_ticks > self.ticks || self.ticks - self.ppu_ticks >= divider {
+            return Err(NesError::InvalidState(
+                "save state's PPU and CPU clocks are out of step".into(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::region::Region;
+
+    #[test]
+    fn test_ntsc_runs_three_dots_per_cycle() {
+        let mut clock = MasterClock::new(Region::Ntsc.timing());
+        for _ in 0..10 {
+            assert_eq!(clock.cpu_cycle(), 3);
+        }
+        assert_eq!(clock.ticks(), 120)
+    }
+
+    #[test]
+    fn test_pal_spreads_the_fifth_dot() {
+        let mut clock = MasterClock::new(Region::Pal.timing());
+        let dots: [u32; 10] = core::array::from_fn(|_| clock.cpu_cycle());
+        assert_eq!(dots, [3, 3, 3, 3, 4, 3, 3, 3, 3, 4]);
+        assert_eq!(clock.ticks(), 160)
+    }
+
+    #[test]
+    fn test_validate() {
+        let mut clock = MasterClock::new(Region::Pal.timing());
+        clock.cpu_cycle();
+        assert!(clock.validate().is_ok());
+        clock.ppu_ticks = 0;
+        assert!(clock.validate().is_err());
+        clock.ppu_ticks = clock.ticks + 1;
+        assert!(clock.validate().is_err())
+    }
+}
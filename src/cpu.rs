@@ -1,47 +1,156 @@
+use alloc::vec::Vec;
+
+use serde::{Deserialize, Serialize};
+
+use crate::bus::RAM_SIZE;
+use crate::error::{NesError, Result};
+use crate::mem::{FlatMemory, Mem};
+use crate::opcodes;
+
+/// Where a program is placed in memory by [`CPU::interpret`]. $8000 is the start of cartridge PRG
+/// ROM on the NES.
+const PROGRAM_START: u16 = 0x8000;
+
+/// Interrupt vectors. Each holds the address the CPU jumps to when the interrupt fires.
+pub const NMI_VECTOR: u16 = 0xFFFA;
+pub const RESET_VECTOR: u16 = 0xFFFC;
+/// Shared by IRQ and `BRK`.
+pub const IRQ_VECTOR: u16 = 0xFFFE;
+
+/// The stack lives in page one, $0100-$01FF, and grows downwards.
+const STACK: u16 = 0x0100;
+const STACK_RESET: u8 = 0xFD;
+/// I and the unused bit 5 set.
+const STATUS_RESET: u8 = 0x24;
+
+/// Reset and interrupt sequences all take seven cycles.
+const INTERRUPT_CYCLES: u8 = 7;
+
+/// How work RAM is filled at power-on. Real consoles come up with semi-random contents, and some
+/// games (and bugs) depend on them.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RamInit {
+    /// Every byte is $00.
+    #[default]
+    Zeros,
+    /// Every byte is $FF.
+    Ones,
+    /// Runs of four $00 bytes followed by four $FF bytes, a pattern commonly seen on hardware.
+    Alternating,
+    /// Pseudo-random bytes from the given seed, so runs stay reproducible.
+    Random(u32),
+}
+
+impl RamInit {
+    pub fn fill(self, ram: &mut [u8]) {
+        match self {
+            RamInit::Zeros => ram.fill(0x00),
+            RamInit::Ones => ram.fill(0xFF),
+            RamInit::Alternating => {
+                for (i, byte) in ram.iter_mut().enumerate() {
+                    *byte = if i & 0b100 == 0 { 0x00 } else { 0xFF };
+                }
+            }
+            RamInit::Random(seed) => {
+                // xorshift32 never leaves zero, so nudge a zero seed.
+                let mut state = seed.max(1);
+                for byte in ram {
+                    state ^= state << 13;
+                    state ^= state >> 17;
+                    state ^= state << 5;
+                    *byte = state as u8;
+                }
+            }
+        }
+    }
+}
+
+/// How an instruction locates its operand.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AddressingMode {
+    /// `#$nn`: the operand is the byte following the opcode.
+    Immediate,
+    /// `$nn`: an address in the zero page.
+    ZeroPage,
+    /// `$nn,X`: zero page address plus X, wrapping within the zero page.
+    ZeroPageX,
+    /// `$nn,Y`: zero page address plus Y, wrapping within the zero page.
+    ZeroPageY,
+    /// `$nnnn`: a full 16-bit address.
+    Absolute,
+    /// `$nnnn,X`
+    AbsoluteX,
+    /// `$nnnn,Y`
+    AbsoluteY,
+    /// `($nnnn)`: the operand is a pointer to the effective address. Only used by `JMP`.
+    Indirect,
+    /// `($nn,X)`: indexed indirect. The pointer is read from the zero page at `$nn + X`.
+    IndirectX,
+    /// `($nn),Y`: indirect indexed. The pointer is read from the zero page at `$nn`, then Y is
+    /// added to it.
+    IndirectY,
+    /// A signed 8-bit offset from the next instruction. Only used by branches.
+    Relative,
+    /// The instruction has no operand.
+    Implied,
+    /// The instruction operates on the accumulator.
+    Accumulator,
+}
+
+/// The 2A03's 6502 core. All memory accesses go through `bus`.
 #[allow(dead_code)]
-pub struct CPU {
+pub struct CPU<M: Mem = FlatMemory> {
     pub reg_a: u8,
     pub reg_x: u8,
+    pub reg_y: u8,
     pub status: Status,
     /// program counter
-    pub pc: u8,
+    pub pc: u16,
+    /// stack pointer, an offset into page one
+    pub sp: u8,
+    /// total CPU cycles elapsed since power-on
+    pub cycles: u64,
+    pub bus: M,
 }
 
 #[allow(dead_code)]
 /// Processor Status Flags. Each flag is one bit in size.
 /// # Flags:
 /// - C  : Carry
-///     The carry flag is set if the last operation caused an overflow from bit 7 of the result or an
-///     underflow from bit 0. This condition is set during arithmetic, comparison and during logical
-///     shifts. It can be explicitly set using the 'Set Carry Flag' (SEC) instruction and cleared with
-///     'Clear Carry Flag' (CLC).
+///   The carry flag is set if the last operation caused an overflow from bit 7 of the result or an
+///   underflow from bit 0. This condition is set during arithmetic, comparison and during logical
+///   shifts. It can be explicitly set using the 'Set Carry Flag' (SEC) instruction and cleared with
+///   'Clear Carry Flag' (CLC).
 ///
 /// - Z  : Zero
-///     The zero flag is set if the result of the last operation as was zero.
+///   The zero flag is set if the result of the last operation as was zero.
 ///
 /// - I  : Interrupt Disable
-///     The interrupt disable flag is set if the program has executed a 'Set Interrupt Disable' (SEI)
-///     instruction. While this flag is set the processor will not respond to interrupts from devices
-///     kuntil it is cleared by a 'Clear Interrupt Disable' (CLI) instruction.
+///   The interrupt disable flag is set if the program has executed a 'Set Interrupt Disable' (SEI)
+///   instruction. While this flag is set the processor will not respond to interrupts from devices
+///   kuntil it is cleared by a 'Clear Interrupt Disable' (CLI) instruction.
 ///
 /// - D  : Decimal Mode
-///     While the decimal mode flag is set the processor will obey the rules of Binary Coded Decimal
-///     (BCD) arithmetic during addition and subtraction. The flag can be explicitly set using 'Set
-///     Decimal Flag' (SED) and cleared with 'Clear Decimal Flag' (CLD).
+///   While the decimal mode flag is set the processor will obey the rules of Binary Coded Decimal
+///   (BCD) arithmetic during addition and subtraction. The flag can be explicitly set using 'Set
+///   Decimal Flag' (SED) and cleared with 'Clear Decimal Flag' (CLD).
 ///
 /// - B  : Break Command
-///     The break command bit is set when a BRK instruction has been executed and an interrupt has
-///     been generated to process it.
+///   The break command bit is set when a BRK instruction has been executed and an interrupt has
+///   been generated to process it.
+///
+/// - U  : Unused
+///   Bit 5 has no meaning. It always reads back as set when the status is pushed to the stack.
 ///
 /// - V  : Overflow
-///     The overflow flag is set during arithmetic operations if the result has yielded an invalid
-///     2's complement result (e.g. adding to positive numbers and ending up with a negative
-///     result: 64 + 64 => -128). It is determined by looking at the carry between bits 6 and 7 and
-///     between bit 7 and the carry flag.
+///   The overflow flag is set during arithmetic operations if the result has yielded an invalid
+///   2's complement result (e.g. adding to positive numbers and ending up with a negative
+///   result: 64 + 64 => -128). It is determined by looking at the carry between bits 6 and 7 and
+///   between bit 7 and the carry flag.
 ///
 /// - N  : Negative Flag
-///     The negative flag is set if the result of the last operation had bit 7 set to a one.
-#[derive(Debug, Clone)]
+///   The negative flag is set if the result of the last operation had bit 7 set to a one.
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Status {
     register: u8,
 }
@@ -51,6 +160,7 @@ pub struct Status {
 pub enum Flag {
     N = 7,
     V = 6,
+    U = 5,
     B = 4,
     D = 3,
     I = 2,
@@ -79,6 +189,23 @@ impl Status {
         self.register
     }
 
+    pub fn set_register(&mut self, value: u8) {
+        self.register = value;
+    }
+
+    pub fn is_set(&self, bit: Flag) -> bool {
+        self.read_bit(bit as u8) != 0
+    }
+
+    /// Sets `bit` if `value` is true and clears it otherwise.
+    pub fn update_bit(&mut self, bit: Flag, value: bool) {
+        if value {
+            self.set_bit(bit);
+        } else {
+            self.unset_bit(bit);
+        }
+    }
+
     // Read N Flag : Bit 7
     // # Return
     //  Returns 1 or 0 if the flag is set or unset respectively.
@@ -129,15 +256,57 @@ impl Status {
     }
 }
 
-#[allow(dead_code)]
-impl CPU {
+impl CPU<FlatMemory> {
+    /// Creates a CPU in its power-on state over a zeroed, flat 64KB address space.
     pub fn new() -> Self {
-        Self {
+        Self::with_bus(FlatMemory::new())
+    }
+}
+
+#[allow(dead_code)]
+impl<M: Mem> CPU<M> {
+    /// Creates a CPU in its power-on state, wired to `bus`.
+    pub fn with_bus(bus: M) -> Self {
+        let mut cpu = Self {
             reg_a: 0,
             reg_x: 0,
+            reg_y: 0,
             status: Status { register: 0x00 },
             pc: 0,
+            sp: 0,
+            cycles: 0,
+            bus,
+        };
+        cpu.power_on(RamInit::default());
+        cpu
+    }
+
+    /// Puts the CPU in its power-on state: A, X and Y cleared, SP at $FD, status $24, work RAM
+    /// filled according to `ram_init`, and PC loaded from the reset vector.
+    pub fn power_on(&mut self, ram_init: RamInit) {
+        self.reg_a = 0;
+        self.reg_x = 0;
+        self.reg_y = 0;
+        self.sp = STACK_RESET;
+        self.status.set_register(STATUS_RESET);
+        let mut ram = [0; RAM_SIZE];
+        ram_init.fill(&mut ram);
+        for (addr, byte) in ram.into_iter().enumerate() {
+            self.mem_write(addr as u16, byte);
         }
+        self.pc = self.mem_read_u16(RESET_VECTOR);
+        self.cycles = INTERRUPT_CYCLES.into();
+    }
+
+    /// Pulls the reset line. Like on hardware, RAM and the A/X/Y registers survive, SP moves down
+    /// by three as if the interrupt sequence had pushed without writing, I is set and PC is
+    /// reloaded from the reset vector.
+    pub fn reset(&mut self) {
+        debug!("reset");
+        self.sp = self.sp.wrapping_sub(3);
+        self.status.set_bit(Flag::I);
+        self.pc = self.mem_read_u16(RESET_VECTOR);
+        self.cycles += u64::from(INTERRUPT_CYCLES);
     }
 
     pub fn status(&self) -> &Status {
@@ -148,37 +317,667 @@ impl CPU {
         &mut self.status
     }
 
-    pub fn interpret(&mut self, program: Vec<u8>) {
-        'instruction_cycle: loop {
-            let opcode = program[self.pc as usize];
-            self.pc += 1;
-            match opcode {
-                // BREAK
-                0x00 => break 'instruction_cycle,
-
-                // LDA - LoaD Accumulator +
-                0xA9 => {
-                    let param = program[self.pc as usize];
-                    self.pc += 1;
-                    self.reg_a = param;
-                    self.update_nf_flags(self.reg_a);
-                }
+    pub fn mem_read(&mut self, addr: u16) -> u8 {
+        self.bus.read(addr)
+    }
 
-                // TAX - Transfer Accumulator to X
-                0xAA => {
-                    self.reg_x = self.reg_a;
-                    self.update_nf_flags(self.reg_x);
-                }
+    pub fn mem_write(&mut self, addr: u16, data: u8) {
+        self.bus.write(addr, data);
+    }
 
-                // INX - INcrement X register
-                0xE8 => {
-                    self.reg_x = self.reg_x.wrapping_add(1); // Integer Overflow is OK here.
-                    self.update_nf_flags(self.reg_x);
-                }
+    pub fn mem_read_u16(&mut self, addr: u16) -> u16 {
+        self.bus.read_u16(addr)
+    }
+
+    pub fn mem_write_u16(&mut self, addr: u16, data: u16) {
+        self.bus.write_u16(addr, data);
+    }
+
+    /// Copies `program` into memory at `start_addr` and points the reset vector at it. Bytes past
+    /// $FFFF wrap around to $0000.
+    pub fn load(&mut self, program: &[u8], start_addr: u16) {
+        for (i, &byte) in program.iter().enumerate() {
+            self.mem_write(start_addr.wrapping_add(i as u16), byte);
+        }
+        self.mem_write_u16(RESET_VECTOR, start_addr);
+    }
+
+    /// Loads `program` at $8000, then starts it from the reset vector and runs until it reaches a
+    /// `BRK`.
+    ///
+    /// # Errors
+    /// Returns [`NesError::CpuFault`] when an opcode the CPU can't execute is fetched.
+    pub fn interpret(&mut self, program: Vec<u8>) -> Result<()> {
+        self.load(&program, PROGRAM_START);
+        self.pc = self.mem_read_u16(RESET_VECTOR);
+        self.run()
+    }
+
+    /// Executes instructions from the current `pc` until the next one is a `BRK`, which is left
+    /// unexecuted. Small test programs use it as a terminator.
+    ///
+    /// # Errors
+    /// Returns [`NesError::CpuFault`] when an opcode the CPU can't execute is fetched.
+    pub fn run(&mut self) -> Result<()> {
+        while self.mem_read(self.pc) != 0x00 {
+            self.step()?;
+        }
+        Ok(())
+    }
+
+    /// Executes a single instruction and returns the number of cycles it took. The total is also
+    /// accumulated in [`CPU::cycles`].
+    ///
+    /// # Errors
+    /// Returns [`NesError::CpuFault`] when an opcode the CPU can't execute is fetched.
+    pub fn step(&mut self) -> Result<u8> {
+        let code = self.mem_read(self.pc);
+        trace!(
+            "{:04X}  {:02X}  A:{:02X} X:{:02X} Y:{:02X} P:{:02X}",
+            self.pc,
+            code,
+            self.reg_a,
+            self.reg_x,
+            self.reg_y,
+            self.status.register()
+        );
+        self.pc = self.pc.wrapping_add(1);
+
+        let Some(opcode) = opcodes::lookup(code) else {
+            let pc = self.pc.wrapping_sub(1);
+            error!("unknown opcode {code:02X} at {pc:04X}");
+            return Err(NesError::CpuFault { opcode: code, pc });
+        };
+        let (operand, page_crossed) = self.resolve_operand(opcode.mode);
+        // Step over the operand bytes. Jumps and branches overwrite this below.
+        self.pc = self.pc.wrapping_add(u16::from(opcode.len) - 1);
+
+        let mut cycles = opcode.cycles;
+        if page_crossed && opcode.page_penalty {
+            cycles += 1;
+        }
+
+        match code {
+            // BRK - software interrupt
+            0x00 => {
+                debug!("BRK at {:04X}", self.pc.wrapping_sub(1));
+                // BRK is followed by a padding byte that the return address skips over.
+                self.pc = self.pc.wrapping_add(1);
+                self.interrupt(IRQ_VECTOR, true);
+            }
+
+            // NOP - No OPeration
+            0xEA => {}
+
+            // ADC - ADd with Carry
+            0x69 | 0x65 | 0x75 | 0x6D | 0x7D | 0x79 | 0x61 | 0x71 => self.adc(operand),
+
+            // SBC - SuBtract with Carry
+            0xE9 | 0xE5 | 0xF5 | 0xED | 0xFD | 0xF9 | 0xE1 | 0xF1 => self.sbc(operand),
+
+            // AND - logical AND
+            0x29 | 0x25 | 0x35 | 0x2D | 0x3D | 0x39 | 0x21 | 0x31 => {
+                let data = self.read_operand(operand);
+                self.set_reg_a(self.reg_a & data);
+            }
+
+            // EOR - Exclusive OR
+            0x49 | 0x45 | 0x55 | 0x4D | 0x5D | 0x59 | 0x41 | 0x51 => {
+                let data = self.read_operand(operand);
+                self.set_reg_a(self.reg_a ^ data);
+            }
+
+            // ORA - logical inclusive OR
+            0x09 | 0x05 | 0x15 | 0x0D | 0x1D | 0x19 | 0x01 | 0x11 => {
+                let data = self.read_operand(operand);
+                self.set_reg_a(self.reg_a | data);
+            }
+
+            // ASL - Arithmetic Shift Left
+            0x0A | 0x06 | 0x16 | 0x0E | 0x1E => {
+                self.modify_operand(operand, Self::asl);
+            }
+
+            // LSR - Logical Shift Right
+            0x4A | 0x46 | 0x56 | 0x4E | 0x5E => {
+                self.modify_operand(operand, Self::lsr);
+            }
+
+            // ROL - ROtate Left
+            0x2A | 0x26 | 0x36 | 0x2E | 0x3E => {
+                self.modify_operand(operand, Self::rol);
+            }
+
+            // ROR - ROtate Right
+            0x6A | 0x66 | 0x76 | 0x6E | 0x7E => {
+                self.modify_operand(operand, Self::ror);
+            }
+
+            // INC - INCrement memory
+            0xE6 | 0xF6 | 0xEE | 0xFE => {
+                self.modify_operand(operand, Self::inc);
+            }
+
+            // INX - INcrement X register
+            0xE8 => {
+                self.reg_x = self.reg_x.wrapping_add(1); // Integer Overflow is OK here.
+                self.update_nf_flags(self.reg_x);
+            }
+
+            // INY - INcrement Y register
+            0xC8 => {
+                self.reg_y = self.reg_y.wrapping_add(1);
+                self.update_nf_flags(self.reg_y);
+            }
+
+            // DEC - DECrement memory
+            0xC6 | 0xD6 | 0xCE | 0xDE => {
+                self.modify_operand(operand, Self::dec);
+            }
+
+            // DEX - DEcrement X register
+            0xCA => {
+                self.reg_x = self.reg_x.wrapping_sub(1);
+                self.update_nf_flags(self.reg_x);
+            }
+
+            // DEY - DEcrement Y register
+            0x88 => {
+                self.reg_y = self.reg_y.wrapping_sub(1);
+                self.update_nf_flags(self.reg_y);
+            }
+
+            // CMP - CoMPare accumulator
+            0xC9 | 0xC5 | 0xD5 | 0xCD | 0xDD | 0xD9 | 0xC1 | 0xD1 => {
+                self.compare(operand, self.reg_a)
+            }
+
+            // CPX - ComPare X register
+            0xE0 | 0xE4 | 0xEC => self.compare(operand, self.reg_x),
+
+            // CPY - ComPare Y register
+            0xC0 | 0xC4 | 0xCC => self.compare(operand, self.reg_y),
+
+            // BIT - BIt Test
+            0x24 | 0x2C => {
+                let data = self.read_operand(operand);
+                let zero = data & self.reg_a == 0;
+                let status = self.status_mut();
+                status.update_bit(Flag::Z, zero);
+                status.update_bit(Flag::N, data & 0b1000_0000 != 0);
+                status.update_bit(Flag::V, data & 0b0100_0000 != 0);
+            }
+
+            // Branches
+            0x90 => cycles += self.branch(operand, page_crossed, !self.status.is_set(Flag::C)), // BCC
+            0xB0 => cycles += self.branch(operand, page_crossed, self.status.is_set(Flag::C)), // BCS
+            0xF0 => cycles += self.branch(operand, page_crossed, self.status.is_set(Flag::Z)), // BEQ
+            0xD0 => cycles += self.branch(operand, page_crossed, !self.status.is_set(Flag::Z)), // BNE
+            0x30 => cycles += self.branch(operand, page_crossed, self.status.is_set(Flag::N)), // BMI
+            0x10 => cycles += self.branch(operand, page_crossed, !self.status.is_set(Flag::N)), // BPL
+            0x50 => cycles += self.branch(operand, page_crossed, !self.status.is_set(Flag::V)), // BVC
+            0x70 => cycles += self.branch(operand, page_crossed, self.status.is_set(Flag::V)), // BVS
+
+            // JMP - JuMP
+            0x4C | 0x6C => self.pc = Self::address(operand),
+
+            // Flag instructions
+            0x18 => self.status.unset_bit(Flag::C), // CLC
+            0xD8 => self.status.unset_bit(Flag::D), // CLD
+            0x58 => self.status.unset_bit(Flag::I), // CLI
+            0xB8 => self.status.unset_bit(Flag::V), // CLV
+            0x38 => self.status.set_bit(Flag::C),   // SEC
+            0xF8 => self.status.set_bit(Flag::D),   // SED
+            0x78 => self.status.set_bit(Flag::I),   // SEI
+
+            // LDA - LoaD Accumulator
+            0xA9 | 0xA5 | 0xB5 | 0xAD | 0xBD | 0xB9 | 0xA1 | 0xB1 => {
+                let data = self.read_operand(operand);
+                self.set_reg_a(data);
+            }
+
+            // LDX - LoaD X register
+            0xA2 | 0xA6 | 0xB6 | 0xAE | 0xBE => {
+                self.reg_x = self.read_operand(operand);
+                self.update_nf_flags(self.reg_x);
+            }
+
+            // LDY - LoaD Y register
+            0xA0 | 0xA4 | 0xB4 | 0xAC | 0xBC => {
+                self.reg_y = self.read_operand(operand);
+                self.update_nf_flags(self.reg_y);
+            }
+
+            // STA - STore Accumulator
+            0x85 | 0x95 | 0x8D | 0x9D | 0x99 | 0x81 | 0x91 => {
+                self.write_operand(operand, self.reg_a)
+            }
+
+            // STX - STore X register
+            0x86 | 0x96 | 0x8E => self.write_operand(operand, self.reg_x),
+
+            // STY - STore Y register
+            0x84 | 0x94 | 0x8C => self.write_operand(operand, self.reg_y),
 
-                other => todo!("Unexpected Integer: {other:b}"),
+            // TAX - Transfer Accumulator to X
+            0xAA => {
+                self.reg_x = self.reg_a;
+                self.update_nf_flags(self.reg_x);
             }
+
+            // TAY - Transfer Accumulator to Y
+            0xA8 => {
+                self.reg_y = self.reg_a;
+                self.update_nf_flags(self.reg_y);
+            }
+
+            // TXA - Transfer X to Accumulator
+            0x8A => self.set_reg_a(self.reg_x),
+
+            // TYA - Transfer Y to Accumulator
+            0x98 => self.set_reg_a(self.reg_y),
+
+            // TSX - Transfer Stack pointer to X
+            0xBA => {
+                self.reg_x = self.sp;
+                self.update_nf_flags(self.reg_x);
+            }
+
+            // TXS - Transfer X to Stack pointer
+            0x9A => self.sp = self.reg_x,
+
+            // PHA - PusH Accumulator
+            0x48 => self.stack_push(self.reg_a),
+
+            // PHP - PusH Processor status
+            0x08 => {
+                // PHP always pushes B and bit 5 as set.
+                let status = self.status.register() | 1 << Flag::B as u8 | 1 << Flag::U as u8;
+                self.stack_push(status);
+            }
+
+            // PLA - PuLl Accumulator
+            0x68 => {
+                let data = self.stack_pop();
+                self.set_reg_a(data);
+            }
+
+            // PLP - PuLl Processor status
+            0x28 => self.pull_status(),
+
+            // JSR - Jump to SubRoutine
+            0x20 => {
+                // The return address pushed is the last byte of the JSR itself.
+                self.stack_push_u16(self.pc.wrapping_sub(1));
+                self.pc = Self::address(operand);
+            }
+
+            // RTS - ReTurn from Subroutine
+            0x60 => self.pc = self.stack_pop_u16().wrapping_add(1),
+
+            // RTI - ReTurn from Interrupt
+            0x40 => {
+                self.pull_status();
+                self.pc = self.stack_pop_u16();
+            }
+
+            /* Unofficial opcodes */
+            // *NOP - single byte
+            0x1A | 0x3A | 0x5A | 0x7A | 0xDA | 0xFA => {}
+
+            // *NOP - skips an immediate operand
+            0x80 | 0x82 | 0x89 | 0xC2 | 0xE2 => {}
+
+            // *NOP - performs a dummy read of its memory operand
+            0x04 | 0x44 | 0x64 | 0x14 | 0x34 | 0x54 | 0x74 | 0xD4 | 0xF4 | 0x0C | 0x1C | 0x3C
+            | 0x5C | 0x7C | 0xDC | 0xFC => {
+                self.read_operand(operand);
+            }
+
+            // *LAX - LDA + LDX
+            0xA7 | 0xB7 | 0xAF | 0xBF | 0xA3 | 0xB3 => {
+                let data = self.read_operand(operand);
+                self.set_reg_a(data);
+                self.reg_x = data;
+            }
+
+            // *SAX - store A & X
+            0x87 | 0x97 | 0x8F | 0x83 => self.write_operand(operand, self.reg_a & self.reg_x),
+
+            // *SBC - same as the official immediate SBC
+            0xEB => self.sbc(operand),
+
+            // *DCP - DEC + CMP
+            0xC7 | 0xD7 | 0xCF | 0xDF | 0xDB | 0xC3 | 0xD3 => {
+                let data = self.modify_operand(operand, Self::dec);
+                self.compare_with(self.reg_a, data);
+            }
+
+            // *ISB - INC + SBC
+            0xE7 | 0xF7 | 0xEF | 0xFF | 0xFB | 0xE3 | 0xF3 => {
+                let data = self.modify_operand(operand, Self::inc);
+                self.add_to_reg_a(!data);
+            }
+
+            // *SLO - ASL + ORA
+            0x07 | 0x17 | 0x0F | 0x1F | 0x1B | 0x03 | 0x13 => {
+                let data = self.modify_operand(operand, Self::asl);
+                self.set_reg_a(self.reg_a | data);
+            }
+
+            // *RLA - ROL + AND
+            0x27 | 0x37 | 0x2F | 0x3F | 0x3B | 0x23 | 0x33 => {
+                let data = self.modify_operand(operand, Self::rol);
+                self.set_reg_a(self.reg_a & data);
+            }
+
+            // *SRE - LSR + EOR
+            0x47 | 0x57 | 0x4F | 0x5F | 0x5B | 0x43 | 0x53 => {
+                let data = self.modify_operand(operand, Self::lsr);
+                self.set_reg_a(self.reg_a ^ data);
+            }
+
+            // *RRA - ROR + ADC
+            0x67 | 0x77 | 0x6F | 0x7F | 0x7B | 0x63 | 0x73 => {
+                let data = self.modify_operand(operand, Self::ror);
+                self.add_to_reg_a(data);
+            }
+
+            // *ANC - AND, then copy N into C
+            0x0B | 0x2B => {
+                let data = self.read_operand(operand);
+                self.set_reg_a(self.reg_a & data);
+                let negative = self.status.is_set(Flag::N);
+                self.status.update_bit(Flag::C, negative);
+            }
+
+            // *ALR - AND + LSR A
+            0x4B => {
+                let data = self.read_operand(operand);
+                self.reg_a = self.lsr(self.reg_a & data);
+            }
+
+            // *ARR - AND + ROR A, with C and V taken from bits 6 and 5 of the result
+            0x6B => {
+                let data = self.read_operand(operand);
+                self.reg_a = self.ror(self.reg_a & data);
+                let bit6 = self.reg_a & 0b0100_0000 != 0;
+                let bit5 = self.reg_a & 0b0010_0000 != 0;
+                self.status.update_bit(Flag::C, bit6);
+                self.status.update_bit(Flag::V, bit6 ^ bit5);
+            }
+
+            // *AXS - X = (A & X) - operand, without borrow
+            0xCB => {
+                let data = self.read_operand(operand);
+                let and = self.reg_a & self.reg_x;
+                self.compare_with(and, data);
+                self.reg_x = and.wrapping_sub(data);
+            }
+
+            _ => unreachable!("opcode {code:02X} is in the table but not dispatched"),
+        }
+
+        self.cycles += u64::from(cycles);
+        Ok(cycles)
+    }
+
+    /// Non-maskable interrupt, raised by the PPU at the start of vertical blank. Returns the cycles
+    /// the interrupt sequence took.
+    pub fn nmi(&mut self) -> u8 {
+        debug!("NMI at {:04X}", self.pc);
+        self.interrupt(NMI_VECTOR, false);
+        self.cycles += u64::from(INTERRUPT_CYCLES);
+        INTERRUPT_CYCLES
+    }
+
+    /// Maskable interrupt request. Ignored while the I flag is set. Returns the cycles the
+    /// interrupt sequence took, which is zero if it was masked.
+    pub fn irq(&mut self) -> u8 {
+        if self.status.is_set(Flag::I) {
+            return 0;
+        }
+        debug!("IRQ at {:04X}", self.pc);
+        self.interrupt(IRQ_VECTOR, false);
+        self.cycles += u64::from(INTERRUPT_CYCLES);
+        INTERRUPT_CYCLES
+    }
+
+    /// Pushes the return address and status, then jumps through `vector`. The pushed B flag is
+    /// the only way to tell a `BRK` apart from a hardware IRQ, since both share a vector.
+    fn interrupt(&mut self, vector: u16, brk: bool) {
+        self.stack_push_u16(self.pc);
+
+        let mut status = self.status.clone();
+        status.set_bit(Flag::U);
+        status.update_bit(Flag::B, brk);
+        self.stack_push(status.register());
+
+        self.status.set_bit(Flag::I);
+        self.pc = self.mem_read_u16(vector);
+    }
+
+    /// Resolves the effective address of the operand starting at `pc` for an instruction using
+    /// `mode`.
+    ///
+    /// Immediate operands resolve to the address of the operand byte itself, relative operands to
+    /// the branch target and indirect operands to the jump target. Implied and accumulator modes
+    /// have no address and return `None`.
+    pub fn operand_address(&mut self, mode: AddressingMode) -> Option<u16> {
+        self.resolve_operand(mode).0
+    }
+
+    /// Like [`CPU::operand_address`], but also reports whether indexing crossed a page boundary.
+    fn resolve_operand(&mut self, mode: AddressingMode) -> (Option<u16>, bool) {
+        let (addr, page_crossed) = match mode {
+            AddressingMode::Implied | AddressingMode::Accumulator => return (None, false),
+            AddressingMode::Immediate => (self.pc, false),
+            AddressingMode::ZeroPage => (self.mem_read(self.pc) as u16, false),
+            AddressingMode::ZeroPageX => (
+                self.mem_read(self.pc).wrapping_add(self.reg_x) as u16,
+                false,
+            ),
+            AddressingMode::ZeroPageY => (
+                self.mem_read(self.pc).wrapping_add(self.reg_y) as u16,
+                false,
+            ),
+            AddressingMode::Absolute => (self.mem_read_u16(self.pc), false),
+            AddressingMode::AbsoluteX => Self::index(self.mem_read_u16(self.pc), self.reg_x),
+            AddressingMode::AbsoluteY => Self::index(self.mem_read_u16(self.pc), self.reg_y),
+            AddressingMode::IndirectX => {
+                let ptr = self.mem_read(self.pc).wrapping_add(self.reg_x);
+                (self.read_zero_page_u16(ptr), false)
+            }
+            AddressingMode::Indirect => {
+                let ptr = self.mem_read_u16(self.pc);
+                // The 6502 doesn't carry into the high byte when fetching the target, so a pointer
+                // at $xxFF reads its high byte from $xx00.
+                let hi_addr = (ptr & 0xFF00) | (ptr as u8).wrapping_add(1) as u16;
+                let addr = u16::from_le_bytes([self.mem_read(ptr), self.mem_read(hi_addr)]);
+                (addr, false)
+            }
+            AddressingMode::IndirectY => {
+                let ptr = self.mem_read(self.pc);
+                Self::index(self.read_zero_page_u16(ptr), self.reg_y)
+            }
+            AddressingMode::Relative => {
+                let offset = self.mem_read(self.pc) as i8;
+                let next = self.pc.wrapping_add(1);
+                let target = next.wrapping_add(offset as u16);
+                (target, !same_page(next, target))
+            }
+        };
+        (Some(addr), page_crossed)
+    }
+
+    /// Adds an index register to `base`, reporting whether that crossed into another page.
+    fn index(base: u16, index: u8) -> (u16, bool) {
+        let addr = base.wrapping_add(index as u16);
+        (addr, !same_page(base, addr))
+    }
+
+    /// Unwraps the operand of an instruction whose mode always has an address.
+    fn address(operand: Option<u16>) -> u16 {
+        operand.expect("instruction requires a memory operand")
+    }
+
+    /// Reads the operand, which is the accumulator for accumulator mode instructions.
+    fn read_operand(&mut self, operand: Option<u16>) -> u8 {
+        match operand {
+            Some(addr) => self.mem_read(addr),
+            None => self.reg_a,
+        }
+    }
+
+    /// Writes the operand, which is the accumulator for accumulator mode instructions.
+    fn write_operand(&mut self, operand: Option<u16>, data: u8) {
+        match operand {
+            Some(addr) => self.mem_write(addr, data),
+            None => self.reg_a = data,
+        }
+    }
+
+    /// Reads a pointer from the zero page. The high byte wraps around to $00 instead of spilling
+    /// into page one.
+    fn read_zero_page_u16(&mut self, ptr: u8) -> u16 {
+        let lo = self.mem_read(ptr as u16);
+        let hi = self.mem_read(ptr.wrapping_add(1) as u16);
+        u16::from_le_bytes([lo, hi])
+    }
+
+    /// Read-modify-write helper for the shift, rotate, INC and DEC instructions. Returns the value
+    /// written back.
+    fn modify_operand(&mut self, operand: Option<u16>, op: impl FnOnce(&mut Self, u8) -> u8) -> u8 {
+        let data = self.read_operand(operand);
+        let result = op(self, data);
+        self.write_operand(operand, result);
+        result
+    }
+
+    fn stack_push(&mut self, data: u8) {
+        self.mem_write(STACK + self.sp as u16, data);
+        self.sp = self.sp.wrapping_sub(1);
+    }
+
+    fn stack_pop(&mut self) -> u8 {
+        self.sp = self.sp.wrapping_add(1);
+        self.mem_read(STACK + self.sp as u16)
+    }
+
+    /// Pushes the high byte first so the word sits little-endian in memory.
+    fn stack_push_u16(&mut self, data: u16) {
+        let [lo, hi] = data.to_le_bytes();
+        self.stack_push(hi);
+        self.stack_push(lo);
+    }
+
+    fn stack_pop_u16(&mut self) -> u16 {
+        let lo = self.stack_pop();
+        let hi = self.stack_pop();
+        u16::from_le_bytes([lo, hi])
+    }
+
+    /// Restores the status register from the stack. B and bit 5 don't exist as real flags, so
+    /// their pulled values are discarded.
+    fn pull_status(&mut self) {
+        let data = self.stack_pop();
+        self.status.set_register(data);
+        self.status.unset_bit(Flag::B);
+        self.status.set_bit(Flag::U);
+    }
+
+    fn set_reg_a(&mut self, value: u8) {
+        self.reg_a = value;
+        self.update_nf_flags(self.reg_a);
+    }
+
+    /// Adds `data` and the carry to the accumulator. The NES's 2A03 has no decimal mode, so the D
+    /// flag is ignored.
+    fn add_to_reg_a(&mut self, data: u8) {
+        let sum = self.reg_a as u16 + data as u16 + self.status.c() as u16;
+        let result = sum as u8;
+
+        // Overflow when both inputs share a sign that differs from the result's.
+        let overflow = (data ^ result) & (result ^ self.reg_a) & 0x80 != 0;
+
+        let status = self.status_mut();
+        status.update_bit(Flag::C, sum > 0xFF);
+        status.update_bit(Flag::V, overflow);
+
+        self.set_reg_a(result);
+    }
+
+    fn adc(&mut self, operand: Option<u16>) {
+        let data = self.read_operand(operand);
+        self.add_to_reg_a(data);
+    }
+
+    fn sbc(&mut self, operand: Option<u16>) {
+        // A - M - (1 - C) == A + !M + C
+        let data = self.read_operand(operand);
+        self.add_to_reg_a(!data);
+    }
+
+    fn asl(&mut self, data: u8) -> u8 {
+        self.status.update_bit(Flag::C, data & 0b1000_0000 != 0);
+        let result = data << 1;
+        self.update_nf_flags(result);
+        result
+    }
+
+    fn lsr(&mut self, data: u8) -> u8 {
+        self.status.update_bit(Flag::C, data & 1 != 0);
+        let result = data >> 1;
+        self.update_nf_flags(result);
+        result
+    }
+
+    fn rol(&mut self, data: u8) -> u8 {
+        let carry_in = self.status.c();
+        self.status.update_bit(Flag::C, data & 0b1000_0000 != 0);
+        let result = data << 1 | carry_in;
+        self.update_nf_flags(result);
+        result
+    }
+
+    fn ror(&mut self, data: u8) -> u8 {
+        let carry_in = self.status.c() << 7;
+        self.status.update_bit(Flag::C, data & 1 != 0);
+        let result = data >> 1 | carry_in;
+        self.update_nf_flags(result);
+        result
+    }
+
+    fn inc(&mut self, data: u8) -> u8 {
+        let result = data.wrapping_add(1);
+        self.update_nf_flags(result);
+        result
+    }
+
+    fn dec(&mut self, data: u8) -> u8 {
+        let result = data.wrapping_sub(1);
+        self.update_nf_flags(result);
+        result
+    }
+
+    fn compare(&mut self, operand: Option<u16>, register: u8) {
+        let data = self.read_operand(operand);
+        self.compare_with(register, data);
+    }
+
+    fn compare_with(&mut self, register: u8, data: u8) {
+        self.status.update_bit(Flag::C, register >= data);
+        self.update_nf_flags(register.wrapping_sub(data));
+    }
+
+    /// Jumps to the resolved relative target if `condition` holds. Returns the extra cycles spent:
+    /// one for a taken branch, plus one more if the target is on another page.
+    fn branch(&mut self, operand: Option<u16>, page_crossed: bool, condition: bool) -> u8 {
+        if !condition {
+            return 0;
         }
+        self.pc = Self::address(operand);
+        1 + u8::from(page_crossed)
     }
 
     fn update_nf_flags(&mut self, result: u8) {
@@ -197,7 +996,11 @@ impl CPU {
     }
 }
 
-impl Default for CPU {
+fn same_page(a: u16, b: u16) -> bool {
+    a & 0xFF00 == b & 0xFF00
+}
+
+impl Default for CPU<FlatMemory> {
     fn default() -> Self {
         Self::new()
     }
@@ -206,12 +1009,13 @@ impl Default for CPU {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use alloc::vec;
 
     #[test]
     fn test_0xaa_tax_move_a_to_x() {
         let mut cpu = CPU::new();
         cpu.reg_a = 10;
-        cpu.interpret(vec![0xaa, 0x00]);
+        cpu.interpret(vec![0xaa, 0x00]).unwrap();
 
         assert_eq!(cpu.reg_x, 10)
     }
@@ -219,7 +1023,7 @@ mod tests {
     #[test]
     fn test_5_ops_working_together() {
         let mut cpu = CPU::new();
-        cpu.interpret(vec![0xa9, 0xc0, 0xaa, 0xe8, 0x00]);
+        cpu.interpret(vec![0xa9, 0xc0, 0xaa, 0xe8, 0x00]).unwrap();
 
         assert_eq!(cpu.reg_x, 0xc1)
     }
@@ -228,15 +1032,546 @@ mod tests {
     fn test_inx_overflow() {
         let mut cpu = CPU::new();
         cpu.reg_x = 0xff;
-        cpu.interpret(vec![0xe8, 0xe8, 0x00]);
+        cpu.interpret(vec![0xe8, 0xe8, 0x00]).unwrap();
 
         assert_eq!(cpu.reg_x, 1)
     }
 
     #[test]
-    #[should_panic]
+    fn test_0xa5_lda_from_memory() {
+        let mut cpu = CPU::new();
+        cpu.mem_write(0x10, 0x55);
+        cpu.interpret(vec![0xa5, 0x10, 0x00]).unwrap();
+
+        assert_eq!(cpu.reg_a, 0x55)
+    }
+
+    #[test]
+    fn test_lda_zero_page_x_wraps() {
+        let mut cpu = CPU::new();
+        cpu.mem_write(0x0f, 0x42);
+        cpu.interpret(vec![0xa2, 0x10, 0xb5, 0xff, 0x00]).unwrap();
+
+        assert_eq!(cpu.reg_a, 0x42)
+    }
+
+    #[test]
+    fn test_lda_indirect_modes() {
+        let mut cpu = CPU::new();
+        cpu.mem_write_u16(0x24, 0x0400);
+        cpu.mem_write(0x0400, 0x11);
+        cpu.mem_write(0x0403, 0x22);
+        // LDX #$04; LDA ($20,X); TAY; LDY #$03; LDA ($24),Y
+        cpu.interpret(vec![
+            0xa2, 0x04, 0xa1, 0x20, 0xa8, 0xa0, 0x03, 0xb1, 0x24, 0x00,
+        ])
+        .unwrap();
+
+        assert_eq!(cpu.reg_a, 0x22)
+    }
+
+    #[test]
+    fn test_operand_address_resolution() {
+        let mut cpu = CPU::new();
+        cpu.pc = 0x0600;
+        cpu.reg_x = 0x02;
+        cpu.reg_y = 0x10;
+        cpu.mem_write_u16(0x0600, 0x12f0);
+        cpu.mem_write_u16(0x00f2, 0x0300);
+        cpu.mem_write_u16(0x12f0, 0xbeef);
+
+        assert_eq!(cpu.operand_address(AddressingMode::Immediate), Some(0x0600));
+        assert_eq!(cpu.operand_address(AddressingMode::ZeroPage), Some(0x00f0));
+        assert_eq!(cpu.operand_address(AddressingMode::ZeroPageX), Some(0x00f2));
+        assert_eq!(cpu.operand_address(AddressingMode::ZeroPageY), Some(0x0000));
+        assert_eq!(cpu.operand_address(AddressingMode::Absolute), Some(0x12f0));
+        assert_eq!(cpu.operand_address(AddressingMode::AbsoluteX), Some(0x12f2));
+        assert_eq!(cpu.operand_address(AddressingMode::AbsoluteY), Some(0x1300));
+        assert_eq!(cpu.operand_address(AddressingMode::Indirect), Some(0xbeef));
+        assert_eq!(cpu.operand_address(AddressingMode::IndirectX), Some(0x0300));
+        // $F0 is read as a relative offset of -16 from $0601.
+        assert_eq!(cpu.operand_address(AddressingMode::Relative), Some(0x05f1));
+        assert_eq!(cpu.operand_address(AddressingMode::Implied), None);
+        assert_eq!(cpu.operand_address(AddressingMode::Accumulator), None);
+    }
+
+    #[test]
+    fn test_sta_stx_sty() {
+        let mut cpu = CPU::new();
+        cpu.interpret(vec![
+            0xa9, 0x01, 0xa2, 0x02, 0xa0, 0x03, 0x85, 0x10, 0x8e, 0x00, 0x02, 0x84, 0x11, 0x00,
+        ])
+        .unwrap();
+
+        assert_eq!(cpu.mem_read(0x10), 0x01);
+        assert_eq!(cpu.mem_read(0x0200), 0x02);
+        assert_eq!(cpu.mem_read(0x11), 0x03)
+    }
+
+    #[test]
+    fn test_adc_sets_carry_and_overflow() {
+        let mut cpu = CPU::new();
+        cpu.interpret(vec![0xa9, 0x50, 0x69, 0x50, 0x00]).unwrap();
+
+        assert_eq!(cpu.reg_a, 0xa0);
+        assert!(cpu.status.is_set(Flag::V));
+        assert!(!cpu.status.is_set(Flag::C));
+
+        cpu.interpret(vec![0xa9, 0xff, 0x69, 0x02, 0x00]).unwrap();
+
+        assert_eq!(cpu.reg_a, 0x01);
+        assert!(cpu.status.is_set(Flag::C));
+        assert!(!cpu.status.is_set(Flag::V))
+    }
+
+    #[test]
+    fn test_sbc_borrows() {
+        let mut cpu = CPU::new();
+        // SEC; LDA #$05; SBC #$06
+        cpu.interpret(vec![0x38, 0xa9, 0x05, 0xe9, 0x06, 0x00])
+            .unwrap();
+
+        assert_eq!(cpu.reg_a, 0xff);
+        assert!(!cpu.status.is_set(Flag::C));
+        assert!(cpu.status.is_set(Flag::N))
+    }
+
+    #[test]
+    fn test_logical_ops() {
+        let mut cpu = CPU::new();
+        // LDA #$F0; AND #$3C; ORA #$01; EOR #$FF
+        cpu.interpret(vec![0xa9, 0xf0, 0x29, 0x3c, 0x09, 0x01, 0x49, 0xff, 0x00])
+            .unwrap();
+
+        assert_eq!(cpu.reg_a, 0xce)
+    }
+
+    #[test]
+    fn test_shifts_and_rotates() {
+        let mut cpu = CPU::new();
+        cpu.mem_write(0x10, 0x01);
+        // LDA #$81; ASL A; ROR $10
+        cpu.interpret(vec![0xa9, 0x81, 0x0a, 0x66, 0x10, 0x00])
+            .unwrap();
+
+        assert_eq!(cpu.reg_a, 0x02);
+        assert_eq!(cpu.mem_read(0x10), 0x80);
+        assert!(cpu.status.is_set(Flag::C))
+    }
+
+    #[test]
+    fn test_inc_dec_memory() {
+        let mut cpu = CPU::new();
+        cpu.mem_write(0x10, 0xff);
+        cpu.mem_write(0x11, 0x01);
+        cpu.interpret(vec![0xe6, 0x10, 0xc6, 0x11, 0x00]).unwrap();
+
+        assert_eq!(cpu.mem_read(0x10), 0x00);
+        assert_eq!(cpu.mem_read(0x11), 0x00);
+        assert!(cpu.status.is_set(Flag::Z))
+    }
+
+    #[test]
+    fn test_compare_flags() {
+        let mut cpu = CPU::new();
+        cpu.interpret(vec![0xa9, 0x10, 0xc9, 0x10, 0x00]).unwrap();
+        assert!(cpu.status.is_set(Flag::Z));
+        assert!(cpu.status.is_set(Flag::C));
+
+        cpu.interpret(vec![0xa2, 0x01, 0xe0, 0x02, 0x00]).unwrap();
+        assert!(!cpu.status.is_set(Flag::C));
+        assert!(cpu.status.is_set(Flag::N))
+    }
+
+    #[test]
+    fn test_bit() {
+        let mut cpu = CPU::new();
+        cpu.mem_write(0x10, 0xc0);
+        cpu.interpret(vec![0xa9, 0x01, 0x24, 0x10, 0x00]).unwrap();
+
+        assert!(cpu.status.is_set(Flag::Z));
+        assert!(cpu.status.is_set(Flag::N));
+        assert!(cpu.status.is_set(Flag::V))
+    }
+
+    #[test]
+    fn test_branch_loop() {
+        let mut cpu = CPU::new();
+        // LDX #$05; loop: INY; DEX; BNE loop
+        cpu.interpret(vec![0xa2, 0x05, 0xc8, 0xca, 0xd0, 0xfc, 0x00])
+            .unwrap();
+
+        assert_eq!(cpu.reg_y, 5);
+        assert_eq!(cpu.reg_x, 0)
+    }
+
+    #[test]
+    fn test_jmp_absolute() {
+        let mut cpu = CPU::new();
+        // JMP $8005; LDA #$01; BRK; LDA #$02
+        cpu.interpret(vec![0x4c, 0x05, 0x80, 0xa9, 0x01, 0xa9, 0x02, 0x00])
+            .unwrap();
+
+        assert_eq!(cpu.reg_a, 0x02)
+    }
+
+    #[test]
+    fn test_jmp_indirect_page_boundary_bug() {
+        let mut cpu = CPU::new();
+        cpu.mem_write(0x02ff, 0x07);
+        cpu.mem_write(0x0200, 0x80);
+        cpu.mem_write(0x0300, 0x90);
+        // JMP ($02FF) lands on $8007, not $9007.
+        cpu.interpret(vec![
+            0x6c, 0xff, 0x02, 0xa9, 0x01, 0x00, 0x00, 0xa9, 0x02, 0x00,
+        ])
+        .unwrap();
+
+        assert_eq!(cpu.reg_a, 0x02)
+    }
+
+    #[test]
+    fn test_transfers() {
+        let mut cpu = CPU::new();
+        // LDA #$07; TAY; LDA #$00; TYA
+        cpu.interpret(vec![0xa9, 0x07, 0xa8, 0xa9, 0x00, 0x98, 0x00])
+            .unwrap();
+
+        assert_eq!(cpu.reg_a, 0x07);
+        assert!(!cpu.status.is_set(Flag::Z))
+    }
+
+    #[test]
+    fn test_jsr_rts() {
+        let mut cpu = CPU::new();
+        // JSR sub; INX; BRK; sub: LDX #$41; RTS
+        cpu.interpret(vec![0x20, 0x05, 0x80, 0xe8, 0x00, 0xa2, 0x41, 0x60])
+            .unwrap();
+
+        assert_eq!(cpu.reg_x, 0x42);
+        assert_eq!(cpu.sp, 0xfd)
+    }
+
+    #[test]
+    fn test_pha_pla() {
+        let mut cpu = CPU::new();
+        // LDA #$80; PHA; LDA #$00; PLA
+        cpu.interpret(vec![0xa9, 0x80, 0x48, 0xa9, 0x00, 0x68, 0x00])
+            .unwrap();
+
+        assert_eq!(cpu.reg_a, 0x80);
+        assert_eq!(cpu.mem_read(0x01fd), 0x80);
+        assert!(cpu.status.is_set(Flag::N));
+        assert_eq!(cpu.sp, 0xfd)
+    }
+
+    #[test]
+    fn test_php_plp() {
+        let mut cpu = CPU::new();
+        // SEC; PHP; CLC; PLP
+        cpu.interpret(vec![0x38, 0x08, 0x18, 0x28, 0x00]).unwrap();
+
+        assert_eq!(cpu.mem_read(0x01fd), 0b0011_0101);
+        assert!(cpu.status.is_set(Flag::C));
+        assert!(!cpu.status.is_set(Flag::B));
+        assert!(cpu.status.is_set(Flag::U))
+    }
+
+    #[test]
+    fn test_tsx_txs() {
+        let mut cpu = CPU::new();
+        // LDX #$80; TXS; PHA; TSX
+        cpu.interpret(vec![0xa2, 0x80, 0x9a, 0x48, 0xba, 0x00])
+            .unwrap();
+
+        assert_eq!(cpu.sp, 0x7f);
+        assert_eq!(cpu.reg_x, 0x7f)
+    }
+
+    #[test]
+    fn test_brk_pushes_state_and_jumps_to_irq_vector() {
+        let mut cpu = CPU::new();
+        cpu.load(&[0x00, 0xea], 0x0600);
+        cpu.mem_write_u16(IRQ_VECTOR, 0x1234);
+        cpu.pc = 0x0600;
+        cpu.status.set_bit(Flag::C);
+        cpu.step().unwrap();
+
+        assert_eq!(cpu.pc, 0x1234);
+        assert_eq!(cpu.sp, 0xfa);
+        assert_eq!(cpu.mem_read_u16(0x01fc), 0x0602);
+        assert_eq!(cpu.mem_read(0x01fb), 0b0011_0101);
+        assert!(cpu.status.is_set(Flag::I))
+    }
+
+    #[test]
+    fn test_nmi_and_rti() {
+        let mut cpu = CPU::new();
+        cpu.mem_write_u16(NMI_VECTOR, 0x0700);
+        cpu.mem_write(0x0700, 0x40); // RTI
+        cpu.pc = 0x0600;
+        cpu.status.set_bit(Flag::I);
+        cpu.nmi();
+
+        assert_eq!(cpu.pc, 0x0700);
+        // Hardware interrupts push B clear.
+        assert_eq!(cpu.mem_read(0x01fb), 0b0010_0100);
+
+        cpu.step().unwrap();
+
+        assert_eq!(cpu.pc, 0x0600);
+        assert_eq!(cpu.sp, 0xfd)
+    }
+
+    #[test]
+    fn test_irq_respects_interrupt_disable() {
+        let mut cpu = CPU::new();
+        cpu.mem_write_u16(IRQ_VECTOR, 0x0700);
+        cpu.pc = 0x0600;
+        cpu.status.set_bit(Flag::I);
+        cpu.irq();
+
+        assert_eq!(cpu.pc, 0x0600);
+
+        cpu.status.unset_bit(Flag::I);
+        cpu.irq();
+
+        assert_eq!(cpu.pc, 0x0700);
+        assert!(cpu.status.is_set(Flag::I))
+    }
+
+    #[test]
+    fn test_power_on_state() {
+        let mut cpu = CPU::new();
+        cpu.mem_write_u16(RESET_VECTOR, 0x8000);
+        cpu.reg_a = 0x12;
+        cpu.power_on(RamInit::Ones);
+
+        assert_eq!(cpu.reg_a, 0);
+        assert_eq!(cpu.sp, 0xfd);
+        assert_eq!(cpu.status.register(), 0x24);
+        assert_eq!(cpu.pc, 0x8000);
+        assert_eq!(cpu.cycles, 7);
+        assert_eq!(cpu.mem_read(0x0000), 0xff);
+        assert_eq!(cpu.mem_read(0x07ff), 0xff);
+        // Only work RAM is filled.
+        assert_eq!(cpu.mem_read(0x0800), 0x00)
+    }
+
+    #[test]
+    fn test_reset_keeps_ram_and_registers() {
+        let mut cpu = CPU::new();
+        cpu.mem_write_u16(RESET_VECTOR, 0x8000);
+        cpu.mem_write(0x0010, 0x99);
+        cpu.reg_a = 0x12;
+        cpu.status.unset_bit(Flag::I);
+        cpu.reset();
+
+        assert_eq!(cpu.mem_read(0x0010), 0x99);
+        assert_eq!(cpu.reg_a, 0x12);
+        assert_eq!(cpu.sp, 0xfa);
+        assert!(cpu.status.is_set(Flag::I));
+        assert_eq!(cpu.pc, 0x8000)
+    }
+
+    #[test]
+    fn test_ram_init_patterns() {
+        let mut ram = [0u8; 16];
+        RamInit::Alternating.fill(&mut ram);
+        assert_eq!(ram[..8], [0, 0, 0, 0, 0xff, 0xff, 0xff, 0xff]);
+
+        let mut a = [0u8; 16];
+        let mut b = [0u8; 16];
+        RamInit::Random(7).fill(&mut a);
+        RamInit::Random(7).fill(&mut b);
+        assert_eq!(a, b);
+        assert_ne!(a, [0u8; 16])
+    }
+
+    #[test]
+    fn test_step_returns_cycles() {
+        let mut cpu = CPU::new();
+        // LDA #$01; STA $0200; INC $10,X; JSR $0600
+        cpu.load(
+            &[0xa9, 0x01, 0x8d, 0x00, 0x02, 0xf6, 0x10, 0x20, 0x00, 0x06],
+            0x8000,
+        );
+        cpu.pc = 0x8000;
+        let start = cpu.cycles;
+
+        assert_eq!(cpu.step().unwrap(), 2);
+        assert_eq!(cpu.step().unwrap(), 4);
+        assert_eq!(cpu.step().unwrap(), 6);
+        assert_eq!(cpu.step().unwrap(), 6);
+        assert_eq!(cpu.cycles - start, 18);
+
+        assert_eq!(cpu.nmi(), 7);
+        assert_eq!(cpu.cycles - start, 25)
+    }
+
+    #[test]
+    fn test_page_cross_penalties() {
+        let mut cpu = CPU::new();
+        cpu.mem_write_u16(0x10, 0x02f0);
+        // LDX #$20; LDA $02F0,X; LDA $0200,X; STA $02F0,X; LDY #$20; LDA ($10),Y
+        cpu.load(
+            &[
+                0xa2, 0x20, 0xbd, 0xf0, 0x02, 0xbd, 0x00, 0x02, 0x9d, 0xf0, 0x02, 0xa0, 0x20, 0xb1,
+                0x10,
+            ],
+            0x8000,
+        );
+        cpu.pc = 0x8000;
+        cpu.step().unwrap();
+
+        assert_eq!(cpu.step().unwrap(), 5);
+        assert_eq!(cpu.step().unwrap(), 4);
+        // Stores always take the extra cycle.
+        assert_eq!(cpu.step().unwrap(), 5);
+        cpu.step().unwrap();
+        assert_eq!(cpu.step().unwrap(), 6)
+    }
+
+    #[test]
+    fn test_branch_penalties() {
+        let mut cpu = CPU::new();
+        // SEC; BCC +2 (not taken); BCS +0 (taken, same page); BCS +$7F (taken, crosses)
+        cpu.load(&[0x38, 0x90, 0x02, 0xb0, 0x00, 0xb0, 0x7f], 0x80f0);
+        cpu.pc = 0x80f0;
+        cpu.step().unwrap();
+
+        assert_eq!(cpu.step().unwrap(), 2);
+        assert_eq!(cpu.step().unwrap(), 3);
+        assert_eq!(cpu.step().unwrap(), 4);
+        assert_eq!(cpu.pc, 0x8176)
+    }
+
+    #[test]
+    fn test_unofficial_lax_sax() {
+        let mut cpu = CPU::new();
+        cpu.mem_write(0x10, 0x3c);
+        // LAX $10; LDX #$0F; SAX $11
+        cpu.interpret(vec![0xa7, 0x10, 0xa2, 0x0f, 0x87, 0x11, 0x00])
+            .unwrap();
+
+        assert_eq!(cpu.reg_a, 0x3c);
+        assert_eq!(cpu.mem_read(0x11), 0x0c)
+    }
+
+    #[test]
+    fn test_unofficial_read_modify_write() {
+        let mut cpu = CPU::new();
+        cpu.mem_write(0x10, 0x06);
+        cpu.mem_write(0x11, 0x40);
+        // LDA #$05; DCP $10; SEC; ISB $10; SLO $11
+        cpu.interpret(vec![
+            0xa9, 0x05, 0xc7, 0x10, 0x38, 0xe7, 0x10, 0x07, 0x11, 0x00,
+        ])
+        .unwrap();
+
+        assert_eq!(cpu.mem_read(0x10), 0x06);
+        assert_eq!(cpu.mem_read(0x11), 0x80);
+        assert_eq!(cpu.reg_a, 0xff)
+    }
+
+    #[test]
+    fn test_unofficial_nops_skip_operands() {
+        let mut cpu = CPU::new();
+        // *NOP; *NOP #$A9; *NOP $1234,X; LDA #$01
+        cpu.interpret(vec![0x1a, 0x80, 0xa9, 0x1c, 0x34, 0x12, 0xa9, 0x01, 0x00])
+            .unwrap();
+
+        assert_eq!(cpu.reg_a, 0x01)
+    }
+
+    #[test]
+    fn test_unofficial_axs() {
+        let mut cpu = CPU::new();
+        // LDA #$F0; LDX #$3C; AXS #$10
+        cpu.interpret(vec![0xa9, 0xf0, 0xa2, 0x3c, 0xcb, 0x10, 0x00])
+            .unwrap();
+
+        assert_eq!(cpu.reg_x, 0x20);
+        assert!(cpu.status.is_set(Flag::C))
+    }
+
+    #[test]
+    fn test_load_at_custom_address() {
+        let mut cpu = CPU::new();
+        cpu.load(&[0xa9, 0x42, 0x00], 0x0600);
+
+        assert_eq!(cpu.mem_read_u16(RESET_VECTOR), 0x0600);
+
+        cpu.pc = cpu.mem_read_u16(RESET_VECTOR);
+        cpu.run().unwrap();
+
+        assert_eq!(cpu.reg_a, 0x42);
+        assert_eq!(cpu.pc, 0x0602)
+    }
+
+    #[test]
+    fn test_custom_bus() {
+        /// Serves a counter at $4000 that increments on every read.
+        struct Counter {
+            ram: FlatMemory,
+            value: u8,
+        }
+
+        impl Mem for Counter {
+            fn read(&mut self, addr: u16) -> u8 {
+                if addr == 0x4000 {
+                    self.value += 1;
+                    return self.value;
+                }
+                self.ram.read(addr)
+            }
+
+            fn write(&mut self, addr: u16, data: u8) {
+                self.ram.write(addr, data);
+            }
+        }
+
+        let mut cpu = CPU::with_bus(Counter {
+            ram: FlatMemory::new(),
+            value: 0,
+        });
+        // LDA $4000; LDA $4000; STA $10
+        cpu.interpret(vec![0xad, 0x00, 0x40, 0xad, 0x00, 0x40, 0x85, 0x10, 0x00])
+            .unwrap();
+
+        assert_eq!(cpu.reg_a, 2);
+        assert_eq!(cpu.mem_read(0x10), 2)
+    }
+
+    #[test]
     fn test_unknown_case() {
         let mut cpu = CPU::new();
-        cpu.interpret(vec![0x5, 0x00]);
+        let err = cpu.interpret(vec![0x02, 0x00]).unwrap_err();
+
+        assert!(matches!(
+            err,
+            NesError::CpuFault {
+                opcode: 0x02,
+                pc: 0x8000
+            }
+        ))
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_independent_cpus_run_concurrently() {
+        let handles: Vec<_> = (0..4u8)
+            .map(|i| {
+                let mut cpu = CPU::new();
+                std::thread::spawn(move || {
+                    cpu.interpret(vec![0xa9, i, 0xaa, 0xe8, 0x00]).unwrap();
+                    cpu.reg_x
+                })
+            })
+            .collect();
+
+        for (i, handle) in handles.into_iter().enumerate() {
+            assert_eq!(handle.join().unwrap(), i as u8 + 1)
+        }
     }
 }
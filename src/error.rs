@@ -0,0 +1,37 @@
+use alloc::string::String;
+
+/// Every way the emulator can fail. Public APIs return [`Result`] instead of panicking so library
+/// consumers can decide how to recover.
+#[derive(Debug, thiserror::Error)]
+pub enum NesError {
+    /// The ROM image is malformed or truncated.
+    #[error("failed to parse ROM: {0}")]
+    RomParse(String),
+
+    /// The cartridge uses a mapper this emulator does not implement.
+    #[error("unsupported mapper {0}")]
+    UnsupportedMapper(u16),
+
+    /// The console was set up with options that don't work, or don't work together.
+    #[error("invalid configuration: {0}")]
+    InvalidConfig(String),
+
+    /// A save state (or other serialized state) could not be restored.
+    #[error("invalid state: {0}")]
+    InvalidState(String),
+
+    /// A cheat code could not be decoded.
+    #[error("invalid cheat code: {0}")]
+    InvalidCheat(String),
+
+    /// The CPU fetched an opcode it cannot execute.
+    #[error("CPU fault: opcode {opcode:#04x} at {pc:#06x}")]
+    CpuFault { opcode: u8, pc: u16 },
+
+    /// Host I/O failed while reading or writing files.
+    #[cfg(feature = "std")]
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+pub type Result<T> = core::result::Result<T, NesError>;
@@ -0,0 +1,141 @@
+//! The Four Score and NES Satellite multitaps.
+//!
+//! A multitap takes both controller ports and has four controllers of its own. Each port reads out
+//! 24 bits after a strobe: its first controller (1 or 2), its second (3 or 4), then a signature
+//! byte games check to see that a multitap is there at all. Reads past the 24th return 1.
+//!
+//! ```text
+//! $4016  reads 1-8 controller 1, 9-16 controller 3, 17-24 signature, 1 on the 20th
+//! $4017  reads 1-8 controller 2, 9-16 controller 4, 17-24 signature, 1 on the 19th
+//! ```
+//!
+//! Each port is its own [`FourScore`] device, holding the two controllers read through it.
+
+use alloc::vec::Vec;
+
+use serde::{Deserialize, Serialize};
+
+use super::{ControllerPort, Joypad};
+use crate::error::Result;
+use crate::state;
+
+/// The signature bytes, in the order they are shifted out.
+const PORT_1_SIGNATURE: u8 = 0x08;
+const PORT_2_SIGNATURE: u8 = 0x04;
+
+/// Two devices' worth of bits and the signature.
+const REPORT_BITS: u32 = 24;
+
+/// One port's half of a multitap.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FourScore {
+    /// The controllers read through this port: 1 and 3, or 2 and 4.
+    joypads: [Joypad; 2],
+    signature: u8,
+    strobe: bool,
+    shift: u32,
+}
+
+impl FourScore {
+    /// The half that plugs into port 1, with controllers 1 and 3.
+    pub fn port1() -> Self {
+        Self::new(PORT_1_SIGNATURE)
+    }
+
+    /// The half that plugs into port 2, with controllers 2 and 4.
+    pub fn port2() -> Self {
+        Self::new(PORT_2_SIGNATURE)
+    }
+
+    fn new(signature: u8) -> Self {
+        Self {
+            joypads: [Joypad::new(), Joypad::new()],
+            signature,
+            strobe: false,
+            shift: 0,
+        }
+    }
+
+    /// The first (0) or second (1) controller read through this port.
+    ///
+    /// # Panics
+    /// If `index` is out of range.
+    pub fn joypad(&self, index: usize) -> &Joypad {
+        &self.joypads[index]
+    }
+
+    pub fn joypad_mut(&mut self, index: usize) -> &mut Joypad {
+        &mut self.joypads[index]
+    }
+
+    /// Everything the port shifts out after a strobe, first bit lowest.
+    fn report(&self) -> u32 {
+        let [first, second] = &self.joypads;
+        u32::from(first.output())
+            | u32::from(second.output()) << 8
+            | u32::from(self.signature) << 16
+    }
+}
+
+impl ControllerPort for FourScore {
+    fn write(&mut self, data: u8) {
+        if self.strobe {
+            self.shift = self.report();
+        }
+        self.strobe = data & 1 != 0;
+    }
+
+    fn read(&mut self) -> u8 {
+        if self.strobe {
+            return (self.report() & 1) as u8;
+        }
+        let bit = (self.shift & 1) as u8;
+        self.shift = self.shift >> 1 | 1 << (REPORT_BITS - 1);
+        bit
+    }
+
+    fn end_frame(&mut self) {
+        for joypad in &mut self.joypads {
+            joypad.end_frame();
+        }
+    }
+
+    fn save_state(&self) -> Vec<u8> {
+        state::to_bytes(self)
+    }
+
+    fn load_state(&mut self, state: &[u8]) -> Result<()> {
+        *self = state::from_bytes(state)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input::Button;
+
+    fn read_report(port: &mut FourScore) -> [u8; 26] {
+        port.write(1);
+        port.write(0);
+        core::array::from_fn(|_| port.read())
+    }
+
+    #[test]
+    fn test_report() {
+        let mut port = FourScore::port1();
+        port.joypad_mut(0).set_button(Button::A, true);
+        port.joypad_mut(1).set_button(Button::Right, true);
+        let bits = read_report(&mut port);
+        assert_eq!(bits[..8], [1, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(bits[8..16], [0, 0, 0, 0, 0, 0, 0, 1]);
+        assert_eq!(bits[16..24], [0, 0, 0, 1, 0, 0, 0, 0]);
+        assert_eq!(bits[24..], [1, 1])
+    }
+
+    #[test]
+    fn test_port_2_signature() {
+        let bits = read_report(&mut FourScore::port2());
+        assert_eq!(bits[16..24], [0, 0, 1, 0, 0, 0, 0, 0])
+    }
+}
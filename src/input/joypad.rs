@@ -0,0 +1,246 @@
+//! The standard NES controller.
+//!
+//! Eight buttons read out one bit at a time. Writing 1 to $4016 holds the controller's shift
+//! register in reload mode, continuously latching the buttons; writing 0 freezes it, after which
+//! each read of the controller's port shifts out the next button in the order A, B, Select, Start,
+//! Up, Down, Left, Right. Reads past the eighth return 1, as on an official controller.
+//!
+//! Buttons can also be put on turbo, like the NES Advantage's A and B: while held, they read as
+//! pressed and released in turn, switching every few frames. The rate counts emulated frames, so
+//! autofire replays the same way every time.
+
+use alloc::vec::Vec;
+
+use serde::{Deserialize, Serialize};
+
+use super::ControllerPort;
+use crate::error::Result;
+use crate::state;
+
+/// The controller's buttons, in the order they are shifted out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Button {
+    A,
+    B,
+    Select,
+    Start,
+    Up,
+    Down,
+    Left,
+    Right,
+}
+
+impl Button {
+    /// The button's bit in [`Joypad::buttons`].
+    pub fn mask(self) -> u8 {
+        1 << self as u8
+    }
+}
+
+/// Frames each turbo press and release lasts unless configured otherwise: 15 presses a second.
+pub const DEFAULT_TURBO_RATE: u8 = 2;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Joypad {
+    /// Held buttons, bit n for the nth button shifted out.
+    buttons: u8,
+    /// While set, the shift register keeps reloading and reads return A.
+    strobe: bool,
+    shift: u8,
+    /// Buttons on turbo.
+    turbo: u8,
+    /// Frames per turbo toggle.
+    turbo_rate: u8,
+    /// Frames into the current toggle, and whether turbo buttons read as pressed during it.
+    turbo_frames: u8,
+    turbo_pressed: bool,
+}
+
+impl Default for Joypad {
+    fn default() -> Self {
+        Self {
+            buttons: 0,
+            strobe: false,
+            shift: 0,
+            turbo: 0,
+            turbo_rate: DEFAULT_TURBO_RATE,
+            turbo_frames: 0,
+            turbo_pressed: true,
+        }
+    }
+}
+
+impl Joypad {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Presses or releases `button`.
+    pub fn set_button(&mut self, button: Button, pressed: bool) {
+        if pressed {
+            self.buttons |= button.mask();
+        } else {
+            self.buttons &= !button.mask();
+        }
+    }
+
+    pub fn pressed(&self, button: Button) -> bool {
+        self.buttons & button.mask() != 0
+    }
+
+    /// Every button at once, one bit each as in [`Button::mask`].
+    pub fn buttons(&self) -> u8 {
+        self.buttons
+    }
+
+    pub fn set_buttons(&mut self, buttons: u8) {
+        self.buttons = buttons;
+    }
+
+    /// Puts `button` on turbo or takes it off. Usually A or B, but any button works.
+    pub fn set_turbo(&mut self, button: Button, enabled: bool) {
+        if enabled {
+            self.turbo |= button.mask();
+        } else {
+            self.turbo &= !button.mask();
+        }
+    }
+
+    pub fn turbo(&self, button: Button) -> bool {
+        self.turbo & button.mask() != 0
+    }
+
+    /// Sets how many frames each turbo press and each release lasts. Clamped to at least 1.
+    pub fn set_turbo_rate(&mut self, frames: u8) {
+        self.turbo_rate = frames.max(1);
+        self.turbo_frames = 0;
+    }
+
+    pub fn turbo_rate(&self) -> u8 {
+        self.turbo_rate
+    }
+
+    /// The buttons as the console sees them: the held ones, minus turbo buttons while they are
+    /// between presses.
+    pub fn output(&self) -> u8 {
+        if self.turbo_pressed {
+            self.buttons
+        } else {
+            self.buttons & !self.turbo
+        }
+    }
+}
+
+impl ControllerPort for Joypad {
+    /// Only bit 0, the strobe, reaches the controller.
+    fn write(&mut self, data: u8) {
+        // Whatever is held when the strobe drops is what gets shifted out.
+        if self.strobe {
+            self.shift = self.output();
+        }
+        self.strobe = data & 1 != 0;
+    }
+
+    /// The next button, in bit 0.
+    fn read(&mut self) -> u8 {
+        if self.strobe {
+            return self.output() & 1;
+        }
+        let bit = self.shift & 1;
+        self.shift = self.shift >> 1 | 0x80;
+        bit
+    }
+
+    fn end_frame(&mut self) {
+        self.turbo_frames += 1;
+        if self.turbo_frames >= self.turbo_rate {
+            self.turbo_frames = 0;
+            self.turbo_pressed = !self.turbo_pressed;
+        }
+    }
+
+    fn save_state(&self) -> Vec<u8> {
+        state::to_bytes(self)
+    }
+
+    fn load_state(&mut self, state: &[u8]) -> Result<()> {
+        *self = state::from_bytes(state)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_buttons_shift_out_in_order() {
+        let mut joypad = Joypad::new();
+        joypad.set_button(Button::A, true);
+        joypad.set_button(Button::Start, true);
+        joypad.set_button(Button::Left, true);
+        joypad.write(1);
+        joypad.write(0);
+        let bits: [u8; 8] = core::array::from_fn(|_| joypad.read());
+        assert_eq!(bits, [1, 0, 0, 1, 0, 0, 1, 0]);
+        // An official controller returns 1 once all eight are out.
+        assert_eq!(joypad.read(), 1);
+        assert_eq!(joypad.read(), 1)
+    }
+
+    #[test]
+    fn test_turbo() {
+        let mut joypad = Joypad::new();
+        joypad.set_turbo(Button::A, true);
+        joypad.set_turbo_rate(3);
+        joypad.set_button(Button::A, true);
+        joypad.set_button(Button::B, true);
+
+        let mut presses = [0; 12];
+        for pressed in &mut presses {
+            *pressed = joypad.output();
+            joypad.end_frame();
+        }
+        let a = Button::A.mask() | Button::B.mask();
+        let b = Button::B.mask();
+        assert_eq!(presses, [a, a, a, b, b, b, a, a, a, b, b, b]);
+
+        // Turbo only applies while held.
+        joypad.set_button(Button::A, false);
+        assert_eq!(joypad.output(), b)
+    }
+
+    #[test]
+    fn test_strobe() {
+        let mut joypad = Joypad::new();
+        joypad.write(1);
+        assert_eq!(joypad.read(), 0);
+        // While the strobe is high the buttons are latched continuously, so reads keep returning A.
+        joypad.set_button(Button::A, true);
+        assert_eq!(joypad.read(), 1);
+        assert_eq!(joypad.read(), 1);
+
+        // Once it drops, the latched state stays put.
+        joypad.write(0);
+        joypad.set_button(Button::A, false);
+        assert_eq!(joypad.read(), 1);
+        assert_eq!(joypad.read(), 0);
+        assert!(!joypad.pressed(Button::A))
+    }
+
+    #[test]
+    fn test_save_state_keeps_the_shift_register() {
+        let mut joypad = Joypad::new();
+        joypad.set_buttons(0b1010_0101);
+        joypad.write(1);
+        joypad.write(0);
+        joypad.read();
+        let state = joypad.save_state();
+
+        let mut loaded = Joypad::new();
+        loaded.load_state(&state).unwrap();
+        let bits: [u8; 7] = core::array::from_fn(|_| loaded.read());
+        assert_eq!(bits, [0, 1, 0, 0, 1, 0, 1]);
+        assert!(loaded.load_state(&state[1..]).is_err())
+    }
+}
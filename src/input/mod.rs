@@ -0,0 +1,56 @@
+//! Devices for the two controller ports.
+//!
+//! ```text
+//! $4016 write  ---- ---S  strobe, seen by both ports (bits 1-2 also go out, unused by most devices)
+//! $4016 read   ---D DDDD  port 1
+//! $4017 read   ---D DDDD  port 2
+//! ```
+//!
+//! Anything plugged into a port implements [`ControllerPort`]. The bus comes with a standard
+//! [`Joypad`] in each; light gun games want a [`Zapper`] in port 2, and four player games a
+//! [`FourScore`] in both.
+
+use alloc::vec::Vec;
+use core::any::Any;
+
+use crate::error::Result;
+use crate::ppu::Ppu;
+
+mod four_score;
+mod joypad;
+mod zapper;
+
+pub use four_score::FourScore;
+pub use joypad::{Button, Joypad, DEFAULT_TURBO_RATE};
+pub use zapper::Zapper;
+
+/// A device plugged into a controller port.
+pub trait ControllerPort: Any + Send {
+    /// A write to $4016.
+    fn write(&mut self, data: u8);
+
+    /// A read of the port's register. Only the low five bits are driven; the bus fills in the
+    /// rest.
+    fn read(&mut self) -> u8;
+
+    /// Called right before each [`read`](Self::read) so light-sensing devices can look at what the
+    /// PPU has drawn so far.
+    fn observe(&mut self, _ppu: &Ppu) {}
+
+    /// Called at the end of every emulated frame, for devices that keep time in frames.
+    fn end_frame(&mut self) {}
+
+    /// The device's state, for a save state. Devices that keep none can leave this empty.
+    fn save_state(&self) -> Vec<u8> {
+        Vec::new()
+    }
+
+    /// Restores the state from [`save_state`](Self::save_state).
+    ///
+    /// # Errors
+    /// Returns [`NesError::InvalidState`](crate::NesError::InvalidState) if `state` wasn't saved
+    /// by this kind of device.
+    fn load_state(&mut self, _state: &[u8]) -> Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,195 @@
+//! The Zapper light gun.
+//!
+//! ```text
+//! ---T L---  T: trigger pulled, L: no light seen (0 while the sensor is lit)
+//! ```
+//!
+//! The gun has no picture of its own. Its photodiode lights up when the CRT beam sweeps past the
+//! spot it is aimed at while drawing something bright, and stays lit for a while after; games
+//! find out where the gun is pointed by flashing targets and polling the sensor during the frame.
+//! Here the sensor looks at the pixels the PPU has drawn around the aimed spot and counts them only
+//! while the beam is close enough behind them.
+
+use alloc::vec::Vec;
+
+use serde::{Deserialize, Serialize};
+
+use super::ControllerPort;
+use crate::error::Result;
+use crate::ppu::{Ppu, HEIGHT, WIDTH};
+use crate::state;
+
+/// How far from the aimed spot, in pixels, the sensor can see.
+const RADIUS: u16 = 2;
+
+/// Scanlines the sensor stays lit after the beam has drawn something bright in its view. The real
+/// figure varies between guns and TVs; this is about the middle of it.
+const LIGHT_SCANLINES: u16 = 20;
+
+/// How bright a pixel has to be to light the sensor, in luma out of 255.
+const LIGHT_THRESHOLD: u32 = 0x80;
+
+const LIGHT_OFF: u8 = 0x08;
+const TRIGGER: u8 = 0x10;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Zapper {
+    /// Where the gun is pointed, in screen pixels. `None` when it points away from the screen.
+    aim: Option<(u16, u16)>,
+    trigger: bool,
+    light: bool,
+}
+
+impl Zapper {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Points the gun at pixel (`x`, `y`) of the picture, or off the screen with `None`.
+    pub fn aim(&mut self, aim: Option<(u16, u16)>) {
+        self.aim = aim.filter(|&(x, y)| usize::from(x) < WIDTH && usize::from(y) < HEIGHT);
+    }
+
+    pub fn set_trigger(&mut self, pulled: bool) {
+        self.trigger = pulled;
+    }
+
+    /// Whether any bright pixel around the aimed spot was drawn recently enough to still light the
+    /// sensor.
+    fn sees_light(&self, ppu: &Ppu) -> bool {
+        let Some((x, y)) = self.aim else {
+            return false;
+        };
+        let (scanline, dot) = (ppu.scanline(), ppu.dot());
+        let frame = ppu.frame();
+        let rows = y.saturating_sub(RADIUS)..=(y + RADIUS).min(HEIGHT as u16 - 1);
+        let columns = x.saturating_sub(RADIUS)..=(x + RADIUS).min(WIDTH as u16 - 1);
+        rows.flat_map(|row| columns.clone().map(move |column| (column, row)))
+            .filter(|&(column, row)| {
+                // Pixel x is drawn at dot x + 1.
+                let drawn = scanline > row || scanline == row && dot > column + 1;
+                drawn && scanline - row <= LIGHT_SCANLINES
+            })
+            .any(|(column, row)| {
+                let index = (usize::from(row) * WIDTH + usize::from(column)) * 4;
+                let [r, g, b] = [frame[index], frame[index + 1], frame[index + 2]].map(u32::from);
+                (r * 299 + g * 587 + b * 114) / 1000 >= LIGHT_THRESHOLD
+            })
+    }
+}
+
+impl ControllerPort for Zapper {
+    /// The Zapper has nothing to strobe.
+    fn write(&mut self, _data: u8) {}
+
+    fn read(&mut self) -> u8 {
+        let mut data = 0;
+        if !self.light {
+            data |= LIGHT_OFF;
+        }
+        if self.trigger {
+            data |= TRIGGER;
+        }
+        data
+    }
+
+    fn observe(&mut self, ppu: &Ppu) {
+        self.light = self.sees_light(ppu);
+    }
+
+    fn save_state(&self) -> Vec<u8> {
+        state::to_bytes(self)
+    }
+
+    fn load_state(&mut self, state: &[u8]) -> Result<()> {
+        let loaded: Zapper = state::from_bytes(state)?;
+        *self = Zapper {
+            aim: None,
+            ..loaded
+        };
+        // An aim off the picture means pointing away from the screen, as it does for `aim`.
+        self.aim(loaded.aim);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::{test_image, Cartridge};
+    use crate::mapper::Nrom;
+    use crate::ppu::{PPUADDR, PPUDATA};
+
+    /// A PPU with rendering off, so it draws the backdrop everywhere, set to white.
+    fn white_screen() -> (Ppu, Nrom) {
+        let mut ppu = Ppu::new();
+        let mut mapper = Nrom::new(Cartridge::new(&test_image(0, 1, 0, 0)).unwrap());
+        ppu.write_register(PPUADDR, 0x3F, &mut mapper);
+        ppu.write_register(PPUADDR, 0x00, &mut mapper);
+        ppu.write_register(PPUDATA, 0x30, &mut mapper);
+        (ppu, mapper)
+    }
+
+    fn run_to(ppu: &mut Ppu, mapper: &mut Nrom, scanline: u16, dot: u16) {
+        while (ppu.scanline(), ppu.dot()) != (scanline, dot) {
+            ppu.tick(mapper);
+        }
+    }
+
+    #[test]
+    fn test_light_follows_the_beam() {
+        let (mut ppu, mut mapper) = white_screen();
+        let mut zapper = Zapper::new();
+        zapper.aim(Some((100, 50)));
+
+        run_to(&mut ppu, &mut mapper, 40, 0);
+        zapper.observe(&ppu);
+        assert_eq!(zapper.read(), LIGHT_OFF);
+
+        // Lit once the beam reaches the spot...
+        run_to(&mut ppu, &mut mapper, 48, 100);
+        zapper.observe(&ppu);
+        assert_eq!(zapper.read(), 0);
+        // ...and for a while after.
+        run_to(&mut ppu, &mut mapper, 50 + LIGHT_SCANLINES + RADIUS, 0);
+        zapper.observe(&ppu);
+        assert_eq!(zapper.read(), 0);
+        run_to(&mut ppu, &mut mapper, 50 + LIGHT_SCANLINES + RADIUS + 1, 0);
+        zapper.observe(&ppu);
+        assert_eq!(zapper.read(), LIGHT_OFF)
+    }
+
+    #[test]
+    fn test_dark_screen_and_trigger() {
+        let (mut ppu, mut mapper) = white_screen();
+        ppu.write_register(PPUADDR, 0x3F, &mut mapper);
+        ppu.write_register(PPUADDR, 0x00, &mut mapper);
+        ppu.write_register(PPUDATA, 0x0F, &mut mapper);
+        let mut zapper = Zapper::new();
+        zapper.aim(Some((100, 50)));
+        zapper.set_trigger(true);
+
+        run_to(&mut ppu, &mut mapper, 55, 0);
+        zapper.observe(&ppu);
+        assert_eq!(zapper.read(), TRIGGER | LIGHT_OFF);
+
+        // Pointing away from the screen never sees anything.
+        zapper.aim(Some((300, 50)));
+        zapper.observe(&ppu);
+        assert_eq!(zapper.read(), TRIGGER | LIGHT_OFF)
+    }
+
+    #[test]
+    fn test_off_screen_aim_in_a_state_is_dropped() {
+        let (ppu, _) = white_screen();
+        let bad = Zapper {
+            aim: Some((u16::MAX, u16::MAX)),
+            ..Zapper::new()
+        };
+        let mut zapper = Zapper::new();
+        zapper.load_state(&bad.save_state()).unwrap();
+        assert_eq!(zapper.aim, None);
+        zapper.observe(&ppu);
+        assert!(!zapper.light)
+    }
+}
@@ -0,0 +1,36 @@
+//! NES emulation core.
+//!
+//! The core is `#![no_std]` and only depends on `alloc`, so it can be ported to embedded and other
+//! exotic targets. Anything that needs the host (file I/O, threads, audio, frontends) is gated
+//! behind the `std` feature or lives in the binary.
+#![no_std]
+
+extern crate alloc;
+#[cfg(feature = "std")]
+extern crate std;
+
+#[macro_use]
+mod logging;
+
+pub mod apu;
+pub mod bus;
+pub mod cartridge;
+pub mod cheats;
+pub mod clock;
+pub mod cpu;
+pub mod error;
+pub mod input;
+pub mod mapper;
+pub mod mem;
+pub mod movie;
+pub mod nes;
+pub mod opcodes;
+pub mod palette;
+pub mod ppu;
+pub mod region;
+pub mod rewind;
+pub mod slots;
+pub mod state;
+
+pub use error::{NesError, Result};
+pub use nes::{Nes, NesBuilder};
@@ -0,0 +1,46 @@
+//! Internal logging macros.
+//!
+//! With the `log` feature enabled these forward to the [`log`](https://docs.rs/log) facade. Without
+//! it they expand to dead code: the arguments are still type checked but never evaluated, so the
+//! instrumentation costs nothing.
+
+#[cfg(feature = "log")]
+macro_rules! emit {
+    ($level:ident, $($arg:tt)+) => {
+        ::log::$level!(target: "nes", $($arg)+)
+    };
+}
+
+#[cfg(not(feature = "log"))]
+macro_rules! emit {
+    ($level:ident, $($arg:tt)+) => {
+        if false {
+            let _ = ::core::format_args!($($arg)+);
+        }
+    };
+}
+
+#[allow(unused_macros)]
+macro_rules! error {
+    ($($arg:tt)+) => { emit!(error, $($arg)+) };
+}
+
+#[allow(unused_macros)]
+macro_rules! warn {
+    ($($arg:tt)+) => { emit!(warn, $($arg)+) };
+}
+
+#[allow(unused_macros)]
+macro_rules! info {
+    ($($arg:tt)+) => { emit!(info, $($arg)+) };
+}
+
+#[allow(unused_macros)]
+macro_rules! debug {
+    ($($arg:tt)+) => { emit!(debug, $($arg)+) };
+}
+
+#[allow(unused_macros)]
+macro_rules! trace {
+    ($($arg:tt)+) => { emit!(trace, $($arg)+) };
+}
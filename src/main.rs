@@ -1,5 +1,26 @@
-pub mod cpu;
+use std::env;
+use std::process::ExitCode;
 
-fn main() {
-    println!("Hello, world!");
+use nes_emulator::cartridge::Cartridge;
+
+const USAGE: &str = "usage: nes-emulator info <rom>";
+
+fn main() -> ExitCode {
+    let args: Vec<String> = env::args().skip(1).collect();
+    match args.as_slice() {
+        [command, rom] if command == "info" => match Cartridge::from_file(rom) {
+            Ok(cart) => {
+                println!("{}", cart.info());
+                ExitCode::SUCCESS
+            }
+            Err(err) => {
+                eprintln!("{rom}: {err}");
+                ExitCode::FAILURE
+            }
+        },
+        _ => {
+            eprintln!("{USAGE}");
+            ExitCode::FAILURE
+        }
+    }
 }
@@ -0,0 +1,105 @@
+use alloc::vec::Vec;
+
+use serde::{Deserialize, Serialize};
+
+use crate::cartridge::{Cartridge, Mirroring};
+use crate::error::Result;
+use crate::mapper::{banked, load_board, Mapper};
+use crate::state;
+
+const PRG_BANK_SIZE: usize = 0x8000;
+
+/// Mapper 7: AxROM. Writes to $8000-$FFFF select a 32KB PRG bank (bits 0-2) and which 1KB of VRAM
+/// every nametable shows (bit 4). CHR is 8KB of RAM.
+#[derive(Serialize, Deserialize)]
+pub struct Axrom {
+    #[serde(skip)]
+    cart: Cartridge,
+    bank: u8,
+}
+
+impl Axrom {
+    pub fn new(cart: Cartridge) -> Self {
+        Self { cart, bank: 0 }
+    }
+}
+
+impl Mapper for Axrom {
+    fn cartridge(&self) -> &Cartridge {
+        &self.cart
+    }
+
+    fn cartridge_mut(&mut self) -> &mut Cartridge {
+        &mut self.cart
+    }
+
+    fn cpu_read(&mut self, addr: u16) -> Option<u8> {
+        match addr {
+            0x8000..=0xFFFF => {
+                let bank = usize::from(self.bank & 0b111);
+                Some(self.cart.prg_rom[banked(&self.cart.prg_rom, PRG_BANK_SIZE, bank, addr)])
+            }
+            _ => None,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x8000..=0xFFFF => self.bank = data,
+            _ => trace!("write {data:02X} to AxROM {addr:04X} ignored"),
+        }
+    }
+
+    fn ppu_read(&mut self, addr: u16) -> u8 {
+        self.cart.chr_rom[usize::from(addr & 0x1FFF)]
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        if self.cart.chr_ram {
+            self.cart.chr_rom[usize::from(addr & 0x1FFF)] = data;
+        }
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        if self.bank & 0b1_0000 == 0 {
+            Mirroring::SingleScreenLower
+        } else {
+            Mirroring::SingleScreenUpper
+        }
+    }
+
+    fn save_state(&self) -> Vec<u8> {
+        state::to_bytes(self)
+    }
+
+    fn load_state(&mut self, state: &[u8]) -> Result<()> {
+        load_board(self, state)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::test_image;
+
+    #[test]
+    fn test_32k_banks_and_single_screen_select() {
+        let mut axrom = Axrom::new(Cartridge::new(&test_image(7, 8, 0, 0)).unwrap());
+        assert_eq!(axrom.mirroring(), Mirroring::SingleScreenLower);
+
+        axrom.cpu_write(0x8000, 0b1_0010);
+        assert_eq!(axrom.cpu_read(0x8000), Some(4));
+        assert_eq!(axrom.cpu_read(0xC000), Some(5));
+        assert_eq!(axrom.mirroring(), Mirroring::SingleScreenUpper)
+    }
+
+    #[test]
+    fn test_16k_prg_is_mirrored() {
+        let mut raw = test_image(7, 1, 0, 0);
+        raw[16 + 0x3FFF] = 0x42;
+        let mut axrom = Axrom::new(Cartridge::new(&raw).unwrap());
+
+        assert_eq!(axrom.cpu_read(0xBFFF), Some(0x42));
+        assert_eq!(axrom.cpu_read(0xFFFF), Some(0x42))
+    }
+}
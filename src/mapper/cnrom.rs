@@ -0,0 +1,108 @@
+use alloc::vec::Vec;
+
+use serde::{Deserialize, Serialize};
+
+use crate::cartridge::{Cartridge, Mirroring};
+use crate::error::Result;
+use crate::mapper::{banked, load_board, Mapper};
+use crate::state;
+
+const CHR_BANK_SIZE: usize = 0x2000;
+
+/// Mapper 3: CNROM. PRG is laid out like NROM; writes to $8000-$FFFF select the 8KB CHR bank.
+///
+/// The board doesn't disable the ROM while the CPU writes, so both drive the data bus at once and
+/// the register sees the AND of the two. Games avoid this by writing a value that matches the ROM
+/// byte at the target address.
+#[derive(Serialize, Deserialize)]
+pub struct Cnrom {
+    #[serde(skip)]
+    cart: Cartridge,
+    chr_bank: u8,
+}
+
+impl Cnrom {
+    pub fn new(cart: Cartridge) -> Self {
+        Self { cart, chr_bank: 0 }
+    }
+
+    fn prg_read(&self, addr: u16) -> u8 {
+        self.cart.prg_rom[usize::from(addr - 0x8000) % self.cart.prg_rom.len()]
+    }
+}
+
+impl Mapper for Cnrom {
+    fn cartridge(&self) -> &Cartridge {
+        &self.cart
+    }
+
+    fn cartridge_mut(&mut self) -> &mut Cartridge {
+        &mut self.cart
+    }
+
+    fn cpu_read(&mut self, addr: u16) -> Option<u8> {
+        match addr {
+            0x8000..=0xFFFF => Some(self.prg_read(addr)),
+            _ => None,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x8000..=0xFFFF => self.chr_bank = data & self.prg_read(addr),
+            _ => trace!("write {data:02X} to CNROM {addr:04X} ignored"),
+        }
+    }
+
+    fn ppu_read(&mut self, addr: u16) -> u8 {
+        let bank = usize::from(self.chr_bank);
+        self.cart.chr_rom[banked(&self.cart.chr_rom, CHR_BANK_SIZE, bank, addr)]
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        trace!("write {data:02X} to CNROM CHR ROM {addr:04X} ignored");
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.cart.mirroring
+    }
+
+    fn save_state(&self) -> Vec<u8> {
+        state::to_bytes(self)
+    }
+
+    fn load_state(&mut self, state: &[u8]) -> Result<()> {
+        load_board(self, state)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::test_image;
+
+    fn cnrom() -> Cnrom {
+        let mut cnrom = Cnrom::new(Cartridge::new(&test_image(3, 2, 4, 0)).unwrap());
+        cnrom.cart.prg_rom[0x0000] = 0xFF;
+        cnrom.cart.prg_rom[0x0001] = 0x01;
+        cnrom
+    }
+
+    #[test]
+    fn test_chr_bank_switching() {
+        let mut cnrom = cnrom();
+        assert_eq!(cnrom.ppu_read(0x0000), 0x80);
+
+        cnrom.cpu_write(0x8000, 2);
+        assert_eq!(cnrom.ppu_read(0x1FFF), 0x82)
+    }
+
+    #[test]
+    fn test_bus_conflicts() {
+        let mut cnrom = cnrom();
+        // The ROM drives $01 at $8001, so bit 1 of the write is lost.
+        cnrom.cpu_write(0x8001, 3);
+
+        assert_eq!(cnrom.ppu_read(0x0000), 0x81)
+    }
+}
@@ -0,0 +1,263 @@
+use alloc::vec::Vec;
+
+use serde::{Deserialize, Serialize};
+
+use crate::cartridge::{Cartridge, Mirroring};
+use crate::error::Result;
+use crate::mapper::{banked, load_board, Mapper};
+use crate::state;
+
+const PRG_BANK_SIZE: usize = 0x4000;
+const CHR_BANK_SIZE: usize = 0x1000;
+/// Boards with 512KB of PRG (SUROM) use CHR bank bit 4 to pick which 256KB half is visible.
+const PRG_OUTER_BANK_SIZE: usize = 0x40000;
+
+/// Mapper 1: Nintendo's MMC1 (SxROM boards).
+///
+/// Registers are loaded serially: five writes to $8000-$FFFF each shift in bit 0, and the fifth
+/// write's address picks the register. Writing a value with bit 7 set resets the shift register.
+///
+/// ```text
+/// $8000-$9FFF  control: CPPMM, C = CHR mode, P = PRG mode, M = mirroring
+/// $A000-$BFFF  CHR bank 0
+/// $C000-$DFFF  CHR bank 1
+/// $E000-$FFFF  PRG bank, bit 4 disables PRG RAM
+/// ```
+#[derive(Serialize, Deserialize)]
+pub struct Mmc1 {
+    #[serde(skip)]
+    cart: Cartridge,
+    /// Shift register with a marker bit; the write that pushes the marker out is the fifth.
+    shift: u8,
+    control: u8,
+    chr_bank0: u8,
+    chr_bank1: u8,
+    prg_bank: u8,
+}
+
+impl Mmc1 {
+    const SHIFT_RESET: u8 = 0b1_0000;
+
+    pub fn new(cart: Cartridge) -> Self {
+        Self {
+            cart,
+            shift: Self::SHIFT_RESET,
+            // Powers up with the last PRG bank fixed at $C000, so the reset vector is reachable.
+            control: 0b0_1100,
+            chr_bank0: 0,
+            chr_bank1: 0,
+            prg_bank: 0,
+        }
+    }
+
+    fn write_register(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x8000..=0x9FFF => self.control = data,
+            0xA000..=0xBFFF => self.chr_bank0 = data,
+            0xC000..=0xDFFF => self.chr_bank1 = data,
+            _ => self.prg_bank = data,
+        }
+    }
+
+    fn prg_ram_enabled(&self) -> bool {
+        self.prg_bank & 0b1_0000 == 0
+    }
+
+    fn prg_offset(&self, addr: u16) -> usize {
+        let banks_per_half = PRG_OUTER_BANK_SIZE / PRG_BANK_SIZE;
+        let outer =
+            if self.cart.prg_rom.len() > PRG_OUTER_BANK_SIZE && self.chr_bank0 & 0b1_0000 != 0 {
+                banks_per_half
+            } else {
+                0
+            };
+        let last = (self.cart.prg_rom.len() / PRG_BANK_SIZE).min(banks_per_half) - 1;
+        let bank = usize::from(self.prg_bank & 0x0F);
+
+        let bank = match ((self.control >> 2) & 0b11, addr) {
+            // 32KB mode ignores the low bank bit.
+            (0 | 1, 0x8000..=0xBFFF) => bank & !1,
+            (0 | 1, _) => bank | 1,
+            (2, 0x8000..=0xBFFF) => 0,
+            (2, _) => bank,
+            (_, 0x8000..=0xBFFF) => bank,
+            (_, _) => last,
+        };
+        banked(&self.cart.prg_rom, PRG_BANK_SIZE, outer + bank, addr)
+    }
+
+    fn chr_offset(&self, addr: u16) -> usize {
+        let bank = if self.control & 0b1_0000 == 0 {
+            // 8KB mode ignores the low bank bit.
+            usize::from(self.chr_bank0 & !1) | usize::from(addr >> 12)
+        } else if addr < 0x1000 {
+            usize::from(self.chr_bank0)
+        } else {
+            usize::from(self.chr_bank1)
+        };
+        banked(&self.cart.chr_rom, CHR_BANK_SIZE, bank, addr)
+    }
+}
+
+impl Mapper for Mmc1 {
+    fn cartridge(&self) -> &Cartridge {
+        &self.cart
+    }
+
+    fn cartridge_mut(&mut self) -> &mut Cartridge {
+        &mut self.cart
+    }
+
+    fn cpu_read(&mut self, addr: u16) -> Option<u8> {
+        match addr {
+            0x6000..=0x7FFF if self.prg_ram_enabled() => {
+                Some(self.cart.prg_ram[usize::from(addr - 0x6000)])
+            }
+            0x8000..=0xFFFF => Some(self.cart.prg_rom[self.prg_offset(addr)]),
+            _ => None,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x6000..=0x7FFF if self.prg_ram_enabled() => {
+                self.cart.prg_ram[usize::from(addr - 0x6000)] = data
+            }
+            0x8000..=0xFFFF if data & 0x80 != 0 => {
+                self.shift = Self::SHIFT_RESET;
+                self.control |= 0b0_1100;
+            }
+            0x8000..=0xFFFF => {
+                let full = self.shift & 1 != 0;
+                self.shift = (self.shift >> 1) | ((data & 1) << 4);
+                if full {
+                    self.write_register(addr, self.shift);
+                    self.shift = Self::SHIFT_RESET;
+                }
+            }
+            _ => trace!("write {data:02X} to MMC1 {addr:04X} ignored"),
+        }
+    }
+
+    fn ppu_read(&mut self, addr: u16) -> u8 {
+        self.cart.chr_rom[self.chr_offset(addr)]
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        if self.cart.chr_ram {
+            let offset = self.chr_offset(addr);
+            self.cart.chr_rom[offset] = data;
+        }
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        match self.control & 0b11 {
+            0 => Mirroring::SingleScreenLower,
+            1 => Mirroring::SingleScreenUpper,
+            2 => Mirroring::Vertical,
+            _ => Mirroring::Horizontal,
+        }
+    }
+
+    fn save_state(&self) -> Vec<u8> {
+        state::to_bytes(self)
+    }
+
+    fn load_state(&mut self, state: &[u8]) -> Result<()> {
+        load_board(self, state)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::test_image;
+
+    fn mmc1(prg_banks: u8, chr_banks: u8) -> Mmc1 {
+        Mmc1::new(Cartridge::new(&test_image(1, prg_banks, chr_banks, 0)).unwrap())
+    }
+
+    /// Loads `value` into the register at `addr` the way games do: five single-bit writes.
+    fn load(mmc1: &mut Mmc1, addr: u16, value: u8) {
+        for bit in 0..5 {
+            mmc1.cpu_write(addr, value >> bit);
+        }
+    }
+
+    #[test]
+    fn test_power_on_fixes_last_bank() {
+        let mut mmc1 = mmc1(8, 1);
+
+        assert_eq!(mmc1.cpu_read(0x8000), Some(0));
+        assert_eq!(mmc1.cpu_read(0xFFFC), Some(7))
+    }
+
+    #[test]
+    fn test_serial_writes_and_reset() {
+        let mut mmc1 = mmc1(8, 1);
+        load(&mut mmc1, 0xE000, 3);
+        assert_eq!(mmc1.cpu_read(0x8000), Some(3));
+
+        // A reset halfway through discards the partial value.
+        mmc1.cpu_write(0xE000, 1);
+        mmc1.cpu_write(0xE000, 1);
+        mmc1.cpu_write(0xE000, 0x80);
+        load(&mut mmc1, 0xE000, 5);
+        assert_eq!(mmc1.cpu_read(0x8000), Some(5))
+    }
+
+    #[test]
+    fn test_prg_modes() {
+        let mut mmc1 = mmc1(8, 1);
+        load(&mut mmc1, 0xE000, 5);
+
+        // 32KB mode: $8000 gets the even bank, $C000 the odd one.
+        load(&mut mmc1, 0x8000, 0b0_0000);
+        assert_eq!(mmc1.cpu_read(0x8000), Some(4));
+        assert_eq!(mmc1.cpu_read(0xC000), Some(5));
+
+        // First bank fixed at $8000.
+        load(&mut mmc1, 0x8000, 0b0_1000);
+        assert_eq!(mmc1.cpu_read(0x8000), Some(0));
+        assert_eq!(mmc1.cpu_read(0xC000), Some(5))
+    }
+
+    #[test]
+    fn test_chr_modes() {
+        let mut mmc1 = mmc1(2, 4);
+        load(&mut mmc1, 0xA000, 3);
+        load(&mut mmc1, 0xC000, 6);
+
+        // 8KB mode uses CHR bank 0 without its low bit.
+        assert_eq!(mmc1.ppu_read(0x0000), 0x81);
+        assert_eq!(mmc1.ppu_read(0x1000), 0x81);
+
+        load(&mut mmc1, 0x8000, 0b1_1100);
+        assert_eq!(mmc1.ppu_read(0x0000), 0x81);
+        assert_eq!(mmc1.ppu_read(0x1000), 0x83)
+    }
+
+    #[test]
+    fn test_mirroring_control() {
+        let mut mmc1 = mmc1(2, 1);
+        for (bits, mirroring) in [
+            (0, Mirroring::SingleScreenLower),
+            (1, Mirroring::SingleScreenUpper),
+            (2, Mirroring::Vertical),
+            (3, Mirroring::Horizontal),
+        ] {
+            load(&mut mmc1, 0x8000, 0b0_1100 | bits);
+            assert_eq!(mmc1.mirroring(), mirroring);
+        }
+    }
+
+    #[test]
+    fn test_prg_ram_enable() {
+        let mut mmc1 = mmc1(2, 1);
+        mmc1.cpu_write(0x6000, 0x12);
+        assert_eq!(mmc1.cpu_read(0x6000), Some(0x12));
+
+        load(&mut mmc1, 0xE000, 0b1_0000);
+        assert_eq!(mmc1.cpu_read(0x6000), None)
+    }
+}
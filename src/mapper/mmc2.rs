@@ -0,0 +1,193 @@
+use alloc::vec::Vec;
+
+use serde::{Deserialize, Serialize};
+
+use crate::cartridge::{Cartridge, Mirroring};
+use crate::error::{NesError, Result};
+use crate::mapper::{banked, load_board, Mapper};
+use crate::state;
+
+const PRG_BANK_SIZE: usize = 0x2000;
+const CHR_BANK_SIZE: usize = 0x1000;
+
+/// Mapper 9: MMC2 (PxROM), made for Punch-Out!!.
+///
+/// ```text
+/// $A000-$AFFF  8KB PRG bank at $8000; the last three banks are fixed at $A000-$FFFF
+/// $B000-$BFFF  CHR bank for $0000 while latch 0 holds $FD
+/// $C000-$CFFF  CHR bank for $0000 while latch 0 holds $FE
+/// $D000-$DFFF  CHR bank for $1000 while latch 1 holds $FD
+/// $E000-$EFFF  CHR bank for $1000 while latch 1 holds $FE
+/// $F000-$FFFF  mirroring, 0 = vertical
+/// ```
+///
+/// Each latch flips once the PPU has fetched tile $FD or $FE from its pattern table, so a game can
+/// swap graphics mid-screen just by placing those tiles.
+#[derive(Serialize, Deserialize)]
+pub struct Mmc2 {
+    #[serde(skip)]
+    cart: Cartridge,
+    prg_bank: u8,
+    /// CHR banks indexed by `[pattern table][latch]`, latch 0 meaning $FD and 1 meaning $FE.
+    chr_banks: [[u8; 2]; 2],
+    latches: [usize; 2],
+    mirroring: Mirroring,
+}
+
+impl Mmc2 {
+    pub fn new(cart: Cartridge) -> Self {
+        Self {
+            mirroring: cart.mirroring,
+            cart,
+            prg_bank: 0,
+            chr_banks: [[0; 2]; 2],
+            latches: [1; 2],
+        }
+    }
+}
+
+impl Mapper for Mmc2 {
+    fn cartridge(&self) -> &Cartridge {
+        &self.cart
+    }
+
+    fn cartridge_mut(&mut self) -> &mut Cartridge {
+        &mut self.cart
+    }
+
+    fn cpu_read(&mut self, addr: u16) -> Option<u8> {
+        let banks = self.cart.prg_rom.len() / PRG_BANK_SIZE;
+        let bank = match addr {
+            0x8000..=0x9FFF => usize::from(self.prg_bank),
+            0xA000..=0xFFFF => banks.max(4) - 4 + usize::from((addr - 0x8000) >> 13),
+            _ => return None,
+        };
+        Some(self.cart.prg_rom[banked(&self.cart.prg_rom, PRG_BANK_SIZE, bank, addr)])
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        let data = data & 0x1F;
+        match addr {
+            0xA000..=0xAFFF => self.prg_bank = data & 0x0F,
+            0xB000..=0xBFFF => self.chr_banks[0][0] = data,
+            0xC000..=0xCFFF => self.chr_banks[0][1] = data,
+            0xD000..=0xDFFF => self.chr_banks[1][0] = data,
+            0xE000..=0xEFFF => self.chr_banks[1][1] = data,
+            0xF000..=0xFFFF => {
+                self.mirroring = if data & 1 == 0 {
+                    Mirroring::Vertical
+                } else {
+                    Mirroring::Horizontal
+                }
+            }
+            _ => trace!("write {data:02X} to MMC2 {addr:04X} ignored"),
+        }
+    }
+
+    fn ppu_read(&mut self, addr: u16) -> u8 {
+        let table = usize::from(addr >> 12) & 1;
+        let bank = usize::from(self.chr_banks[table][self.latches[table]]);
+        self.cart.chr_rom[banked(&self.cart.chr_rom, CHR_BANK_SIZE, bank, addr)]
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        trace!("write {data:02X} to MMC2 CHR ROM {addr:04X} ignored");
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn ppu_access(&mut self, addr: u16) {
+        // Latch 0 only reacts to the exact addresses; latch 1 to the whole eight-byte row.
+        match addr {
+            0x0FD8 => self.latches[0] = 0,
+            0x0FE8 => self.latches[0] = 1,
+            0x1FD8..=0x1FDF => self.latches[1] = 0,
+            0x1FE8..=0x1FEF => self.latches[1] = 1,
+            _ => {}
+        }
+    }
+
+    fn save_state(&self) -> Vec<u8> {
+        state::to_bytes(self)
+    }
+
+    fn load_state(&mut self, state: &[u8]) -> Result<()> {
+        load_board(self, state)?;
+        if self.latches.iter().any(|&latch| latch > 1) {
+            return Err(NesError::InvalidState(
+                "save state's MMC2 latches are out of range".into(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::test_image;
+
+    fn mmc2() -> Mmc2 {
+        // 128KB of PRG as 8KB banks: 16 banks, filled 0, 0, 1, 1, ... by the 16KB test image.
+        let mut mmc2 = Mmc2::new(Cartridge::new(&test_image(9, 8, 8, 0)).unwrap());
+        mmc2.cpu_write(0xB000, 2);
+        mmc2.cpu_write(0xC000, 4);
+        mmc2.cpu_write(0xD000, 6);
+        mmc2.cpu_write(0xE000, 8);
+        mmc2
+    }
+
+    #[test]
+    fn test_prg_banks() {
+        let mut mmc2 = mmc2();
+        mmc2.cpu_write(0xA000, 5);
+
+        assert_eq!(mmc2.cpu_read(0x8000), Some(2));
+        assert_eq!(mmc2.cpu_read(0xA000), Some(6));
+        assert_eq!(mmc2.cpu_read(0xE000), Some(7))
+    }
+
+    #[test]
+    fn test_bad_latches_are_rejected() {
+        let mut mmc2 = mmc2();
+        mmc2.latches[1] = 2;
+        let state = mmc2.save_state();
+        assert!(matches!(
+            mmc2.load_state(&state),
+            Err(NesError::InvalidState(_))
+        ))
+    }
+
+    #[test]
+    fn test_16k_prg_wraps_the_fixed_banks() {
+        let mut raw = test_image(9, 1, 1, 0);
+        raw[16 + 0x3FFF] = 0x42;
+        let mut mmc2 = Mmc2::new(Cartridge::new(&raw).unwrap());
+
+        assert_eq!(mmc2.cpu_read(0xA000), Some(0));
+        assert_eq!(mmc2.cpu_read(0xBFFF), Some(0x42));
+        assert_eq!(mmc2.cpu_read(0xFFFF), Some(0x42))
+    }
+
+    #[test]
+    fn test_chr_latches_switch_after_the_fetch() {
+        let mut mmc2 = mmc2();
+        // Both latches power up on $FE. Each 8KB test CHR bank spans two 4KB banks.
+        assert_eq!(mmc2.ppu_read(0x0000), 0x82);
+        assert_eq!(mmc2.ppu_read(0x1000), 0x84);
+
+        // Fetching tile $FD from the first table flips latch 0, but that fetch used the old bank.
+        assert_eq!(mmc2.ppu_read(0x0FD8), 0x82);
+        mmc2.ppu_access(0x0FD8);
+        assert_eq!(mmc2.ppu_read(0x0000), 0x81);
+        assert_eq!(mmc2.ppu_read(0x1000), 0x84);
+
+        mmc2.ppu_access(0x1FDD);
+        assert_eq!(mmc2.ppu_read(0x1000), 0x83);
+
+        mmc2.ppu_access(0x0FE8);
+        assert_eq!(mmc2.ppu_read(0x0000), 0x82)
+    }
+}
@@ -0,0 +1,590 @@
+use alloc::vec::Vec;
+
+use serde::{Deserialize, Serialize};
+
+use crate::cartridge::{Cartridge, Mirroring};
+use crate::error::Result;
+use crate::mapper::{banked, load_board, Mapper};
+use crate::state;
+
+const PRG_BANK_SIZE: usize = 0x2000;
+const CHR_BANK_SIZE: usize = 0x0400;
+const EXRAM_SIZE: usize = 0x0400;
+
+/// PPU reads per rendered scanline: 32 background tiles, 8 sprites and 2 prefetched tiles at four
+/// reads each, plus the two dummy nametable reads at the end of the line.
+const SPRITE_FETCHES: core::ops::Range<u16> = 128..160;
+const PREFETCH_START: u16 = 160;
+const PREFETCH_END: u16 = 168;
+
+/// Mapper 5: Nintendo's MMC5 (ExROM boards).
+///
+/// ```text
+/// $5100        PRG mode: 32KB, 16KB+16KB, 16KB+8KB+8KB or four 8KB banks
+/// $5101        CHR mode: 8KB, 4KB, 2KB or 1KB banks
+/// $5102/$5103  PRG RAM write protect, writable only with $02 and $01
+/// $5104        ExRAM mode: extra nametable, extended attributes, CPU RAM, CPU ROM
+/// $5105        nametable mapping, two bits per nametable: CIRAM A, CIRAM B, ExRAM, fill
+/// $5106/$5107  fill-mode tile and attribute
+/// $5113-$5117  PRG banks; bit 7 picks ROM over PRG RAM, $5113 is the $6000 RAM bank
+/// $5120-$5127  CHR set A: sprites in 8x16 mode, everything in 8x8 mode
+/// $5128-$512B  CHR set B: background in 8x16 mode
+/// $5130        upper CHR bank bits
+/// $5200-$5202  vertical split: control, scroll, CHR bank
+/// $5203/$5204  scanline IRQ compare, and IRQ enable / status
+/// $5205/$5206  8x8 -> 16 bit multiplier
+/// $5C00-$5FFF  1KB ExRAM
+/// ```
+///
+/// The MMC5 has no direct line to the PPU's timing. It follows it the way the hardware does: it
+/// spots the three identical nametable reads at the end of every rendered scanline, counts PPU reads
+/// from there to tell sprite fetches from background ones, and snoops PPUCTRL and PPUMASK writes.
+#[derive(Serialize, Deserialize)]
+pub struct Mmc5 {
+    #[serde(skip)]
+    cart: Cartridge,
+    prg_mode: u8,
+    chr_mode: u8,
+    prg_ram_protect: [u8; 2],
+    exram_mode: u8,
+    #[serde(with = "state::array")]
+    exram: [u8; EXRAM_SIZE],
+    nametable_mapping: u8,
+    fill_tile: u8,
+    fill_attribute: u8,
+    /// $5113-$5117.
+    prg_banks: [u8; 5],
+    chr_a: [u16; 8],
+    chr_b: [u16; 4],
+    chr_upper: u8,
+    /// Whether set B was written last. Used whenever the PPU isn't rendering.
+    chr_b_last: bool,
+    split_control: u8,
+    split_scroll: u8,
+    split_bank: u8,
+    irq_compare: u8,
+    irq_enabled: bool,
+    irq_pending: bool,
+    multiplicand: u8,
+    multiplier: u8,
+
+    large_sprites: bool,
+    in_frame: bool,
+    scanline: u8,
+    last_nametable_addr: u16,
+    nametable_repeats: u8,
+    /// PPU reads since the current scanline was detected.
+    fetches: u16,
+    /// ExRAM byte for the background tile being fetched, in extended attribute mode.
+    tile_exram: u8,
+    /// Split-region scanline of the background tile being fetched, if it lies in the split.
+    tile_split: Option<u16>,
+}
+
+impl Mmc5 {
+    pub fn new(cart: Cartridge) -> Self {
+        Self {
+            cart,
+            prg_mode: 3,
+            chr_mode: 0,
+            prg_ram_protect: [0; 2],
+            exram_mode: 0,
+            exram: [0; EXRAM_SIZE],
+            nametable_mapping: 0,
+            fill_tile: 0,
+            fill_attribute: 0,
+            prg_banks: [0, 0, 0, 0, 0xFF],
+            chr_a: [0; 8],
+            chr_b: [0; 4],
+            chr_upper: 0,
+            chr_b_last: false,
+            split_control: 0,
+            split_scroll: 0,
+            split_bank: 0,
+            irq_compare: 0,
+            irq_enabled: false,
+            irq_pending: false,
+            multiplicand: 0xFF,
+            multiplier: 0xFF,
+            large_sprites: false,
+            in_frame: false,
+            scanline: 0,
+            last_nametable_addr: 0,
+            nametable_repeats: 0,
+            fetches: 0,
+            tile_exram: 0,
+            tile_split: None,
+        }
+    }
+
+    /// The 8KB bank mapped at `addr` ($8000-$FFFF), and whether it is ROM rather than PRG RAM.
+    fn prg_bank(&self, addr: u16) -> (usize, bool) {
+        let slot = usize::from((addr - 0x8000) / 0x2000);
+        let (reg, banks) = match self.prg_mode {
+            0 => (4, 4),
+            1 => ([2, 4][slot / 2], 2),
+            2 => ([2, 2, 3, 4][slot], [2, 2, 1, 1][slot]),
+            _ => (slot + 1, 1),
+        };
+        // $5117 always maps ROM.
+        let rom = reg == 4 || self.prg_banks[reg] & 0x80 != 0;
+        // Larger banks ignore their low bits and index by position within the bank instead.
+        let bank = (usize::from(self.prg_banks[reg] & 0x7F) & !(banks - 1)) | (slot & (banks - 1));
+        (bank, rom)
+    }
+
+    fn prg_ram_writable(&self) -> bool {
+        self.prg_ram_protect == [0b10, 0b01]
+    }
+
+    fn prg_ram_offset(&self, bank: usize, addr: u16) -> usize {
+        banked(&self.cart.prg_ram, PRG_BANK_SIZE, bank & 0x0F, addr)
+    }
+
+    fn rendering_background(&self) -> bool {
+        self.in_frame && !SPRITE_FETCHES.contains(&self.fetches)
+    }
+
+    /// Column and scanline offset of the background tile the current fetch belongs to.
+    fn tile_column(&self) -> Option<(u16, u8)> {
+        match self.fetches {
+            0..=127 => Some((self.fetches / 4 + 2, 0)),
+            PREFETCH_START..PREFETCH_END => Some(((self.fetches - PREFETCH_START) / 4, 1)),
+            _ => None,
+        }
+    }
+
+    fn split_row(&self) -> Option<u16> {
+        if self.split_control & 0x80 == 0 || !self.in_frame {
+            return None;
+        }
+        let (column, line_offset) = self.tile_column()?;
+        let threshold = u16::from(self.split_control & 0x1F);
+        let right_side = self.split_control & 0x40 != 0;
+        if (column >= threshold) != right_side {
+            return None;
+        }
+        let y = u16::from(self.split_scroll) + u16::from(self.scanline) + u16::from(line_offset);
+        Some(y % 240)
+    }
+
+    fn detect_scanline(&mut self) {
+        self.fetches = 0;
+        if self.in_frame {
+            self.scanline = self.scanline.wrapping_add(1);
+            if self.scanline == self.irq_compare {
+                self.irq_pending = true;
+            }
+        } else {
+            self.in_frame = true;
+            self.scanline = 0;
+        }
+    }
+
+    /// The 1KB CHR bank for `addr`, taking the current fetch into account.
+    fn chr_bank(&self, addr: u16) -> usize {
+        let slot = usize::from(addr >> 10) & 7;
+        let background = self.rendering_background();
+
+        if background && self.tile_split.is_some() {
+            return usize::from(self.split_bank) * 4 + slot % 4;
+        }
+        if background && self.exram_mode == 1 {
+            let bank = usize::from(self.tile_exram & 0x3F) | (usize::from(self.chr_upper) << 6);
+            return bank * 4 + slot % 4;
+        }
+
+        let use_b = if !self.in_frame {
+            self.chr_b_last
+        } else {
+            self.large_sprites && background
+        };
+        let (regs, slot): (&[u16], usize) = if use_b {
+            (&self.chr_b, slot % 4)
+        } else {
+            (&self.chr_a, slot)
+        };
+        let last = regs.len() - 1;
+        let (reg, size) = match self.chr_mode {
+            0 => (last, 8),
+            1 => (if slot < 4 { 3 } else { last }, 4),
+            2 => ((slot / 2) * 2 + 1, 2),
+            _ => (slot, 1),
+        };
+        let reg = reg.min(last);
+        usize::from(regs[reg]) * size + (usize::from(addr >> 10) & (size - 1))
+    }
+
+    fn fill_attribute_byte(bits: u8) -> u8 {
+        let bits = bits & 0b11;
+        bits | bits << 2 | bits << 4 | bits << 6
+    }
+}
+
+impl Mapper for Mmc5 {
+    fn cartridge(&self) -> &Cartridge {
+        &self.cart
+    }
+
+    fn cartridge_mut(&mut self) -> &mut Cartridge {
+        &mut self.cart
+    }
+
+    fn cpu_read(&mut self, addr: u16) -> Option<u8> {
+        match addr {
+            0x5204 => {
+                let status = u8::from(self.irq_pending) << 7 | u8::from(self.in_frame) << 6;
+                self.irq_pending = false;
+                Some(status)
+            }
+            0x5205 => Some((u16::from(self.multiplicand) * u16::from(self.multiplier)) as u8),
+            0x5206 => {
+                Some(((u16::from(self.multiplicand) * u16::from(self.multiplier)) >> 8) as u8)
+            }
+            0x5C00..=0x5FFF if self.exram_mode >= 2 => Some(self.exram[usize::from(addr - 0x5C00)]),
+            0x6000..=0x7FFF => {
+                let offset = self.prg_ram_offset(self.prg_banks[0].into(), addr);
+                Some(self.cart.prg_ram[offset])
+            }
+            0x8000..=0xFFFF => {
+                if addr == 0xFFFA || addr == 0xFFFB {
+                    // Fetching the NMI vector means vblank has started.
+                    self.in_frame = false;
+                }
+                let (bank, rom) = self.prg_bank(addr);
+                if rom {
+                    Some(self.cart.prg_rom[banked(&self.cart.prg_rom, PRG_BANK_SIZE, bank, addr)])
+                } else {
+                    Some(self.cart.prg_ram[self.prg_ram_offset(bank, addr)])
+                }
+            }
+            _ => None,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x5100 => self.prg_mode = data & 0b11,
+            0x5101 => self.chr_mode = data & 0b11,
+            0x5102 => self.prg_ram_protect[0] = data & 0b11,
+            0x5103 => self.prg_ram_protect[1] = data & 0b11,
+            0x5104 => self.exram_mode = data & 0b11,
+            0x5105 => self.nametable_mapping = data,
+            0x5106 => self.fill_tile = data,
+            0x5107 => self.fill_attribute = data & 0b11,
+            0x5113..=0x5117 => self.prg_banks[usize::from(addr - 0x5113)] = data,
+            0x5120..=0x5127 => {
+                self.chr_a[usize::from(addr - 0x5120)] =
+                    u16::from(data) | u16::from(self.chr_upper) << 8;
+                self.chr_b_last = false;
+            }
+            0x5128..=0x512B => {
+                self.chr_b[usize::from(addr - 0x5128)] =
+                    u16::from(data) | u16::from(self.chr_upper) << 8;
+                self.chr_b_last = true;
+            }
+            0x5130 => self.chr_upper = data & 0b11,
+            0x5200 => self.split_control = data,
+            0x5201 => self.split_scroll = data,
+            0x5202 => self.split_bank = data,
+            0x5203 => self.irq_compare = data,
+            0x5204 => self.irq_enabled = data & 0x80 != 0,
+            0x5205 => self.multiplicand = data,
+            0x5206 => self.multiplier = data,
+            0x5C00..=0x5FFF if self.exram_mode != 3 => {
+                self.exram[usize::from(addr - 0x5C00)] = data
+            }
+            0x6000..=0x7FFF if self.prg_ram_writable() => {
+                let offset = self.prg_ram_offset(self.prg_banks[0].into(), addr);
+                self.cart.prg_ram[offset] = data;
+            }
+            0x8000..=0xDFFF if self.prg_ram_writable() => {
+                let (bank, rom) = self.prg_bank(addr);
+                if !rom {
+                    let offset = self.prg_ram_offset(bank, addr);
+                    self.cart.prg_ram[offset] = data;
+                }
+            }
+            _ => trace!("write {data:02X} to MMC5 {addr:04X} ignored"),
+        }
+    }
+
+    fn ppu_read(&mut self, addr: u16) -> u8 {
+        let mut addr = addr;
+        if let (true, Some(row)) = (self.rendering_background(), self.tile_split) {
+            // Split tiles use the split's own fine Y.
+            addr = (addr & !0b111) | (row & 0b111);
+        }
+        let bank = self.chr_bank(addr);
+        self.cart.chr_rom[banked(&self.cart.chr_rom, CHR_BANK_SIZE, bank, addr)]
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        if self.cart.chr_ram {
+            let bank = self.chr_bank(addr);
+            let offset = banked(&self.cart.chr_rom, CHR_BANK_SIZE, bank, addr);
+            self.cart.chr_rom[offset] = data;
+        }
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        // Only meaningful for the simple layouts; nametable reads go through $5105 directly.
+        match self.nametable_mapping {
+            0x44 => Mirroring::Vertical,
+            0x50 => Mirroring::Horizontal,
+            0x55 => Mirroring::SingleScreenUpper,
+            _ => Mirroring::SingleScreenLower,
+        }
+    }
+
+    fn nametable_read(&mut self, addr: u16, vram: &[u8]) -> u8 {
+        let offset = usize::from(addr & 0x03FF);
+        let attribute = offset >= 0x3C0;
+
+        // Detect the third identical read in a row before this read is classified.
+        if addr == self.last_nametable_addr && self.nametable_repeats >= 2 {
+            self.detect_scanline();
+        }
+
+        if self.rendering_background() && !attribute {
+            self.tile_split = self.split_row();
+        }
+        if let (true, Some(row)) = (self.rendering_background(), self.tile_split) {
+            let (column, _) = self.tile_column().unwrap_or((0, 0));
+            let column = column % 32;
+            return if attribute {
+                let byte = self.exram[0x3C0 + usize::from(row / 32 * 8 + column / 4)];
+                let shift = ((row / 16) & 1) << 2 | ((column / 2) & 1) << 1;
+                Self::fill_attribute_byte(byte >> shift)
+            } else {
+                self.exram[usize::from(row / 8 * 32 + column)]
+            };
+        }
+
+        if self.exram_mode == 1 && self.rendering_background() {
+            if attribute {
+                return Self::fill_attribute_byte(self.tile_exram >> 6);
+            }
+            self.tile_exram = self.exram[offset];
+        }
+
+        let table = usize::from(addr >> 10) & 0b11;
+        match (self.nametable_mapping >> (table * 2)) & 0b11 {
+            page @ (0 | 1) => vram[usize::from(page) * 0x400 + offset],
+            2 if self.exram_mode <= 1 => self.exram[offset],
+            2 => 0,
+            _ if attribute => Self::fill_attribute_byte(self.fill_attribute),
+            _ => self.fill_tile,
+        }
+    }
+
+    fn nametable_write(&mut self, addr: u16, data: u8, vram: &mut [u8]) {
+        let offset = usize::from(addr & 0x03FF);
+        let table = usize::from(addr >> 10) & 0b11;
+        match (self.nametable_mapping >> (table * 2)) & 0b11 {
+            page @ (0 | 1) => vram[usize::from(page) * 0x400 + offset] = data,
+            2 if self.exram_mode <= 1 => self.exram[offset] = data,
+            _ => {}
+        }
+    }
+
+    fn ppu_register_write(&mut self, reg: u16, data: u8) {
+        match reg {
+            0x2000 => self.large_sprites = data & 0x20 != 0,
+            0x2001 if data & 0x18 == 0 => self.in_frame = false,
+            _ => {}
+        }
+    }
+
+    fn ppu_access(&mut self, addr: u16) {
+        if (0x2000..=0x2FFF).contains(&addr) && addr == self.last_nametable_addr {
+            self.nametable_repeats = self.nametable_repeats.saturating_add(1);
+        } else {
+            self.nametable_repeats = 1;
+        }
+        self.last_nametable_addr = addr;
+        self.fetches = self.fetches.saturating_add(1);
+    }
+
+    fn irq(&self) -> bool {
+        self.irq_enabled && self.irq_pending
+    }
+
+    /// Resetting clears PPUCTRL and PPUMASK without a write for the board to snoop, so drop out
+    /// of the frame the way those writes would.
+    fn reset(&mut self) {
+        self.large_sprites = false;
+        self.in_frame = false;
+        self.irq_pending = false;
+    }
+
+    fn save_state(&self) -> Vec<u8> {
+        state::to_bytes(self)
+    }
+
+    fn load_state(&mut self, state: &[u8]) -> Result<()> {
+        load_board(self, state)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::test_image;
+
+    fn mmc5() -> Mmc5 {
+        // 16 banks of 8KB PRG, 32 of 1KB CHR.
+        Mmc5::new(Cartridge::new(&test_image(5, 8, 4, 0)).unwrap())
+    }
+
+    /// Reads through the PPU bus like the PPU would, including the `ppu_access` notification.
+    fn ppu_fetch(mmc5: &mut Mmc5, addr: u16, vram: &[u8]) -> u8 {
+        let data = if addr >= 0x2000 {
+            mmc5.nametable_read(addr, vram)
+        } else {
+            mmc5.ppu_read(addr)
+        };
+        mmc5.ppu_access(addr);
+        data
+    }
+
+    /// Performs the 170 reads of one rendered scanline, ending with the dummy nametable reads.
+    fn render_scanline(mmc5: &mut Mmc5, vram: &[u8]) {
+        for fetch in 0..170u16 {
+            // The dummy reads repeat the address of the next line's first tile.
+            let addr = match (fetch, fetch % 4) {
+                (168.., _) => 0x2000,
+                (_, 0) => 0x2000 + fetch / 4,
+                (_, 1) => 0x23C0,
+                (_, _) => 0x0000,
+            };
+            ppu_fetch(mmc5, addr, vram);
+        }
+    }
+
+    #[test]
+    fn test_prg_modes() {
+        let mut mmc5 = mmc5();
+        // Power-on: mode 3 with $5117 = $FF, the last bank at $E000.
+        assert_eq!(mmc5.cpu_read(0xFFFC), Some(7));
+
+        mmc5.cpu_write(0x5100, 1);
+        mmc5.cpu_write(0x5115, 0x80 | 4);
+        assert_eq!(mmc5.cpu_read(0x8000), Some(2));
+        assert_eq!(mmc5.cpu_read(0xA000), Some(2));
+
+        mmc5.cpu_write(0x5100, 3);
+        mmc5.cpu_write(0x5114, 0x80 | 9);
+        assert_eq!(mmc5.cpu_read(0x8000), Some(4))
+    }
+
+    #[test]
+    fn test_prg_ram_banks_and_protection() {
+        let mut mmc5 = mmc5();
+        mmc5.cpu_write(0x6000, 0x11);
+        assert_eq!(mmc5.cpu_read(0x6000), Some(0));
+
+        mmc5.cpu_write(0x5102, 0b10);
+        mmc5.cpu_write(0x5103, 0b01);
+        mmc5.cpu_write(0x6000, 0x11);
+        assert_eq!(mmc5.cpu_read(0x6000), Some(0x11));
+
+        // PRG RAM mapped into $8000 by clearing bit 7 of the bank.
+        mmc5.cpu_write(0x5114, 0x00);
+        assert_eq!(mmc5.cpu_read(0x8000), Some(0x11))
+    }
+
+    #[test]
+    fn test_multiplier_and_exram() {
+        let mut mmc5 = mmc5();
+        mmc5.cpu_write(0x5205, 200);
+        mmc5.cpu_write(0x5206, 100);
+        assert_eq!(mmc5.cpu_read(0x5205), Some(0x20));
+        assert_eq!(mmc5.cpu_read(0x5206), Some(0x4E));
+
+        mmc5.cpu_write(0x5C10, 0x42);
+        assert_eq!(mmc5.cpu_read(0x5C10), None);
+        mmc5.cpu_write(0x5104, 2);
+        assert_eq!(mmc5.cpu_read(0x5C10), Some(0x42))
+    }
+
+    #[test]
+    fn test_nametable_mapping_and_fill() {
+        let mut mmc5 = mmc5();
+        let mut vram = [0; 0x800];
+        vram[0x405] = 0xBB;
+        mmc5.exram[0x005] = 0xEE;
+        mmc5.cpu_write(0x5106, 0x77);
+        mmc5.cpu_write(0x5107, 0b10);
+        // $2000: CIRAM B, $2400: ExRAM, $2800: fill, $2C00: CIRAM A.
+        mmc5.cpu_write(0x5105, 0b00_11_10_01);
+
+        assert_eq!(mmc5.nametable_read(0x2005, &vram), 0xBB);
+        assert_eq!(mmc5.nametable_read(0x2405, &vram), 0xEE);
+        assert_eq!(mmc5.nametable_read(0x2805, &vram), 0x77);
+        assert_eq!(mmc5.nametable_read(0x2BC0, &vram), 0b1010_1010);
+
+        mmc5.nametable_write(0x2C05, 0x12, &mut vram);
+        assert_eq!(vram[0x005], 0x12)
+    }
+
+    #[test]
+    fn test_scanline_irq() {
+        let mut mmc5 = mmc5();
+        let vram = [0; 0x800];
+        mmc5.cpu_write(0x5203, 3);
+        mmc5.cpu_write(0x5204, 0x80);
+
+        render_scanline(&mut mmc5, &vram);
+        assert!(!mmc5.in_frame);
+        for _ in 0..3 {
+            render_scanline(&mut mmc5, &vram);
+        }
+        assert!(mmc5.in_frame);
+        assert!(!mmc5.irq());
+        render_scanline(&mut mmc5, &vram);
+        assert!(mmc5.irq());
+
+        assert_eq!(mmc5.cpu_read(0x5204), Some(0xC0));
+        assert!(!mmc5.irq());
+
+        // The NMI vector fetch ends the frame.
+        mmc5.cpu_read(0xFFFA);
+        assert_eq!(mmc5.cpu_read(0x5204), Some(0x00))
+    }
+
+    #[test]
+    fn test_8x16_sprites_and_background_use_separate_chr_sets() {
+        let mut mmc5 = mmc5();
+        let vram = [0; 0x800];
+        mmc5.cpu_write(0x5127, 1);
+        mmc5.cpu_write(0x512B, 2);
+        mmc5.ppu_register_write(0x2000, 0x20);
+        render_scanline(&mut mmc5, &vram);
+        render_scanline(&mut mmc5, &vram);
+
+        // Background fetches at the start of the line use set B.
+        ppu_fetch(&mut mmc5, 0x2000, &vram);
+        assert_eq!(ppu_fetch(&mut mmc5, 0x1000, &vram), 0x82);
+        for _ in 2..128 {
+            ppu_fetch(&mut mmc5, 0x0000, &vram);
+        }
+        // Sprite fetches use set A.
+        assert_eq!(ppu_fetch(&mut mmc5, 0x1000, &vram), 0x81)
+    }
+
+    #[test]
+    fn test_extended_attributes() {
+        let mut mmc5 = mmc5();
+        let vram = [0; 0x800];
+        mmc5.cpu_write(0x5104, 1);
+        // The first tile fetched on a line uses 4KB CHR bank 3 and palette 2.
+        mmc5.exram[0] = 0b10_000011;
+        render_scanline(&mut mmc5, &vram);
+        render_scanline(&mut mmc5, &vram);
+
+        ppu_fetch(&mut mmc5, 0x2000, &vram);
+        assert_eq!(ppu_fetch(&mut mmc5, 0x23C0, &vram), 0b1010_1010);
+        assert_eq!(ppu_fetch(&mut mmc5, 0x0000, &vram), 0x81)
+    }
+}
@@ -0,0 +1,180 @@
+//! Cartridge boards.
+//!
+//! Everything in cartridge space ($4020-$FFFF on the CPU side, the pattern tables at $0000-$1FFF on
+//! the PPU side) is decoded by the board, and every board wires it differently. Each mapper number
+//! gets its own [`Mapper`] implementation and the bus talks to whichever one the cartridge asks for.
+
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::mem;
+
+use serde::de::DeserializeOwned;
+
+use crate::cartridge::{Cartridge, Mirroring};
+use crate::error::{NesError, Result};
+use crate::state;
+
+mod axrom;
+mod cnrom;
+mod mmc1;
+mod mmc2;
+mod mmc5;
+mod nrom;
+mod uxrom;
+mod vrc6;
+
+pub use axrom::Axrom;
+pub use cnrom::Cnrom;
+pub use mmc1::Mmc1;
+pub use mmc2::Mmc2;
+pub use mmc5::Mmc5;
+pub use nrom::Nrom;
+pub use uxrom::Uxrom;
+pub use vrc6::Vrc6;
+
+pub trait Mapper: Send {
+    /// The cartridge the board was built from, including its PRG RAM.
+    fn cartridge(&self) -> &Cartridge;
+
+    fn cartridge_mut(&mut self) -> &mut Cartridge;
+
+    /// Reads from cartridge space. `None` means the board leaves the data bus floating.
+    fn cpu_read(&mut self, addr: u16) -> Option<u8>;
+
+    fn cpu_write(&mut self, addr: u16, data: u8);
+
+    /// Reads from the pattern tables.
+    fn ppu_read(&mut self, addr: u16) -> u8;
+
+    fn ppu_write(&mut self, addr: u16, data: u8);
+
+    fn mirroring(&self) -> Mirroring;
+
+    /// Reads a nametable byte ($2000-$2FFF). `vram` is the console's nametable memory; boards that
+    /// map their own memory into nametable space override this.
+    fn nametable_read(&mut self, addr: u16, vram: &[u8]) -> u8 {
+        vram[self.mirroring().nametable_offset(addr)]
+    }
+
+    fn nametable_write(&mut self, addr: u16, data: u8, vram: &mut [u8]) {
+        vram[self.mirroring().nametable_offset(addr)] = data;
+    }
+
+    /// Sees CPU writes to the PPU registers. Some boards snoop them to follow the PPU's settings.
+    fn ppu_register_write(&mut self, _reg: u16, _data: u8) {}
+
+    /// Called after every PPU memory access, including nametable fetches that never reach the
+    /// board's CHR. Boards that watch the PPU address bus (CHR latches, scanline counters) hook in
+    /// here.
+    fn ppu_access(&mut self, _addr: u16) {}
+
+    /// Whether the board is holding the CPU's IRQ line low.
+    fn irq(&self) -> bool {
+        false
+    }
+
+    /// The console's reset button was pressed. Most boards never see the reset line and keep
+    /// their registers; some follow what it does to the PPU.
+    fn reset(&mut self) {}
+
+    /// Called once per CPU cycle, for boards with their own timers (CPU-clocked IRQ counters,
+    /// expansion audio).
+    fn cpu_clock(&mut self) {}
+
+    /// Current level of the board's expansion audio, on the same scale as the APU's mixed output
+    /// (0.0 silent to roughly 1.0 at full volume). The APU mixes it in with its own channels.
+    fn expansion_audio(&self) -> f32 {
+        0.0
+    }
+
+    /// The board's registers and any memory of its own, for a save state. The cartridge's PRG
+    /// and CHR RAM are saved by the bus.
+    fn save_state(&self) -> Vec<u8>;
+
+    /// Restores the registers from [`Mapper::save_state`].
+    ///
+    /// # Errors
+    /// Returns [`NesError::InvalidState`] if `state` wasn't saved by this kind of board.
+    fn load_state(&mut self, state: &[u8]) -> Result<()>;
+}
+
+/// Builds the board `cart` was made for.
+///
+/// # Errors
+/// Returns [`NesError::UnsupportedMapper`] for boards that aren't implemented.
+pub fn from_cartridge(cart: Cartridge) -> Result<Box<dyn Mapper>> {
+    let mapper = cart.mapper;
+    let board: Box<dyn Mapper> = match mapper {
+        0 => Box::new(Nrom::new(cart)),
+        1 => Box::new(Mmc1::new(cart)),
+        2 => Box::new(Uxrom::new(cart)),
+        3 => Box::new(Cnrom::new(cart)),
+        5 => Box::new(Mmc5::new(cart)),
+        7 => Box::new(Axrom::new(cart)),
+        9 => Box::new(Mmc2::new(cart)),
+        24 => Box::new(Vrc6::new(cart, false)),
+        26 => Box::new(Vrc6::new(cart, true)),
+        mapper => {
+            warn!("mapper {mapper} isn't supported");
+            return Err(NesError::UnsupportedMapper(mapper));
+        }
+    };
+    debug!("mapper {mapper} board ready");
+    Ok(board)
+}
+
+/// [`Mapper::load_state`] for boards that derive their state, skipping the cartridge: a board is
+/// deserialized from `state` and takes over `board`'s cartridge.
+fn load_board<B: Mapper + DeserializeOwned>(board: &mut B, state: &[u8]) -> Result<()> {
+    let mut loaded: B = state::from_bytes(state)?;
+    mem::swap(loaded.cartridge_mut(), board.cartridge_mut());
+    *board = loaded;
+    Ok(())
+}
+
+/// Index into `mem` of `addr` in bank `bank` of a `size`-byte bank layout. Bank numbers past the
+/// end wrap around, the way unconnected high bank bits do on real boards, and memory smaller than
+/// one bank is mirrored through it.
+fn banked(mem: &[u8], size: usize, bank: usize, addr: u16) -> usize {
+    let banks = (mem.len() / size).max(1);
+    ((bank % banks) * size + (usize::from(addr) & (size - 1))) % mem.len().max(1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::test_image;
+
+    #[test]
+    fn test_unsupported_mapper() {
+        let cart = Cartridge::new(&test_image(0xAB, 1, 1, 0)).unwrap();
+
+        assert!(matches!(
+            from_cartridge(cart),
+            Err(NesError::UnsupportedMapper(0xAB))
+        ))
+    }
+
+    #[test]
+    fn test_board_state() {
+        let raw = test_image(1, 8, 0, 0);
+        let mut board = from_cartridge(Cartridge::new(&raw).unwrap()).unwrap();
+        // Five serial writes of 3 to the PRG bank register.
+        for bit in [1, 1, 0, 0, 0] {
+            board.cpu_write(0xE000, bit);
+        }
+        let state = board.save_state();
+
+        let mut loaded = from_cartridge(Cartridge::new(&raw).unwrap()).unwrap();
+        assert_ne!(loaded.cpu_read(0x8000), board.cpu_read(0x8000));
+        loaded.load_state(&state).unwrap();
+        assert_eq!(loaded.cpu_read(0x8000), board.cpu_read(0x8000));
+        assert_eq!(loaded.cartridge().prg_rom.len(), 8 * 0x4000);
+
+        let mut nrom = from_cartridge(Cartridge::new(&test_image(0, 1, 1, 0)).unwrap()).unwrap();
+        assert!(matches!(
+            nrom.load_state(&state),
+            Err(NesError::InvalidState(_))
+        ))
+    }
+}
@@ -0,0 +1,121 @@
+use alloc::vec::Vec;
+
+use serde::{Deserialize, Serialize};
+
+use crate::cartridge::{Cartridge, Mirroring};
+use crate::error::Result;
+use crate::mapper::{load_board, Mapper};
+use crate::state;
+
+/// Mapper 0: no bank switching at all. 16KB or 32KB of PRG ROM at $8000 (16KB images are mirrored
+/// into $C000), optional PRG RAM at $6000 and a fixed 8KB of CHR.
+#[derive(Serialize, Deserialize)]
+pub struct Nrom {
+    #[serde(skip)]
+    cart: Cartridge,
+}
+
+impl Nrom {
+    pub fn new(cart: Cartridge) -> Self {
+        Self { cart }
+    }
+}
+
+impl Mapper for Nrom {
+    fn cartridge(&self) -> &Cartridge {
+        &self.cart
+    }
+
+    fn cartridge_mut(&mut self) -> &mut Cartridge {
+        &mut self.cart
+    }
+
+    fn cpu_read(&mut self, addr: u16) -> Option<u8> {
+        match addr {
+            0x6000..=0x7FFF => Some(self.cart.prg_ram[usize::from(addr - 0x6000)]),
+            0x8000..=0xFFFF => {
+                let offset = usize::from(addr - 0x8000) % self.cart.prg_rom.len();
+                Some(self.cart.prg_rom[offset])
+            }
+            _ => None,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x6000..=0x7FFF => self.cart.prg_ram[usize::from(addr - 0x6000)] = data,
+            _ => trace!("write {data:02X} to NROM {addr:04X} ignored"),
+        }
+    }
+
+    fn ppu_read(&mut self, addr: u16) -> u8 {
+        self.cart.chr_rom[usize::from(addr & 0x1FFF)]
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        if self.cart.chr_ram {
+            self.cart.chr_rom[usize::from(addr & 0x1FFF)] = data;
+        }
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.cart.mirroring
+    }
+
+    fn save_state(&self) -> Vec<u8> {
+        state::to_bytes(self)
+    }
+
+    fn load_state(&mut self, state: &[u8]) -> Result<()> {
+        load_board(self, state)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::test_image;
+
+    fn nrom(prg_banks: u8, chr_banks: u8) -> Nrom {
+        Nrom::new(Cartridge::new(&test_image(0, prg_banks, chr_banks, 0)).unwrap())
+    }
+
+    #[test]
+    fn test_16k_prg_is_mirrored() {
+        let mut nrom = nrom(1, 1);
+        nrom.cart.prg_rom[0x0123] = 0x42;
+
+        assert_eq!(nrom.cpu_read(0x8123), Some(0x42));
+        assert_eq!(nrom.cpu_read(0xC123), Some(0x42))
+    }
+
+    #[test]
+    fn test_32k_prg_is_linear() {
+        let mut nrom = nrom(2, 1);
+
+        assert_eq!(nrom.cpu_read(0x8000), Some(0));
+        assert_eq!(nrom.cpu_read(0xC000), Some(1))
+    }
+
+    #[test]
+    fn test_prg_ram_and_unmapped() {
+        let mut nrom = nrom(1, 1);
+        nrom.cpu_write(0x6010, 0x99);
+        nrom.cpu_write(0x8000, 0x99);
+
+        assert_eq!(nrom.cpu_read(0x6010), Some(0x99));
+        assert_eq!(nrom.cpu_read(0x8000), Some(0));
+        assert_eq!(nrom.cpu_read(0x5000), None)
+    }
+
+    #[test]
+    fn test_chr_rom_is_read_only_and_chr_ram_is_not() {
+        let mut rom = nrom(1, 1);
+        rom.ppu_write(0x0010, 0x55);
+        assert_eq!(rom.ppu_read(0x0010), 0x80);
+
+        let mut ram = nrom(1, 0);
+        ram.ppu_write(0x0010, 0x55);
+        assert_eq!(ram.ppu_read(0x0010), 0x55)
+    }
+}
@@ -0,0 +1,98 @@
+use alloc::vec::Vec;
+
+use serde::{Deserialize, Serialize};
+
+use crate::cartridge::{Cartridge, Mirroring};
+use crate::error::Result;
+use crate::mapper::{banked, load_board, Mapper};
+use crate::state;
+
+const PRG_BANK_SIZE: usize = 0x4000;
+
+/// Mapper 2: UNROM/UOROM. Any write to $8000-$FFFF selects the 16KB PRG bank at $8000; the last
+/// bank is fixed at $C000. CHR is 8KB of RAM.
+#[derive(Serialize, Deserialize)]
+pub struct Uxrom {
+    #[serde(skip)]
+    cart: Cartridge,
+    prg_bank: u8,
+}
+
+impl Uxrom {
+    pub fn new(cart: Cartridge) -> Self {
+        Self { cart, prg_bank: 0 }
+    }
+}
+
+impl Mapper for Uxrom {
+    fn cartridge(&self) -> &Cartridge {
+        &self.cart
+    }
+
+    fn cartridge_mut(&mut self) -> &mut Cartridge {
+        &mut self.cart
+    }
+
+    fn cpu_read(&mut self, addr: u16) -> Option<u8> {
+        let bank = match addr {
+            0x8000..=0xBFFF => usize::from(self.prg_bank),
+            0xC000..=0xFFFF => self.cart.prg_rom.len() / PRG_BANK_SIZE - 1,
+            _ => return None,
+        };
+        Some(self.cart.prg_rom[banked(&self.cart.prg_rom, PRG_BANK_SIZE, bank, addr)])
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x8000..=0xFFFF => self.prg_bank = data,
+            _ => trace!("write {data:02X} to UxROM {addr:04X} ignored"),
+        }
+    }
+
+    fn ppu_read(&mut self, addr: u16) -> u8 {
+        self.cart.chr_rom[usize::from(addr & 0x1FFF)]
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        if self.cart.chr_ram {
+            self.cart.chr_rom[usize::from(addr & 0x1FFF)] = data;
+        }
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.cart.mirroring
+    }
+
+    fn save_state(&self) -> Vec<u8> {
+        state::to_bytes(self)
+    }
+
+    fn load_state(&mut self, state: &[u8]) -> Result<()> {
+        load_board(self, state)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::test_image;
+
+    #[test]
+    fn test_switchable_and_fixed_banks() {
+        let mut uxrom = Uxrom::new(Cartridge::new(&test_image(2, 8, 0, 0)).unwrap());
+        assert_eq!(uxrom.cpu_read(0x8000), Some(0));
+        assert_eq!(uxrom.cpu_read(0xC000), Some(7));
+
+        uxrom.cpu_write(0x8000, 5);
+        assert_eq!(uxrom.cpu_read(0xBFFF), Some(5));
+        assert_eq!(uxrom.cpu_read(0xFFFF), Some(7))
+    }
+
+    #[test]
+    fn test_chr_ram() {
+        let mut uxrom = Uxrom::new(Cartridge::new(&test_image(2, 2, 0, 0)).unwrap());
+        uxrom.ppu_write(0x1234, 0x77);
+
+        assert_eq!(uxrom.ppu_read(0x1234), 0x77)
+    }
+}
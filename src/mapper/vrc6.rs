@@ -0,0 +1,453 @@
+use alloc::vec::Vec;
+
+use serde::{Deserialize, Serialize};
+
+use crate::cartridge::{Cartridge, Mirroring};
+use crate::error::{NesError, Result};
+use crate::mapper::{banked, load_board, Mapper};
+use crate::state;
+
+const PRG_16K: usize = 0x4000;
+const PRG_8K: usize = 0x2000;
+const CHR_BANK_SIZE: usize = 0x0400;
+
+/// CPU cycles per scanline, times three, for the IRQ prescaler in scanline mode.
+const PRESCALER_PERIOD: i16 = 341;
+
+/// VRC6 output level to APU output level. One VRC6 volume step is about as loud as one step of an
+/// APU pulse channel.
+const AUDIO_SCALE: f32 = 0.00752;
+
+/// Mappers 24 and 26: Konami's VRC6 (Akumajou Densetsu, Madara, Esper Dream 2).
+///
+/// ```text
+/// $8000-$8003  16KB PRG bank at $8000
+/// $9000-$9002  pulse 1: MDDD VVVV (mode, duty, volume), period low, E... PPPP (enable, period high)
+/// $9003        audio halt
+/// $A000-$A002  pulse 2, same layout
+/// $B000-$B002  sawtooth: ..AA AAAA (accumulator rate), period low, E... PPPP
+/// $B003        PPU banking: R... MMCC (PRG RAM enable, mirroring, CHR layout)
+/// $C000-$C003  8KB PRG bank at $C000; the last 8KB is fixed at $E000
+/// $D000-$E003  CHR registers R0-R7
+/// $F000-$F002  IRQ latch, control and acknowledge
+/// ```
+///
+/// Mapper 26 boards swap the A0 and A1 lines, so their registers at $x001 and $x002 trade places.
+#[derive(Serialize, Deserialize)]
+pub struct Vrc6 {
+    #[serde(skip)]
+    cart: Cartridge,
+    swapped_lines: bool,
+    prg_16k: u8,
+    prg_8k: u8,
+    chr: [u8; 8],
+    banking: u8,
+    irq: VrcIrq,
+    audio_halt: bool,
+    pulses: [Vrc6Pulse; 2],
+    saw: Vrc6Saw,
+}
+
+impl Vrc6 {
+    pub fn new(cart: Cartridge, swapped_lines: bool) -> Self {
+        Self {
+            cart,
+            swapped_lines,
+            prg_16k: 0,
+            prg_8k: 0,
+            chr: [0; 8],
+            banking: 0,
+            irq: VrcIrq::default(),
+            audio_halt: false,
+            pulses: [Vrc6Pulse::default(); 2],
+            saw: Vrc6Saw::default(),
+        }
+    }
+
+    /// Normalizes a register address to the mapper 24 layout.
+    fn register(&self, addr: u16) -> u16 {
+        let addr = addr & 0xF003;
+        if self.swapped_lines {
+            (addr & !0b11) | ((addr & 0b01) << 1) | ((addr & 0b10) >> 1)
+        } else {
+            addr
+        }
+    }
+
+    fn prg_ram_enabled(&self) -> bool {
+        self.banking & 0x80 != 0
+    }
+
+    fn chr_bank(&self, addr: u16) -> usize {
+        let slot = usize::from(addr >> 10) & 7;
+        let a10 = slot & 1;
+        let r = |n: usize| usize::from(self.chr[n]);
+        match self.banking & 0b11 {
+            0 => r(slot),
+            // 2KB banks, low bank bit from PPU A10.
+            1 => (r(slot / 2) & !1) | a10,
+            _ if slot < 4 => r(slot),
+            _ => (r(4 + (slot - 4) / 2) & !1) | a10,
+        }
+    }
+}
+
+impl Mapper for Vrc6 {
+    fn cartridge(&self) -> &Cartridge {
+        &self.cart
+    }
+
+    fn cartridge_mut(&mut self) -> &mut Cartridge {
+        &mut self.cart
+    }
+
+    fn cpu_read(&mut self, addr: u16) -> Option<u8> {
+        let (size, bank) = match addr {
+            0x6000..=0x7FFF if self.prg_ram_enabled() => {
+                return Some(self.cart.prg_ram[usize::from(addr - 0x6000)]);
+            }
+            0x8000..=0xBFFF => (PRG_16K, usize::from(self.prg_16k)),
+            0xC000..=0xDFFF => (PRG_8K, usize::from(self.prg_8k)),
+            0xE000..=0xFFFF => (PRG_8K, self.cart.prg_rom.len() / PRG_8K - 1),
+            _ => return None,
+        };
+        Some(self.cart.prg_rom[banked(&self.cart.prg_rom, size, bank, addr)])
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        if let 0x6000..=0x7FFF = addr {
+            if self.prg_ram_enabled() {
+                self.cart.prg_ram[usize::from(addr - 0x6000)] = data;
+            }
+            return;
+        }
+        match self.register(addr) {
+            0x8000..=0x8003 => self.prg_16k = data & 0x0F,
+            reg @ (0x9000..=0x9002 | 0xA000..=0xA002) => {
+                let pulse = usize::from(reg >> 12) - 9;
+                self.pulses[pulse].write(reg & 0b11, data);
+            }
+            0x9003 => self.audio_halt = data & 1 != 0,
+            reg @ 0xB000..=0xB002 => self.saw.write(reg & 0b11, data),
+            0xB003 => self.banking = data,
+            0xC000..=0xC003 => self.prg_8k = data & 0x1F,
+            reg @ (0xD000..=0xD003 | 0xE000..=0xE003) => {
+                let index = usize::from(reg & 0b11) + if reg >= 0xE000 { 4 } else { 0 };
+                self.chr[index] = data;
+            }
+            0xF000 => self.irq.latch = data,
+            0xF001 => self.irq.write_control(data),
+            0xF002 => self.irq.acknowledge(),
+            _ => trace!("write {data:02X} to VRC6 {addr:04X} ignored"),
+        }
+    }
+
+    fn ppu_read(&mut self, addr: u16) -> u8 {
+        let bank = self.chr_bank(addr);
+        self.cart.chr_rom[banked(&self.cart.chr_rom, CHR_BANK_SIZE, bank, addr)]
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        if self.cart.chr_ram {
+            let bank = self.chr_bank(addr);
+            let offset = banked(&self.cart.chr_rom, CHR_BANK_SIZE, bank, addr);
+            self.cart.chr_rom[offset] = data;
+        }
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        match (self.banking >> 2) & 0b11 {
+            0 => Mirroring::Vertical,
+            1 => Mirroring::Horizontal,
+            2 => Mirroring::SingleScreenLower,
+            _ => Mirroring::SingleScreenUpper,
+        }
+    }
+
+    fn irq(&self) -> bool {
+        self.irq.pending
+    }
+
+    fn cpu_clock(&mut self) {
+        self.irq.clock();
+        if !self.audio_halt {
+            for pulse in &mut self.pulses {
+                pulse.clock();
+            }
+            self.saw.clock();
+        }
+    }
+
+    fn expansion_audio(&self) -> f32 {
+        let level = self.pulses[0].output() + self.pulses[1].output() + self.saw.output();
+        f32::from(level) * AUDIO_SCALE
+    }
+
+    fn save_state(&self) -> Vec<u8> {
+        state::to_bytes(self)
+    }
+
+    fn load_state(&mut self, state: &[u8]) -> Result<()> {
+        load_board(self, state)?;
+        if self.saw.step >= 14 {
+            return Err(NesError::InvalidState(
+                "save state's VRC6 sawtooth step is out of range".into(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// The IRQ counter shared by Konami's VRC4, VRC6 and VRC7. It counts up from the latch and fires
+/// when it overflows, either every CPU cycle or once per scanline through a prescaler.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+struct VrcIrq {
+    latch: u8,
+    counter: u8,
+    prescaler: i16,
+    enabled: bool,
+    enable_after_ack: bool,
+    cycle_mode: bool,
+    pending: bool,
+}
+
+impl VrcIrq {
+    fn write_control(&mut self, data: u8) {
+        self.enable_after_ack = data & 0b001 != 0;
+        self.enabled = data & 0b010 != 0;
+        self.cycle_mode = data & 0b100 != 0;
+        self.pending = false;
+        if self.enabled {
+            self.counter = self.latch;
+            self.prescaler = PRESCALER_PERIOD;
+        }
+    }
+
+    fn acknowledge(&mut self) {
+        self.pending = false;
+        self.enabled = self.enable_after_ack;
+    }
+
+    fn clock(&mut self) {
+        if !self.enabled {
+            return;
+        }
+        if !self.cycle_mode {
+            self.prescaler -= 3;
+            if self.prescaler > 0 {
+                return;
+            }
+            self.prescaler += PRESCALER_PERIOD;
+        }
+        if self.counter == 0xFF {
+            self.counter = self.latch;
+            self.pending = true;
+        } else {
+            self.counter += 1;
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+struct Vrc6Pulse {
+    volume: u8,
+    duty: u8,
+    /// Ignore the duty cycle and output the volume constantly.
+    constant: bool,
+    period: u16,
+    enabled: bool,
+    timer: u16,
+    step: u8,
+}
+
+impl Vrc6Pulse {
+    fn write(&mut self, reg: u16, data: u8) {
+        match reg {
+            0 => {
+                self.volume = data & 0x0F;
+                self.duty = (data >> 4) & 0b111;
+                self.constant = data & 0x80 != 0;
+            }
+            1 => self.period = (self.period & 0x0F00) | u16::from(data),
+            _ => {
+                self.period = (self.period & 0x00FF) | (u16::from(data & 0x0F) << 8);
+                self.enabled = data & 0x80 != 0;
+                if !self.enabled {
+                    self.step = 0;
+                }
+            }
+        }
+    }
+
+    fn clock(&mut self) {
+        if !self.enabled {
+            return;
+        }
+        if self.timer == 0 {
+            self.timer = self.period;
+            self.step = (self.step + 1) & 0x0F;
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    fn output(&self) -> u8 {
+        if self.enabled && (self.constant || self.step <= self.duty) {
+            self.volume
+        } else {
+            0
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+struct Vrc6Saw {
+    rate: u8,
+    period: u16,
+    enabled: bool,
+    timer: u16,
+    /// Counts the 14 timer clocks of one sawtooth period; the accumulator advances on every
+    /// other one.
+    step: u8,
+    accumulator: u8,
+}
+
+impl Vrc6Saw {
+    fn write(&mut self, reg: u16, data: u8) {
+        match reg {
+            0 => self.rate = data & 0x3F,
+            1 => self.period = (self.period & 0x0F00) | u16::from(data),
+            _ => {
+                self.period = (self.period & 0x00FF) | (u16::from(data & 0x0F) << 8);
+                self.enabled = data & 0x80 != 0;
+                if !self.enabled {
+                    self.step = 0;
+                    self.accumulator = 0;
+                }
+            }
+        }
+    }
+
+    fn clock(&mut self) {
+        if !self.enabled {
+            return;
+        }
+        if self.timer > 0 {
+            self.timer -= 1;
+            return;
+        }
+        self.timer = self.period;
+        self.step += 1;
+        if self.step == 14 {
+            self.step = 0;
+            self.accumulator = 0;
+        } else if self.step & 1 == 0 {
+            self.accumulator = self.accumulator.wrapping_add(self.rate);
+        }
+    }
+
+    fn output(&self) -> u8 {
+        self.accumulator >> 3
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::test_image;
+
+    fn vrc6(swapped_lines: bool) -> Vrc6 {
+        // 8 banks of 16KB PRG, 64 banks of 1KB CHR.
+        Vrc6::new(
+            Cartridge::new(&test_image(24, 8, 8, 0)).unwrap(),
+            swapped_lines,
+        )
+    }
+
+    #[test]
+    fn test_prg_banks() {
+        let mut vrc6 = vrc6(false);
+        vrc6.cpu_write(0x8000, 3);
+        vrc6.cpu_write(0xC000, 9);
+
+        assert_eq!(vrc6.cpu_read(0x8000), Some(3));
+        assert_eq!(vrc6.cpu_read(0xC000), Some(4));
+        assert_eq!(vrc6.cpu_read(0xE000), Some(7))
+    }
+
+    #[test]
+    fn test_chr_banks_and_mirroring() {
+        let mut vrc6 = vrc6(false);
+        vrc6.cpu_write(0xD001, 8);
+        vrc6.cpu_write(0xE003, 63);
+        assert_eq!(vrc6.ppu_read(0x0400), 0x81);
+        assert_eq!(vrc6.ppu_read(0x1C00), 0x87);
+
+        vrc6.cpu_write(0xB003, 0b0000_0100);
+        assert_eq!(vrc6.mirroring(), Mirroring::Horizontal)
+    }
+
+    #[test]
+    fn test_mapper_26_swaps_register_lines() {
+        let mut vrc6 = vrc6(true);
+        // $D002 on mapper 26 is R1.
+        vrc6.cpu_write(0xD002, 8);
+
+        assert_eq!(vrc6.ppu_read(0x0400), 0x81)
+    }
+
+    #[test]
+    fn test_cycle_mode_irq() {
+        let mut vrc6 = vrc6(false);
+        vrc6.cpu_write(0xF000, 0xFD);
+        vrc6.cpu_write(0xF001, 0b110);
+
+        vrc6.cpu_clock();
+        vrc6.cpu_clock();
+        assert!(!vrc6.irq());
+        vrc6.cpu_clock();
+        assert!(vrc6.irq());
+
+        vrc6.cpu_write(0xF002, 0);
+        assert!(!vrc6.irq())
+    }
+
+    #[test]
+    fn test_scanline_mode_irq() {
+        let mut vrc6 = vrc6(false);
+        vrc6.cpu_write(0xF000, 0xFF);
+        vrc6.cpu_write(0xF001, 0b010);
+
+        for _ in 0..113 {
+            vrc6.cpu_clock();
+        }
+        assert!(!vrc6.irq());
+        vrc6.cpu_clock();
+        assert!(vrc6.irq())
+    }
+
+    #[test]
+    fn test_expansion_audio() {
+        let mut vrc6 = vrc6(false);
+        assert_eq!(vrc6.expansion_audio(), 0.0);
+
+        // Pulse 1 in constant mode at volume 10.
+        vrc6.cpu_write(0x9000, 0x8A);
+        vrc6.cpu_write(0x9002, 0x80);
+        assert_eq!(vrc6.expansion_audio(), 10.0 * AUDIO_SCALE);
+
+        // The sawtooth accumulates its rate every other clock, outputting the top five bits.
+        vrc6.cpu_write(0xB000, 0x20);
+        vrc6.cpu_write(0xB002, 0x80);
+        for _ in 0..4 {
+            vrc6.cpu_clock();
+        }
+        assert_eq!(vrc6.saw.output(), 0x40 >> 3);
+
+        vrc6.cpu_write(0x9003, 1);
+        for _ in 0..4 {
+            vrc6.cpu_clock();
+        }
+        assert_eq!(vrc6.saw.output(), 0x40 >> 3)
+    }
+}
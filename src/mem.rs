@@ -0,0 +1,54 @@
+use alloc::boxed::Box;
+
+/// Anything the CPU can address. Reads take `&mut self` because on the NES many of them have side
+/// effects (clearing PPU flags, shifting controller bits).
+pub trait Mem {
+    fn read(&mut self, addr: u16) -> u8;
+
+    fn write(&mut self, addr: u16, data: u8);
+
+    /// Reads a little-endian word.
+    fn read_u16(&mut self, addr: u16) -> u16 {
+        let lo = self.read(addr);
+        let hi = self.read(addr.wrapping_add(1));
+        u16::from_le_bytes([lo, hi])
+    }
+
+    /// Writes a little-endian word.
+    fn write_u16(&mut self, addr: u16, data: u16) {
+        let [lo, hi] = data.to_le_bytes();
+        self.write(addr, lo);
+        self.write(addr.wrapping_add(1), hi);
+    }
+}
+
+/// A flat 64KB address space with no mapping at all. Handy for tests and for running bare 6502
+/// programs.
+#[derive(Debug, Clone)]
+pub struct FlatMemory {
+    data: Box<[u8; 0x10000]>,
+}
+
+impl FlatMemory {
+    pub fn new() -> Self {
+        Self {
+            data: Box::new([0; 0x10000]),
+        }
+    }
+}
+
+impl Default for FlatMemory {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Mem for FlatMemory {
+    fn read(&mut self, addr: u16) -> u8 {
+        self.data[addr as usize]
+    }
+
+    fn write(&mut self, addr: u16, data: u8) {
+        self.data[addr as usize] = data;
+    }
+}
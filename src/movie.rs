@@ -0,0 +1,371 @@
+//! Input movies: the controller state of every frame, recorded so a run can be played back exactly.
+//!
+//! Movies are stored in FCEUX's FM2 text format, so they can be traded with it. A header of
+//! `key value` lines is followed by one line per frame:
+//!
+//! ```text
+//! version 3
+//! romFilename smb
+//! port0 1
+//! port1 1
+//! |0|R..U...A|........||
+//! ```
+//!
+//! Each frame line holds the reset commands, then a `RLDUTSBA` field per controller with a `.` for
+//! every released button. Only standard controllers in the two ports are supported, which is what
+//! nearly every FM2 file has. Playback performs a frame's resets before setting its buttons, the
+//! way FCEUX does.
+
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt::Write;
+
+use crate::bus::{Bus, CONTROLLER_PORTS};
+use crate::error::{NesError, Result};
+use crate::input::Joypad;
+use crate::nes::Nes;
+
+/// FM2's button field order. Character i is button 7 - i in [`Button`](crate::input::Button)
+/// order.
+const BUTTON_CHARS: &[u8; 8] = b"RLDUTSBA";
+
+/// Frame command bits.
+pub const COMMAND_SOFT_RESET: u8 = 0b01;
+pub const COMMAND_HARD_RESET: u8 = 0b10;
+
+/// One frame of input.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MovieFrame {
+    /// Resets to perform before the frame, `COMMAND_*` bits.
+    pub commands: u8,
+    /// Each port's buttons, as in [`Joypad::buttons`].
+    pub buttons: [u8; CONTROLLER_PORTS],
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Movie {
+    pub rom_filename: String,
+    /// FCEUX's "base64:..." MD5 of the ROM, kept as is.
+    pub rom_checksum: String,
+    pub guid: String,
+    pub pal: bool,
+    pub rerecord_count: u32,
+    pub comments: Vec<String>,
+    frames: Vec<MovieFrame>,
+}
+
+impl Movie {
+    /// An empty movie, ready to record into.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn frames(&self) -> &[MovieFrame] {
+        &self.frames
+    }
+
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    pub fn push(&mut self, frame: MovieFrame) {
+        self.frames.push(frame);
+    }
+
+    /// Drops every frame from `len` on, to re-record from there.
+    pub fn truncate(&mut self, len: usize) {
+        self.frames.truncate(len);
+    }
+
+    /// Appends what the console sees from the joypads on `bus` this frame. Turbo is recorded as
+    /// the presses and releases it produces, so movies should be played back with it off.
+    /// Anything but a joypad in a port records as nothing pressed.
+    pub fn record(&mut self, bus: &Bus) {
+        let mut frame = MovieFrame::default();
+        for (port, buttons) in frame.buttons.iter_mut().enumerate() {
+            *buttons = bus
+                .controller::<Joypad>(port)
+                .map_or(0, |joypad| joypad.output());
+        }
+        self.frames.push(frame);
+    }
+
+    /// Performs frame `index`'s resets on `nes`, a hard reset winning over a soft one, and sets
+    /// its joypads to the frame's buttons. Returns `false`, leaving the console alone, once the
+    /// movie has run out.
+    pub fn play(&self, index: usize, nes: &mut Nes) -> bool {
+        let Some(frame) = self.frames.get(index) else {
+            return false;
+        };
+        if frame.commands & COMMAND_HARD_RESET != 0 {
+            nes.power_cycle();
+        } else if frame.commands & COMMAND_SOFT_RESET != 0 {
+            nes.soft_reset();
+        }
+        for (port, &buttons) in frame.buttons.iter().enumerate() {
+            if let Some(joypad) = nes.bus_mut().joypad_mut(port) {
+                joypad.set_buttons(buttons);
+            }
+        }
+        true
+    }
+
+    /// Parses an FM2 movie.
+    ///
+    /// # Errors
+    /// Returns [`NesError::InvalidState`] for a malformed file, a binary one, or one using input
+    /// devices other than standard controllers.
+    pub fn parse(fm2: &str) -> Result<Movie> {
+        let mut movie = Movie::new();
+        let mut version = None;
+        let mut ports = [true; CONTROLLER_PORTS];
+        for (number, line) in fm2.lines().enumerate() {
+            let line = line.trim_end_matches('\r');
+            if line.starts_with('|') {
+                movie.frames.push(parse_frame(line, ports).ok_or_else(|| {
+                    NesError::InvalidState(format!("bad FM2 frame on line {}", number + 1))
+                })?);
+                continue;
+            }
+            if line.is_empty() {
+                continue;
+            }
+            let (key, value) = line.split_once(' ').unwrap_or((line, ""));
+            match key {
+                "version" => version = Some(value),
+                "romFilename" => movie.rom_filename = value.to_string(),
+                "romChecksum" => movie.rom_checksum = value.to_string(),
+                "guid" => movie.guid = value.to_string(),
+                "palFlag" => movie.pal = value == "1",
+                "rerecordCount" => movie.rerecord_count = value.parse().unwrap_or(0),
+                "comment" => movie.comments.push(value.to_string()),
+                "binary" | "fourscore" | "microphone" if value != "0" => {
+                    return Err(NesError::InvalidState(format!("unsupported FM2 {key}")));
+                }
+                "port0" | "port1" => {
+                    // 0 is nothing plugged in, 1 a standard controller.
+                    let port = usize::from(key == "port1");
+                    ports[port] = match value {
+                        "0" => false,
+                        "1" => true,
+                        _ => {
+                            return Err(NesError::InvalidState(format!(
+                                "unsupported FM2 device {value} in {key}"
+                            )))
+                        }
+                    };
+                }
+                _ => {}
+            }
+        }
+        if version != Some("3") {
+            return Err(NesError::InvalidState("not an FM2 version 3 movie".into()));
+        }
+        Ok(movie)
+    }
+
+    /// The movie in FM2 format.
+    pub fn to_fm2(&self) -> String {
+        let mut fm2 = String::new();
+        // Writing to a String can't fail.
+        let _ = write!(
+            fm2,
+            "version 3\nemuVersion 22020\nrerecordCount {}\npalFlag {}\nromFilename {}\n\
+             romChecksum {}\nguid {}\nfourscore 0\nmicrophone 0\nport0 1\nport1 1\nport2 0\n\
+             FDS 0\nNewPPU 0\n",
+            self.rerecord_count,
+            u8::from(self.pal),
+            self.rom_filename,
+            self.rom_checksum,
+            self.guid,
+        );
+        for comment in &self.comments {
+            let _ = writeln!(fm2, "comment {comment}");
+        }
+        for frame in &self.frames {
+            let _ = write!(fm2, "|{}|", frame.commands);
+            for buttons in frame.buttons {
+                for (i, &name) in BUTTON_CHARS.iter().enumerate() {
+                    let pressed = buttons & (0x80 >> i) != 0;
+                    fm2.push(if pressed { char::from(name) } else { '.' });
+                }
+                fm2.push('|');
+            }
+            fm2.push_str("|\n");
+        }
+        fm2
+    }
+}
+
+/// `|commands|port0|port1|port2|`, with empty fields for empty ports.
+fn parse_frame(line: &str, ports: [bool; CONTROLLER_PORTS]) -> Option<MovieFrame> {
+    let mut fields = line.split('|').skip(1);
+    let mut frame = MovieFrame {
+        commands: fields.next()?.trim().parse().ok()?,
+        ..MovieFrame::default()
+    };
+    for (buttons, connected) in frame.buttons.iter_mut().zip(ports) {
+        let field = fields.next()?.as_bytes();
+        if !connected {
+            continue;
+        }
+        if field.len() != BUTTON_CHARS.len() {
+            return None;
+        }
+        for (i, &c) in field.iter().enumerate() {
+            if c != b'.' && c != b' ' {
+                *buttons |= 0x80 >> i;
+            }
+        }
+    }
+    Some(frame)
+}
+
+#[cfg(feature = "std")]
+impl Movie {
+    /// Reads and parses an FM2 file.
+    ///
+    /// # Errors
+    /// Returns [`NesError::Io`] if the file can't be read, or [`NesError::InvalidState`] if it
+    /// isn't a movie this emulator can play.
+    pub fn from_file(path: impl AsRef<std::path::Path>) -> Result<Movie> {
+        Movie::parse(&std::fs::read_to_string(path)?)
+    }
+
+    /// Writes the movie as an FM2 file.
+    ///
+    /// # Errors
+    /// Returns [`NesError::Io`] if the file can't be written.
+    pub fn save_file(&self, path: impl AsRef<std::path::Path>) -> Result<()> {
+        std::fs::write(path, self.to_fm2())?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::{test_image, Cartridge};
+    use crate::input::Button;
+
+    const FM2: &str = "version 3\n\
+                       emuVersion 22020\n\
+                       rerecordCount 7\n\
+                       palFlag 0\n\
+                       romFilename smb\n\
+                       romChecksum base64:jjYwGG411HcjG/j9UOVM3Q==\n\
+                       guid 01234567-89AB-CDEF-0123-456789ABCDEF\n\
+                       fourscore 0\n\
+                       port0 1\n\
+                       port1 1\n\
+                       port2 0\n\
+                       comment author someone\n\
+                       |0|........|........||\n\
+                       |0|R......A|...U....||\n\
+                       |2|.L..T.B.|........||\n";
+
+    #[test]
+    fn test_parse() {
+        let movie = Movie::parse(FM2).unwrap();
+        assert_eq!(movie.rom_filename, "smb");
+        assert_eq!(movie.rerecord_count, 7);
+        assert_eq!(movie.comments, ["author someone"]);
+        assert_eq!(movie.len(), 3);
+        assert_eq!(
+            movie.frames()[1].buttons,
+            [Button::Right.mask() | Button::A.mask(), Button::Up.mask()]
+        );
+        assert_eq!(movie.frames()[2].commands, COMMAND_HARD_RESET);
+        assert_eq!(
+            movie.frames()[2].buttons[0],
+            Button::Left.mask() | Button::Start.mask() | Button::B.mask()
+        )
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let movie = Movie::parse(FM2).unwrap();
+        assert_eq!(Movie::parse(&movie.to_fm2()).unwrap(), movie)
+    }
+
+    #[test]
+    fn test_rejects_unsupported_movies() {
+        assert!(matches!(
+            Movie::parse("version 2\n"),
+            Err(NesError::InvalidState(_))
+        ));
+        assert!(matches!(
+            Movie::parse("version 3\nfourscore 1\n"),
+            Err(NesError::InvalidState(_))
+        ));
+        assert!(matches!(
+            Movie::parse("version 3\nport1 2\n"),
+            Err(NesError::InvalidState(_))
+        ));
+        assert!(matches!(
+            Movie::parse("version 3\n|0|RLD|........||\n"),
+            Err(NesError::InvalidState(_))
+        ))
+    }
+
+    fn console() -> Nes {
+        Nes::new(Cartridge::new(&test_image(0, 1, 1, 0)).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_record_and_play() {
+        let mut bus = Bus::new();
+        let mut movie = Movie::new();
+        bus.joypad_mut(0).unwrap().set_button(Button::A, true);
+        movie.record(&bus);
+        bus.joypad_mut(0).unwrap().set_button(Button::A, false);
+        bus.joypad_mut(1).unwrap().set_button(Button::Down, true);
+        movie.record(&bus);
+
+        let mut replay = console();
+        assert!(movie.play(0, &mut replay));
+        assert!(replay.bus_mut().joypad_mut(0).unwrap().pressed(Button::A));
+        assert!(movie.play(1, &mut replay));
+        let bus = replay.bus_mut();
+        assert!(!bus.joypad_mut(0).unwrap().pressed(Button::A));
+        assert!(bus.joypad_mut(1).unwrap().pressed(Button::Down));
+        assert!(!movie.play(2, &mut replay))
+    }
+
+    #[test]
+    fn test_play_performs_resets() {
+        let mut movie = Movie::new();
+        for commands in [0, COMMAND_SOFT_RESET, COMMAND_HARD_RESET] {
+            movie.push(MovieFrame {
+                commands,
+                buttons: [Button::Start.mask(), 0],
+            });
+        }
+        let mut nes = console();
+        let reset_vector = nes.cpu().pc;
+        let scribble = |nes: &mut Nes| {
+            nes.cpu_mut().pc = 0x1234;
+            nes.cpu_mut().mem_write(0x0300, 0x42);
+        };
+
+        scribble(&mut nes);
+        assert!(movie.play(0, &mut nes));
+        assert_eq!(nes.cpu().pc, 0x1234);
+
+        // A soft reset keeps work RAM, a hard one clears it; both restart the program.
+        assert!(movie.play(1, &mut nes));
+        assert_eq!(nes.cpu().pc, reset_vector);
+        assert_eq!(nes.cpu_mut().mem_read(0x0300), 0x42);
+        scribble(&mut nes);
+        assert!(movie.play(2, &mut nes));
+        assert_eq!(nes.cpu().pc, reset_vector);
+        assert_eq!(nes.cpu_mut().mem_read(0x0300), 0);
+        // The frame's buttons are pressed after the reset.
+        assert!(nes.bus_mut().joypad_mut(0).unwrap().pressed(Button::Start))
+    }
+}
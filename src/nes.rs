@@ -0,0 +1,731 @@
+//! The whole console: a CPU wired to a [`Bus`] with everything else hanging off it.
+//!
+//! [`Nes`] is what frontends drive. It runs an instruction at a time, lets the rest of the machine
+//! catch up after each one and delivers the interrupts they raise, and offers coarser steps on top
+//! of that: a scanline or a whole frame.
+//!
+//! How fast emulated time passes is up to the frontend, which sleeps for
+//! [`Nes::frame_duration`] between frames. That follows the speed setting: a fraction or multiple
+//! of normal speed, or fast-forward, which doesn't wait at all. The audio from
+//! [`Nes::take_samples`] keeps its pitch at other speeds by dropping or repeating short blocks of
+//! it; fast-forward is silent.
+//!
+//! [`Nes::save_state`] freezes the whole machine into bytes and [`Nes::load_state`] puts it back,
+//! in the format described in [`state`](crate::state). With rewind on, a snapshot is also taken
+//! every few frames for [`Nes::rewind`] to go back to.
+
+use alloc::boxed::Box;
+use alloc::format;
+use alloc::vec::Vec;
+use core::time::Duration;
+
+use crate::bus::{Bus, CONTROLLER_PORTS};
+use crate::cartridge::Cartridge;
+use crate::cpu::{RamInit, CPU};
+use crate::error::{NesError, Result};
+use crate::input::ControllerPort;
+use crate::region::Region;
+use crate::rewind::{RewindBuffer, RewindConfig};
+use crate::state::{self, StateReader, StateWriter};
+
+/// The slowest speed [`Nes::set_speed`] accepts.
+pub const MIN_SPEED: f64 = 0.05;
+
+/// Samples in each block of audio dropped or repeated to keep up with the speed. About 6ms at
+/// 44.1kHz: short enough that the joins aren't heard as stutter.
+const AUDIO_BLOCK: usize = 256;
+
+pub struct Nes {
+    cpu: CPU<Bus>,
+    speed: f64,
+    fast_forward: bool,
+    /// APU samples that don't yet make up a whole block.
+    audio: Vec<f32>,
+    /// How many times the next block is due to be played, in fractions of a block.
+    audio_phase: f64,
+    /// Save states are only loaded into the cartridge they came from.
+    rom_checksum: u32,
+    rewind: Option<RewindBuffer>,
+    /// What work RAM is filled with on a power cycle.
+    ram_init: RamInit,
+}
+
+/// Sets up a [`Nes`] before powering it on.
+pub struct NesBuilder {
+    cart: Cartridge,
+    region: Option<Region>,
+    sample_rate: Option<u32>,
+    ram_init: RamInit,
+    /// Devices to plug in instead of the standard controllers, by port.
+    controllers: Vec<(usize, Option<Box<dyn ControllerPort>>)>,
+}
+
+impl NesBuilder {
+    /// Runs the console as `region` instead of what the cartridge's header says.
+    pub fn region(mut self, region: Region) -> Self {
+        self.region = Some(region);
+        self
+    }
+
+    /// The audio sample rate, [`DEFAULT_SAMPLE_RATE`](crate::apu::DEFAULT_SAMPLE_RATE) unless
+    /// set.
+    pub fn sample_rate(mut self, hz: u32) -> Self {
+        self.sample_rate = Some(hz);
+        self
+    }
+
+    /// What work RAM holds at power-on, all zeros unless set.
+    pub fn ram_init(mut self, ram_init: RamInit) -> Self {
+        self.ram_init = ram_init;
+        self
+    }
+
+    /// Plugs `device` into `port` (0 or 1), or leaves the port empty with `None`. Both ports have
+    /// a standard controller unless set.
+    pub fn controller(mut self, port: usize, device: Option<Box<dyn ControllerPort>>) -> Self {
+        self.controllers.push((port, device));
+        self
+    }
+
+    /// Powers the console on.
+    ///
+    /// # Errors
+    /// Returns [`NesError::InvalidConfig`](crate::NesError::InvalidConfig) for a sample rate of
+    /// zero or a controller port that doesn't exist, and
+    /// [`NesError::UnsupportedMapper`](crate::NesError::UnsupportedMapper) when the cartridge's
+    /// board isn't implemented.
+    pub fn build(self) -> Result<Nes> {
+        if self.sample_rate == Some(0) {
+            return Err(NesError::InvalidConfig("sample rate can't be 0 Hz".into()));
+        }
+        if let Some(&(port, _)) = self
+            .controllers
+            .iter()
+            .find(|(port, _)| *port >= CONTROLLER_PORTS)
+        {
+            return Err(NesError::InvalidConfig(format!(
+                "there is no controller port {port}"
+            )));
+        }
+
+        let region = self.region.unwrap_or(self.cart.region);
+        let rom_checksum = state::rom_checksum(&self.cart);
+        let mut bus = Bus::with_region(self.cart, region)?;
+        if let Some(hz) = self.sample_rate {
+            bus.apu_mut().set_sample_rate(hz);
+        }
+        for (port, device) in self.controllers {
+            bus.set_controller(port, device);
+        }
+        let mut cpu = CPU::with_bus(bus);
+        cpu.power_on(self.ram_init);
+        Ok(Nes {
+            cpu,
+            speed: 1.0,
+            fast_forward: false,
+            audio: Vec::new(),
+            audio_phase: 0.0,
+            rom_checksum,
+            rewind: None,
+            ram_init: self.ram_init,
+        })
+    }
+}
+
+impl Nes {
+    /// Powers on a console with `cart` inserted, in the region its header asks for.
+    ///
+    /// # Errors
+    /// Returns [`NesError::UnsupportedMapper`](crate::NesError::UnsupportedMapper) when the
+    /// cartridge's board isn't implemented.
+    pub fn new(cart: Cartridge) -> Result<Nes> {
+        Nes::builder(cart).build()
+    }
+
+    /// Starts setting up a console with `cart` inserted.
+    pub fn builder(cart: Cartridge) -> NesBuilder {
+        NesBuilder {
+            cart,
+            region: None,
+            sample_rate: None,
+            ram_init: RamInit::default(),
+            controllers: Vec::new(),
+        }
+    }
+
+    pub fn region(&self) -> Region {
+        self.cpu.bus.region()
+    }
+
+    pub fn cpu(&self) -> &CPU<Bus> {
+        &self.cpu
+    }
+
+    pub fn cpu_mut(&mut self) -> &mut CPU<Bus> {
+        &mut self.cpu
+    }
+
+    pub fn bus(&self) -> &Bus {
+        &self.cpu.bus
+    }
+
+    pub fn bus_mut(&mut self) -> &mut Bus {
+        &mut self.cpu.bus
+    }
+
+    /// The picture as RGBA8. See [`Ppu::frame`](crate::ppu::Ppu::frame).
+    pub fn frame(&self) -> &[u8] {
+        self.cpu.bus.ppu().frame()
+    }
+
+    /// The emulation speed as a multiple of the console's, 1.0 being normal.
+    pub fn speed(&self) -> f64 {
+        self.speed
+    }
+
+    /// Sets the emulation speed, e.g. 0.5 for slow motion. Clamped to at least [`MIN_SPEED`].
+    pub fn set_speed(&mut self, speed: f64) {
+        self.speed = speed.max(MIN_SPEED);
+    }
+
+    pub fn fast_forward(&self) -> bool {
+        self.fast_forward
+    }
+
+    /// Runs as fast as the host can while set, overriding the speed.
+    pub fn set_fast_forward(&mut self, fast_forward: bool) {
+        self.fast_forward = fast_forward;
+    }
+
+    /// How long a frame should take in real time at the current speed, for frontends to pace
+    /// [`run_frame`](Self::run_frame) with. `None` during fast-forward: don't wait at all.
+    pub fn frame_duration(&self) -> Option<Duration> {
+        if self.fast_forward {
+            return None;
+        }
+        let frame_rate = self.cpu.bus.clock().timing().frame_rate_hz;
+        Some(Duration::from_secs_f64(1.0 / (frame_rate * self.speed)))
+    }
+
+    /// Moves the audio produced since the last call onto the end of `samples`, at the APU's
+    /// [`sample_rate`](crate::apu::Apu::sample_rate) in real time.
+    ///
+    /// Away from normal speed, blocks of samples are dropped (faster) or repeated (slower) so
+    /// that there is as much audio as real time has passed without changing its pitch.
+    /// Fast-forward produces none.
+    pub fn take_samples(&mut self, samples: &mut Vec<f32>) {
+        self.cpu.bus.apu_mut().take_samples(&mut self.audio);
+        if self.fast_forward {
+            self.audio.clear();
+            return;
+        }
+        if self.speed == 1.0 {
+            samples.append(&mut self.audio);
+            return;
+        }
+        let whole = self.audio.len() / AUDIO_BLOCK * AUDIO_BLOCK;
+        for block in self.audio[..whole].chunks_exact(AUDIO_BLOCK) {
+            self.audio_phase += 1.0 / self.speed;
+            while self.audio_phase >= 1.0 {
+                samples.extend_from_slice(block);
+                self.audio_phase -= 1.0;
+            }
+        }
+        self.audio.drain(..whole);
+    }
+
+    /// The whole console's state. Host settings, like the speed and the APU's filters, aren't part
+    /// of it.
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut writer = StateWriter::new();
+        state::write_header(&mut writer, self.region(), self.rom_checksum);
+        let cpu = &self.cpu;
+        writer.write(&(
+            cpu.reg_a,
+            cpu.reg_x,
+            cpu.reg_y,
+            &cpu.status,
+            cpu.pc,
+            cpu.sp,
+            cpu.cycles,
+        ));
+        cpu.bus.save_state(&mut writer);
+        writer.into_bytes()
+    }
+
+    /// Puts the console back in a state from [`save_state`](Self::save_state). It has to have been
+    /// saved with the same cartridge, in the same region and with the same controller devices
+    /// plugged in. Nothing changes if it can't be loaded. The rewind history is cleared, since it
+    /// belongs to a different timeline.
+    ///
+    /// # Errors
+    /// Returns [`NesError::InvalidState`](crate::NesError::InvalidState) when `state` isn't a
+    /// save state from this version of the emulator or doesn't fit this console.
+    pub fn load_state(&mut self, state: &[u8]) -> Result<()> {
+        self.restore_state(state)?;
+        if let Some(buffer) = &mut self.rewind {
+            buffer.clear();
+        }
+        Ok(())
+    }
+
+    /// [`load_state`](Self::load_state), keeping the rewind history.
+    fn restore_state(&mut self, state: &[u8]) -> Result<()> {
+        let backup = self.save_state();
+        self.apply_state(state).inspect_err(|_| {
+            self.apply_state(&backup)
+                .expect("a state the console just saved loads");
+        })
+    }
+
+    fn apply_state(&mut self, state: &[u8]) -> Result<()> {
+        let mut reader = StateReader::new(state);
+        state::read_header(&mut reader, self.region(), self.rom_checksum)?;
+        let (reg_a, reg_x, reg_y, status, pc, sp, cycles) = reader.read()?;
+        self.cpu.bus.load_state(&mut reader)?;
+        reader.finish()?;
+        let cpu = &mut self.cpu;
+        (cpu.reg_a, cpu.reg_x, cpu.reg_y) = (reg_a, reg_x, reg_y);
+        (cpu.status, cpu.pc, cpu.sp, cpu.cycles) = (status, pc, sp, cycles);
+        Ok(())
+    }
+
+    /// Turns rewinding on with `config`, or off with `None`. Either way the history starts over.
+    pub fn set_rewind(&mut self, config: Option<RewindConfig>) {
+        self.rewind = config.map(RewindBuffer::new);
+    }
+
+    /// The rewind history, while rewinding is on.
+    pub fn rewind_buffer(&self) -> Option<&RewindBuffer> {
+        self.rewind.as_ref()
+    }
+
+    /// Goes back at least `frames` frames, to the newest snapshot that old, or as far as the
+    /// history goes. Returns how many frames back that was: 0 with rewinding off or nothing
+    /// saved yet. Called every frame with a `frames` of 1, it plays the game backwards one
+    /// snapshot at a time.
+    ///
+    /// # Errors
+    /// Returns [`NesError::InvalidState`](crate::NesError::InvalidState) if the snapshot can't
+    /// be loaded, which only a bug would cause.
+    pub fn rewind(&mut self, frames: u32) -> Result<u64> {
+        let now = self.cpu.bus.ppu().frame_count();
+        let target = now.saturating_sub(u64::from(frames));
+        let Some((frame, state)) = self
+            .rewind
+            .as_mut()
+            .and_then(|buffer| buffer.rewind_to(target))
+            .map(|(frame, state)| (frame, state.to_vec()))
+        else {
+            return Ok(0);
+        };
+        self.restore_state(&state)?;
+        Ok(now.saturating_sub(frame))
+    }
+
+    /// Pulls the reset line, as the console's reset button does. Work RAM and the cartridge's
+    /// memory survive; the CPU restarts from the reset vector with A, X and Y kept, the PPU and
+    /// APU go quiet until the game sets them up again, and boards that see the reset react to it.
+    pub fn soft_reset(&mut self) {
+        self.cpu.bus.reset();
+        self.cpu.reset();
+    }
+
+    /// Switches the console off and on again. Everything starts from its power-on state, with
+    /// work RAM filled the way the builder's [`ram_init`](NesBuilder::ram_init) asked; only
+    /// battery-backed cartridge RAM is kept. Settings, controller devices and cheats stay, and the
+    /// rewind history is cleared.
+    pub fn power_cycle(&mut self) {
+        self.cpu.bus.power_cycle();
+        self.cpu.power_on(self.ram_init);
+        self.audio.clear();
+        self.audio_phase = 0.0;
+        if let Some(buffer) = &mut self.rewind {
+            buffer.clear();
+        }
+    }
+
+    /// Executes one instruction, lets the rest of the console catch up with it, then takes an
+    /// NMI or IRQ if one was raised meanwhile. Returns the CPU cycles that took, stalls and
+    /// interrupt sequence included.
+    ///
+    /// # Errors
+    /// Returns [`NesError::CpuFault`](crate::NesError::CpuFault) when the CPU fetches an opcode it
+    /// can't execute.
+    pub fn step_instruction(&mut self) -> Result<u32> {
+        let cycles = self.cpu.step()?;
+        let mut total = self.cpu.bus.tick(cycles);
+
+        // NMI wins when both are pending; the IRQ line stays held and is taken after the handler
+        // clears the I flag again.
+        let interrupt = if self.cpu.bus.ppu_mut().poll_nmi() {
+            self.cpu.nmi()
+        } else if self.cpu.bus.irq() {
+            self.cpu.irq()
+        } else {
+            0
+        };
+        if interrupt > 0 {
+            total += self.cpu.bus.tick(interrupt);
+        }
+        Ok(total)
+    }
+
+    /// Runs until the PPU moves on to the next scanline. The instruction that gets it there is
+    /// finished, so the PPU can end up a few dots in.
+    ///
+    /// # Errors
+    /// Returns [`NesError::CpuFault`](crate::NesError::CpuFault) when the CPU fetches an opcode it
+    /// can't execute.
+    pub fn step_scanline(&mut self) -> Result<()> {
+        let scanline = self.cpu.bus.ppu().scanline();
+        while self.cpu.bus.ppu().scanline() == scanline {
+            self.step_instruction()?;
+        }
+        Ok(())
+    }
+
+    /// Runs until the PPU finishes the frame it is on, then tells the controllers a frame has
+    /// passed and takes a rewind snapshot if one is due. The picture is in [`frame`](Self::frame)
+    /// afterwards.
+    ///
+    /// # Errors
+    /// Returns [`NesError::CpuFault`](crate::NesError::CpuFault) when the CPU fetches an opcode it
+    /// can't execute.
+    pub fn run_frame(&mut self) -> Result<()> {
+        let frame = self.cpu.bus.ppu().frame_count();
+        let start = self.cpu.bus.cycles();
+        trace!("frame {frame} starts at CPU cycle {start}");
+        while self.cpu.bus.ppu().frame_count() == frame {
+            self.step_instruction()?;
+        }
+        debug!(
+            "frame {frame} done in {} CPU cycles",
+            self.cpu.bus.cycles() - start
+        );
+        self.cpu.bus.end_frame();
+        if self.rewind.as_mut().is_some_and(RewindBuffer::frame_done) {
+            let state = self.save_state();
+            let frame = self.cpu.bus.ppu().frame_count();
+            if let Some(buffer) = &mut self.rewind {
+                buffer.push(frame, state);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::cartridge::test_image;
+    use crate::input::{Joypad, Zapper};
+
+    /// A 16KB NROM cartridge running `program` from reset, with `nmi` and `irq` handlers. Each
+    /// piece is placed at the start of its own 256-byte page: $C000, $C100, $C200.
+    fn cartridge(program: &[u8], nmi: &[u8], irq: &[u8]) -> Cartridge {
+        let mut raw = test_image(0, 1, 1, 0);
+        for (page, code) in [program, nmi, irq].into_iter().enumerate() {
+            let start = 16 + page * 0x100;
+            raw[start..start + code.len()].copy_from_slice(code);
+        }
+        raw[16 + 0x3FFA..16 + 0x4000].copy_from_slice(&[0x00, 0xC1, 0x00, 0xC0, 0x00, 0xC2]);
+        Cartridge::new(&raw).unwrap()
+    }
+
+    fn console(program: &[u8], nmi: &[u8], irq: &[u8]) -> Nes {
+        Nes::new(cartridge(program, nmi, irq)).unwrap()
+    }
+
+    /// JMP to itself at $C000 + `offset`.
+    fn spin(offset: u8) -> [u8; 3] {
+        [0x4C, offset, 0xC0]
+    }
+
+    #[test]
+    fn test_run_frame_and_scanline() {
+        let mut nes = console(&spin(0), &[0x40], &[0x40]);
+        nes.step_scanline().unwrap();
+        assert_eq!(nes.bus().ppu().scanline(), 1);
+        nes.run_frame().unwrap();
+        assert_eq!(nes.bus().ppu().frame_count(), 1);
+        nes.run_frame().unwrap();
+        assert_eq!(nes.bus().ppu().frame_count(), 2);
+        assert_eq!(nes.cpu().pc, 0xC000)
+    }
+
+    #[test]
+    fn test_nmi_every_frame() {
+        // LDA #$80; STA $2000; spin. The handler counts in $10.
+        let mut program = [0xA9, 0x80, 0x8D, 0x00, 0x20, 0, 0, 0].to_vec();
+        program[5..].copy_from_slice(&spin(5));
+        let mut nes = console(&program, &[0xE6, 0x10, 0x40], &[0x40]);
+        for _ in 0..3 {
+            nes.run_frame().unwrap();
+        }
+        assert_eq!(nes.cpu_mut().mem_read(0x10), 3)
+    }
+
+    #[test]
+    fn test_apu_frame_irq() {
+        // CLI; spin. The handler acknowledges through $4015 and counts in $11.
+        let mut program = [0x58, 0, 0, 0];
+        program[1..].copy_from_slice(&spin(1));
+        let irq = [0xAD, 0x15, 0x40, 0xE6, 0x11, 0x40];
+        let mut nes = console(&program, &[0x40], &irq);
+        // A 4-step sequence is a little longer than a frame.
+        for _ in 0..4 {
+            nes.run_frame().unwrap();
+        }
+        assert_eq!(nes.cpu_mut().mem_read(0x11), 3)
+    }
+
+    #[test]
+    fn test_frame_duration() {
+        let mut nes = console(&spin(0), &[0x40], &[0x40]);
+        assert_eq!(nes.frame_duration().unwrap().as_micros(), 16_639);
+        nes.set_speed(2.0);
+        assert_eq!(nes.frame_duration().unwrap().as_micros(), 8_319);
+        nes.set_speed(0.0);
+        assert_eq!(nes.speed(), MIN_SPEED);
+        nes.set_fast_forward(true);
+        assert_eq!(nes.frame_duration(), None)
+    }
+
+    /// Samples `take_samples` returns over `frames` frames.
+    fn audio_over(nes: &mut Nes, frames: usize) -> usize {
+        let mut samples = Vec::new();
+        for _ in 0..frames {
+            nes.run_frame().unwrap();
+            nes.take_samples(&mut samples);
+        }
+        samples.len()
+    }
+
+    #[test]
+    fn test_audio_follows_the_speed() {
+        let mut nes = console(&spin(0), &[0x40], &[0x40]);
+        let normal = audio_over(&mut nes, 60);
+        assert!((44_000..44_200).contains(&normal), "{normal}");
+
+        // Twice as many frames go by in the same real time, for the same amount of audio.
+        nes.set_speed(2.0);
+        let fast = audio_over(&mut nes, 120);
+        assert!(fast.abs_diff(normal) <= 2 * AUDIO_BLOCK, "{fast}");
+
+        nes.set_speed(0.5);
+        let slow = audio_over(&mut nes, 30);
+        assert!(slow.abs_diff(normal) <= 2 * AUDIO_BLOCK, "{slow}");
+
+        nes.set_fast_forward(true);
+        assert_eq!(audio_over(&mut nes, 10), 0)
+    }
+
+    #[test]
+    fn test_builder_region() {
+        let nes = Nes::builder(cartridge(&spin(0), &[0x40], &[0x40]))
+            .region(Region::Pal)
+            .sample_rate(48_000)
+            .build()
+            .unwrap();
+        assert_eq!(nes.region(), Region::Pal);
+        assert_eq!(nes.bus().apu().sample_rate(), 48_000);
+        assert_eq!(nes.frame_duration().unwrap().as_micros(), 19_997);
+
+        let mut nes = console(&spin(0), &[0x40], &[0x40]);
+        assert_eq!(nes.region(), Region::Ntsc);
+        nes.run_frame().unwrap();
+        let ntsc_cycles = nes.bus().cycles();
+        let mut pal = Nes::builder(cartridge(&spin(0), &[0x40], &[0x40]))
+            .region(Region::Pal)
+            .build()
+            .unwrap();
+        pal.run_frame().unwrap();
+        // 341 * 312 / 3.2 CPU cycles a frame against 341 * 262 / 3.
+        assert!(pal.bus().cycles() > ntsc_cycles + 3000)
+    }
+
+    #[test]
+    fn test_builder_ram_and_controllers() {
+        let mut nes = Nes::builder(cartridge(&spin(0), &[0x40], &[0x40]))
+            .ram_init(RamInit::Ones)
+            .controller(1, Some(Box::new(Zapper::new())))
+            .build()
+            .unwrap();
+        assert_eq!(nes.cpu_mut().mem_read(0x0123), 0xFF);
+        assert!(nes.bus().controller::<Joypad>(0).is_some());
+        assert!(nes.bus().controller::<Zapper>(1).is_some());
+
+        for builder in [
+            Nes::builder(cartridge(&spin(0), &[0x40], &[0x40])).sample_rate(0),
+            Nes::builder(cartridge(&spin(0), &[0x40], &[0x40])).controller(2, None),
+        ] {
+            assert!(matches!(builder.build(), Err(NesError::InvalidConfig(_))));
+        }
+    }
+
+    /// Turns on NMI, rendering and pulse 1, then spins. The NMI handler counts frames in $10 and
+    /// retunes the pulse to the count.
+    fn busy_console() -> Nes {
+        let mut program = [
+            0xA9, 0x80, 0x8D, 0x00, 0x20, 0xA9, 0x1E, 0x8D, 0x01, 0x20, 0xA9, 0x01, 0x8D, 0x15,
+            0x40, 0xA9, 0xBF, 0x8D, 0x00, 0x40, 0xA9, 0x40, 0x8D, 0x02, 0x40, 0xA9, 0x08, 0x8D,
+            0x03, 0x40, 0, 0, 0,
+        ];
+        program[30..].copy_from_slice(&spin(30));
+        let nmi = [0xE6, 0x10, 0xA5, 0x10, 0x8D, 0x02, 0x40, 0x40];
+        let mut nes = console(&program, &nmi, &[0x40]);
+        // The filters are host state and carry on across a load; the raw mix replays exactly.
+        nes.bus_mut().apu_mut().filters_mut().clear();
+        nes
+    }
+
+    /// The picture, audio and frame counter after `frames` more frames.
+    fn run_frames(nes: &mut Nes, frames: usize) -> (Vec<u8>, Vec<f32>, u8) {
+        let mut samples = Vec::new();
+        for _ in 0..frames {
+            nes.run_frame().unwrap();
+            nes.take_samples(&mut samples);
+        }
+        (nes.frame().to_vec(), samples, nes.cpu_mut().mem_read(0x10))
+    }
+
+    #[test]
+    fn test_save_and_load_state() {
+        let mut nes = busy_console();
+        run_frames(&mut nes, 5);
+        let state = nes.save_state();
+        let first = run_frames(&mut nes, 3);
+        assert_eq!(first.2, 8);
+
+        nes.load_state(&state).unwrap();
+        assert_eq!(nes.cpu_mut().mem_read(0x10), 5);
+        assert_eq!(run_frames(&mut nes, 3), first);
+
+        // And into a console that has never run.
+        let mut fresh = busy_console();
+        fresh.load_state(&state).unwrap();
+        assert_eq!(run_frames(&mut fresh, 3), first)
+    }
+
+    #[test]
+    fn test_rewind() {
+        let mut nes = busy_console();
+        assert_eq!(nes.rewind(1).unwrap(), 0);
+        nes.set_rewind(Some(RewindConfig {
+            interval: 1,
+            ..RewindConfig::default()
+        }));
+        run_frames(&mut nes, 10);
+        assert_eq!(nes.rewind_buffer().unwrap().len(), 10);
+        let replay = run_frames(&mut nes, 2);
+
+        assert_eq!(nes.rewind(3).unwrap(), 3);
+        assert_eq!(nes.cpu_mut().mem_read(0x10), 9);
+        // What's already been played replays the same.
+        assert_eq!(run_frames(&mut nes, 1).2, 10);
+        assert_eq!(run_frames(&mut nes, 2), replay);
+
+        // Back to the oldest snapshot, taken after the first frame.
+        assert_eq!(nes.rewind(100).unwrap(), 11);
+        assert_eq!(nes.bus().ppu().frame_count(), 1);
+
+        let state = nes.save_state();
+        run_frames(&mut nes, 2);
+        nes.load_state(&state).unwrap();
+        assert!(nes.rewind_buffer().unwrap().is_empty())
+    }
+
+    #[test]
+    fn test_bad_states_change_nothing() {
+        let mut nes = busy_console();
+        run_frames(&mut nes, 2);
+        let state = nes.save_state();
+        run_frames(&mut nes, 1);
+        let before = nes.save_state();
+
+        let other_cart = console(&spin(0), &[0x40], &[0x40]).save_state();
+        let mut newer = state.clone();
+        newer[4] += 1;
+        for bad in [
+            &b"not a state"[..],
+            &state[..state.len() - 1],
+            &other_cart,
+            &newer,
+        ] {
+            assert!(matches!(
+                nes.load_state(bad),
+                Err(NesError::InvalidState(_))
+            ));
+            assert!(nes.save_state() == before);
+        }
+
+        let mut pal = Nes::builder(nes.bus().cartridge().unwrap().clone())
+            .region(Region::Pal)
+            .build()
+            .unwrap();
+        assert!(pal.load_state(&state).is_err());
+        let mut zapper = busy_console();
+        zapper
+            .bus_mut()
+            .set_controller(1, Some(Box::new(Zapper::new())));
+        assert!(zapper.load_state(&state).is_err())
+    }
+
+    #[test]
+    fn test_corrupted_fields_are_rejected() {
+        let mut nes = busy_console();
+        run_frames(&mut nes, 2);
+        let state = nes.save_state();
+        let before = state.clone();
+
+        // The clock is its region, then ticks and PPU ticks. A PPU left far behind would make the
+        // next cycle catch up on billions of dots.
+        let ticks = nes.bus().clock().ticks().to_le_bytes();
+        let at = state.windows(8).position(|window| window == ticks).unwrap();
+        let mut bad = state.clone();
+        bad[at + 8..at + 16].fill(0);
+        assert!(matches!(
+            nes.load_state(&bad),
+            Err(NesError::InvalidState(_))
+        ));
+        assert!(nes.save_state() == before)
+    }
+
+    #[test]
+    fn test_soft_reset_and_power_cycle() {
+        let mut nes = busy_console();
+        run_frames(&mut nes, 3);
+        nes.cpu_mut().mem_write(0x0300, 0x42);
+        assert!(nes.bus().ppu().nmi_enabled());
+        assert_ne!(nes.cpu_mut().mem_read(0x4015) & 1, 0);
+
+        nes.soft_reset();
+        assert_eq!(nes.cpu().pc, 0xC000);
+        assert_eq!(nes.cpu_mut().mem_read(0x0300), 0x42);
+        assert!(!nes.bus().ppu().nmi_enabled());
+        assert_eq!(nes.cpu_mut().mem_read(0x4015), 0);
+        // The program sets everything up again.
+        run_frames(&mut nes, 1);
+        assert!(nes.bus().ppu().nmi_enabled());
+
+        nes.power_cycle();
+        assert_eq!(nes.cpu().pc, 0xC000);
+        assert_eq!(nes.cpu_mut().mem_read(0x0300), 0);
+        assert_eq!(nes.bus().ppu().frame_count(), 0);
+        // Powered on again, the program runs the same as the first time.
+        let mut fresh = busy_console();
+        assert_eq!(run_frames(&mut nes, 3), run_frames(&mut fresh, 3))
+    }
+
+    #[test]
+    fn test_cpu_fault() {
+        // $02 jams a real 6502.
+        let mut nes = console(&[0x02], &[0x40], &[0x40]);
+        assert!(matches!(
+            nes.step_instruction(),
+            Err(NesError::CpuFault { opcode: 0x02, .. })
+        ))
+    }
+}
@@ -0,0 +1,128 @@
+//! The NES master palette.
+//!
+//! The PPU doesn't output RGB: each pixel is one of 64 color indices that the 2C02 turns straight
+//! into a composite video signal. Frontends need RGB, so this is the usual approximation of what an
+//! NTSC television makes of each index.
+
+/// RGB for each of the 64 color indices on an NTSC 2C02.
+///
+/// Rows are brightness levels and columns are hues. Columns $D-$F are black, except $0D, which is
+/// "blacker than black" on a real set and shown black here.
+pub const NTSC_PALETTE: [[u8; 3]; 64] = [
+    [84, 84, 84],
+    [0, 30, 116],
+    [8, 16, 144],
+    [48, 0, 136],
+    [68, 0, 100],
+    [92, 0, 48],
+    [84, 4, 0],
+    [60, 24, 0],
+    [32, 42, 0],
+    [8, 58, 0],
+    [0, 64, 0],
+    [0, 60, 0],
+    [0, 50, 60],
+    [0, 0, 0],
+    [0, 0, 0],
+    [0, 0, 0],
+    [152, 150, 152],
+    [8, 76, 196],
+    [48, 50, 236],
+    [92, 30, 228],
+    [136, 20, 176],
+    [160, 20, 100],
+    [152, 34, 32],
+    [120, 60, 0],
+    [84, 90, 0],
+    [40, 114, 0],
+    [8, 124, 0],
+    [0, 118, 40],
+    [0, 102, 120],
+    [0, 0, 0],
+    [0, 0, 0],
+    [0, 0, 0],
+    [236, 238, 236],
+    [76, 154, 236],
+    [120, 124, 236],
+    [176, 98, 236],
+    [228, 84, 236],
+    [236, 88, 180],
+    [236, 106, 100],
+    [212, 136, 32],
+    [160, 170, 0],
+    [116, 196, 0],
+    [76, 208, 32],
+    [56, 204, 108],
+    [56, 180, 204],
+    [60, 60, 60],
+    [0, 0, 0],
+    [0, 0, 0],
+    [236, 238, 236],
+    [168, 204, 236],
+    [188, 188, 236],
+    [212, 178, 236],
+    [236, 174, 236],
+    [236, 174, 212],
+    [236, 180, 176],
+    [228, 196, 144],
+    [204, 210, 120],
+    [180, 222, 120],
+    [168, 226, 144],
+    [152, 226, 180],
+    [160, 214, 228],
+    [160, 162, 160],
+    [0, 0, 0],
+    [0, 0, 0],
+];
+
+// Emphasis bits, PPUMASK bits 5-7 shifted down.
+pub const EMPHASIZE_RED: u8 = 0b001;
+pub const EMPHASIZE_GREEN: u8 = 0b010;
+pub const EMPHASIZE_BLUE: u8 = 0b100;
+
+/// How much emphasis dims the channels it doesn't emphasize, out of 256. Roughly what the 2C02's
+/// attenuation does to the signal.
+const ATTENUATION: u16 = 190;
+
+/// The RGBA8 color of NES color index `index`, fully opaque. Only the low six bits matter.
+pub fn rgba(index: u8) -> [u8; 4] {
+    emphasized_rgba(index, 0)
+}
+
+/// The RGBA8 color of `index` with PPUMASK's color emphasis applied. Any emphasis darkens the other
+/// channels; emphasizing all three darkens everything.
+pub fn emphasized_rgba(index: u8, emphasis: u8) -> [u8; 4] {
+    let mut rgb = NTSC_PALETTE[usize::from(index & 0x3F)];
+    if emphasis != 0 {
+        for (channel, value) in rgb.iter_mut().enumerate() {
+            if emphasis & (1 << channel) == 0 || emphasis == 0b111 {
+                *value = (u16::from(*value) * ATTENUATION / 256) as u8;
+            }
+        }
+    }
+    let [r, g, b] = rgb;
+    [r, g, b, 0xFF]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rgba() {
+        assert_eq!(rgba(0x16), [152, 34, 32, 0xFF]);
+        // Only the low six bits pick the color.
+        assert_eq!(rgba(0x56), rgba(0x16))
+    }
+
+    #[test]
+    fn test_emphasis() {
+        assert_eq!(emphasized_rgba(0x30, 0), rgba(0x30));
+        assert_eq!(emphasized_rgba(0x30, EMPHASIZE_RED), [236, 176, 175, 0xFF]);
+        assert_eq!(
+            emphasized_rgba(0x30, EMPHASIZE_GREEN | EMPHASIZE_BLUE),
+            [175, 238, 236, 0xFF]
+        );
+        assert_eq!(emphasized_rgba(0x30, 0b111), [175, 176, 175, 0xFF])
+    }
+}
@@ -0,0 +1,302 @@
+//! Views of PPU memory for debuggers and development tools. None of these touch emulation state:
+//! cartridge memory is read without the bus side effects rendering would have.
+
+use alloc::vec;
+use alloc::vec::Vec;
+
+use super::{
+    Ppu, ATTRIBUTES, CTRL_BACKGROUND_TABLE, HEIGHT, NAMETABLES, OAM_SIZE, PALETTES, PALETTE_SIZE,
+    SPRITE_BEHIND_BACKGROUND, SPRITE_FLIP_HORIZONTAL, SPRITE_FLIP_VERTICAL, SPRITE_PALETTE, WIDTH,
+};
+use crate::mapper::Mapper;
+use crate::palette;
+
+/// Both pattern tables side by side, each 16x16 tiles of 8x8 pixels.
+pub const PATTERN_TABLES_WIDTH: usize = 256;
+pub const PATTERN_TABLES_HEIGHT: usize = 128;
+/// Bytes in an RGBA8 image of the pattern tables.
+pub const PATTERN_TABLES_SIZE: usize = PATTERN_TABLES_WIDTH * PATTERN_TABLES_HEIGHT * 4;
+
+/// All four nametables in a 2x2 grid, $2000 top left and $2C00 bottom right.
+pub const NAMETABLES_WIDTH: usize = WIDTH * 2;
+pub const NAMETABLES_HEIGHT: usize = HEIGHT * 2;
+
+/// Outline color of the scroll viewport in the nametable view.
+const VIEWPORT_COLOR: [u8; 4] = [0xFF, 0x00, 0xFF, 0xFF];
+
+/// Sprites in OAM.
+pub const SPRITES: usize = OAM_SIZE / 4;
+
+/// One OAM entry, decoded.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct OamSprite {
+    pub x: u8,
+    /// The line above the sprite's top row: sprites show up a line later than their Y.
+    pub y: u8,
+    /// The tile number as stored. In 8x16 mode bit 0 picks the pattern table.
+    pub tile: u8,
+    /// Sprite palette 0-3, palettes 4-7 overall.
+    pub palette: u8,
+    pub behind_background: bool,
+    pub flip_horizontal: bool,
+    pub flip_vertical: bool,
+}
+
+impl Ppu {
+    /// All 32 palette RAM entries as RGB, with the $3F10/$3F14/$3F18/$3F1C mirrors resolved.
+    pub fn palette_rgb(&self) -> [[u8; 3]; PALETTE_SIZE] {
+        let mut colors = [[0; 3]; PALETTE_SIZE];
+        for (entry, rgb) in colors.iter_mut().enumerate() {
+            let index = self.palette[Self::palette_index(PALETTES | entry as u16)];
+            *rgb = palette::NTSC_PALETTE[usize::from(index & 0x3F)];
+        }
+        colors
+    }
+
+    /// All 64 sprites in OAM.
+    pub fn oam_sprites(&self) -> [OamSprite; SPRITES] {
+        let mut sprites = [OamSprite::default(); SPRITES];
+        for (sprite, entry) in sprites.iter_mut().zip(self.oam.chunks_exact(4)) {
+            let attributes = entry[2];
+            *sprite = OamSprite {
+                x: entry[3],
+                y: entry[0],
+                tile: entry[1],
+                palette: attributes & SPRITE_PALETTE,
+                behind_background: attributes & SPRITE_BEHIND_BACKGROUND != 0,
+                flip_horizontal: attributes & SPRITE_FLIP_HORIZONTAL != 0,
+                flip_vertical: attributes & SPRITE_FLIP_VERTICAL != 0,
+            };
+        }
+        sprites
+    }
+
+    /// Draws both pattern tables, $0000 on the left and $1000 on the right, as RGBA8 in palette
+    /// `palette_index` (0-3 background, 4-7 sprites).
+    pub fn render_pattern_tables(
+        &self,
+        palette_index: u8,
+        mapper: &mut dyn Mapper,
+    ) -> [u8; PATTERN_TABLES_SIZE] {
+        let mut image = [0; PATTERN_TABLES_SIZE];
+        for tile in 0..512u16 {
+            // The tile's top-left corner: 16 tiles to a row, tables side by side.
+            let left = usize::from(tile / 256 * 128 + tile % 16 * 8);
+            let top = usize::from(tile % 256 / 16 * 8);
+            for row in 0..8 {
+                let low = mapper.ppu_read(tile << 4 | row);
+                let high = mapper.ppu_read(tile << 4 | row | 0b1000);
+                for column in 0..8 {
+                    let bit = 7 - column;
+                    let color = ((low >> bit) & 1) | (((high >> bit) & 1) << 1);
+                    let rgba = self.palette_rgba(palette_index, color);
+                    let offset =
+                        ((top + usize::from(row)) * PATTERN_TABLES_WIDTH + left + column) * 4;
+                    image[offset..offset + 4].copy_from_slice(&rgba);
+                }
+            }
+        }
+        image
+    }
+
+    /// Draws all four nametables as RGBA8, `NAMETABLES_WIDTH` x `NAMETABLES_HEIGHT`, with the
+    /// background pattern table and palettes currently selected. The cartridge's mirroring decides
+    /// which nametables are copies of each other. With `show_viewport`, the 256x240 area the
+    /// scroll registers point at is outlined, wrapping around the edges as the picture does.
+    ///
+    /// Nametables come straight from VRAM, so MMC5's ExRAM and fill modes don't show.
+    pub fn render_nametables(&self, mapper: &mut dyn Mapper, show_viewport: bool) -> Vec<u8> {
+        let mut image = vec![0; NAMETABLES_WIDTH * NAMETABLES_HEIGHT * 4];
+        let mirroring = mapper.mirroring();
+        let vram = |addr: u16| self.vram[mirroring.nametable_offset(addr)];
+        let table = if self.ctrl & CTRL_BACKGROUND_TABLE != 0 {
+            0x1000
+        } else {
+            0
+        };
+
+        for nametable in 0..4u16 {
+            let base = nametable << 10;
+            for (coarse_y, coarse_x) in (0..30u16).flat_map(|y| (0..32u16).map(move |x| (y, x))) {
+                let index = vram(NAMETABLES | base | coarse_y << 5 | coarse_x);
+                let attribute = vram(ATTRIBUTES | base | (coarse_y >> 2) << 3 | coarse_x >> 2);
+                let shift = (coarse_y & 0b10) << 1 | (coarse_x & 0b10);
+                let palette_index = (attribute >> shift) & 0b11;
+
+                let left = usize::from(nametable & 1) * WIDTH + usize::from(coarse_x) * 8;
+                let top = usize::from(nametable >> 1) * HEIGHT + usize::from(coarse_y) * 8;
+                for row in 0..8 {
+                    let pattern = table | u16::from(index) << 4 | row;
+                    let low = mapper.ppu_read(pattern);
+                    let high = mapper.ppu_read(pattern | 0b1000);
+                    for column in 0..8 {
+                        let bit = 7 - column;
+                        let color = ((low >> bit) & 1) | (((high >> bit) & 1) << 1);
+                        let offset =
+                            ((top + usize::from(row)) * NAMETABLES_WIDTH + left + column) * 4;
+                        image[offset..offset + 4]
+                            .copy_from_slice(&self.palette_rgba(palette_index, color));
+                    }
+                }
+            }
+        }
+
+        if show_viewport {
+            self.outline_viewport(&mut image);
+        }
+        image
+    }
+
+    /// The top-left corner of the picture in the nametable view, from `t` and fine X.
+    fn viewport(&self) -> (usize, usize) {
+        let t = usize::from(self.t);
+        let x = (t >> 10 & 1) * WIDTH + (t & 0x1F) * 8 + usize::from(self.fine_x);
+        let y = (t >> 11 & 1) * HEIGHT + (t >> 5 & 0x1F) * 8 + (t >> 12 & 7);
+        (x, y)
+    }
+
+    fn outline_viewport(&self, image: &mut [u8]) {
+        let (left, top) = self.viewport();
+        let mut plot = |x: usize, y: usize| {
+            let offset = ((y % NAMETABLES_HEIGHT) * NAMETABLES_WIDTH + x % NAMETABLES_WIDTH) * 4;
+            image[offset..offset + 4].copy_from_slice(&VIEWPORT_COLOR);
+        };
+        for x in left..left + WIDTH {
+            plot(x, top);
+            plot(x, top + HEIGHT - 1);
+        }
+        for y in top..top + HEIGHT {
+            plot(left, y);
+            plot(left + WIDTH - 1, y);
+        }
+    }
+
+    /// RGBA for color `color` of palette `palette_index`. Color 0 is the backdrop, as when
+    /// rendering.
+    fn palette_rgba(&self, palette_index: u8, color: u8) -> [u8; 4] {
+        let addr = match color & 3 {
+            0 => PALETTES,
+            color => PALETTES | u16::from(palette_index & 7) << 2 | u16::from(color),
+        };
+        palette::rgba(self.palette[Self::palette_index(addr)])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ppu::tests::ppu;
+    use crate::ppu::{PPUCTRL, PPUSCROLL};
+
+    fn pixel(image: &[u8], width: usize, x: usize, y: usize) -> [u8; 4] {
+        let offset = (y * width + x) * 4;
+        [
+            image[offset],
+            image[offset + 1],
+            image[offset + 2],
+            image[offset + 3],
+        ]
+    }
+
+    #[test]
+    fn test_render_pattern_tables() {
+        let (mut ppu, mut mapper) = ppu();
+        ppu.palette[0] = 0x0F;
+        ppu.palette[5] = 0x16;
+        ppu.palette[7] = 0x30;
+        // Tile 1 of the left table: top row color 1, the rest color 3.
+        mapper.ppu_write(0x0010, 0xFF);
+        for row in 1..8 {
+            mapper.ppu_write(0x0010 + row, 0xFF);
+            mapper.ppu_write(0x0018 + row, 0xFF);
+        }
+
+        let image = ppu.render_pattern_tables(1, &mut mapper);
+        let pixel = |x, y| pixel(&image, PATTERN_TABLES_WIDTH, x, y);
+        assert_eq!(pixel(0, 0), palette::rgba(0x0F));
+        assert_eq!(pixel(8, 0), palette::rgba(0x16));
+        assert_eq!(pixel(15, 7), palette::rgba(0x30));
+        assert_eq!(pixel(16, 0), palette::rgba(0x0F));
+        // CHR RAM is 8KB, so the right table is its own memory, still blank.
+        assert_eq!(pixel(136, 0), palette::rgba(0x0F))
+    }
+
+    #[test]
+    fn test_render_nametables() {
+        let (mut ppu, mut mapper) = ppu();
+        ppu.palette[0] = 0x0F;
+        ppu.palette[5] = 0x16;
+        for row in 0..8 {
+            mapper.ppu_write(0x0010 + row, 0xFF);
+        }
+        // Tile 1 at the top left of $2000, in palette 1, and at the bottom right of $2400.
+        ppu.vram[0x000] = 1;
+        ppu.vram[0x3C0] = 0b01;
+        ppu.vram[0x400 + 29 * 32 + 31] = 1;
+
+        let image = ppu.render_nametables(&mut mapper, false);
+        let pixel = |x, y| pixel(&image, NAMETABLES_WIDTH, x, y);
+        assert_eq!(pixel(0, 0), palette::rgba(0x16));
+        assert_eq!(pixel(8, 0), palette::rgba(0x0F));
+        // Palette 0 color 1 is still $00.
+        assert_eq!(pixel(511, 239), palette::rgba(0x00));
+        assert_eq!(pixel(503, 239), palette::rgba(0x0F));
+        // Vertical mirroring: $2800 shows $2000.
+        assert_eq!(pixel(0, 240), palette::rgba(0x16))
+    }
+
+    #[test]
+    fn test_nametable_viewport() {
+        let (mut ppu, mut mapper) = ppu();
+        let blank = palette::rgba(0);
+        let mut write = |reg, data| ppu.write_register(reg, data, &mut mapper);
+        write(PPUCTRL, 0b01);
+        write(PPUSCROLL, 0x84);
+        write(PPUSCROLL, 0x10);
+        assert_eq!(ppu.viewport(), (256 + 0x84, 0x10));
+
+        let image = ppu.render_nametables(&mut mapper, true);
+        let pixel = |x, y| pixel(&image, NAMETABLES_WIDTH, x, y);
+        assert_eq!(pixel(388, 16), VIEWPORT_COLOR);
+        assert_eq!(pixel(388, 100), VIEWPORT_COLOR);
+        assert_eq!(pixel(389, 100), blank);
+        // The right edge wraps around into $2000.
+        assert_eq!(pixel(387 - 256, 100), VIEWPORT_COLOR);
+        assert_eq!(pixel(20, 16), VIEWPORT_COLOR);
+        assert_eq!(pixel(20, 255), VIEWPORT_COLOR)
+    }
+
+    #[test]
+    fn test_palette_rgb() {
+        let (mut ppu, _) = ppu();
+        ppu.palette[0x00] = 0x16;
+        ppu.palette[0x11] = 0x30;
+
+        let colors = ppu.palette_rgb();
+        assert_eq!(colors[0x00], [152, 34, 32]);
+        assert_eq!(colors[0x10], colors[0x00]);
+        assert_eq!(colors[0x11], [236, 238, 236]);
+        assert_eq!(colors[0x01], [84, 84, 84])
+    }
+
+    #[test]
+    fn test_oam_sprites() {
+        let (mut ppu, _) = ppu();
+        ppu.oam[4..8].copy_from_slice(&[0x20, 0x42, 0b1010_0010, 0x80]);
+
+        let sprites = ppu.oam_sprites();
+        assert_eq!(
+            sprites[1],
+            OamSprite {
+                x: 0x80,
+                y: 0x20,
+                tile: 0x42,
+                palette: 2,
+                behind_background: true,
+                flip_horizontal: false,
+                flip_vertical: true,
+            }
+        );
+        assert_eq!(sprites[0].tile, 0)
+    }
+}
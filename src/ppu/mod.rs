@@ -0,0 +1,1539 @@
+//! The picture processing unit (2C02).
+//!
+//! The CPU sees the PPU as eight registers at $2000-$2007. The PPU has its own 16KB address bus:
+//!
+//! ```text
+//! $0000-$1FFF  pattern tables, on the cartridge
+//! $2000-$2FFF  four nametables, backed by 2KB of VRAM and mirrored as the cartridge decides
+//! $3000-$3EFF  mirror of $2000-$2EFF
+//! $3F00-$3FFF  palette RAM
+//! ```
+
+use alloc::boxed::Box;
+use core::mem;
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::{NesError, Result};
+use crate::mapper::Mapper;
+use crate::palette;
+use crate::region::{Region, Timing};
+use crate::state;
+
+mod debug;
+
+pub use debug::{
+    OamSprite, NAMETABLES_HEIGHT, NAMETABLES_WIDTH, PATTERN_TABLES_HEIGHT, PATTERN_TABLES_SIZE,
+    PATTERN_TABLES_WIDTH, SPRITES,
+};
+
+pub const PPUCTRL: u16 = 0x2000;
+pub const PPUMASK: u16 = 0x2001;
+pub const PPUSTATUS: u16 = 0x2002;
+pub const OAMADDR: u16 = 0x2003;
+pub const OAMDATA: u16 = 0x2004;
+pub const PPUSCROLL: u16 = 0x2005;
+pub const PPUADDR: u16 = 0x2006;
+pub const PPUDATA: u16 = 0x2007;
+
+/// 64 sprites of four bytes each.
+pub const OAM_SIZE: usize = 0x100;
+/// Room for four distinct nametables, for four-screen cartridges. Everything else uses the first
+/// 2KB.
+const VRAM_SIZE: usize = 0x1000;
+const PALETTE_SIZE: usize = 0x20;
+
+const NAMETABLES: u16 = 0x2000;
+const ATTRIBUTES: u16 = 0x23C0;
+const PALETTES: u16 = 0x3F00;
+
+pub const WIDTH: usize = 256;
+pub const HEIGHT: usize = 240;
+
+const DOTS_PER_SCANLINE: u16 = 341;
+
+// PPUCTRL
+const CTRL_NAMETABLE: u8 = 0b0000_0011;
+const CTRL_VRAM_INCREMENT: u8 = 0b0000_0100;
+const CTRL_SPRITE_TABLE: u8 = 0b0000_1000;
+const CTRL_BACKGROUND_TABLE: u8 = 0b0001_0000;
+const CTRL_SPRITE_SIZE: u8 = 0b0010_0000;
+const CTRL_NMI_ENABLE: u8 = 0b1000_0000;
+
+// PPUMASK
+const MASK_GRAYSCALE: u8 = 0b0000_0001;
+const MASK_BACKGROUND_LEFT: u8 = 0b0000_0010;
+const MASK_SPRITES_LEFT: u8 = 0b0000_0100;
+const MASK_BACKGROUND: u8 = 0b0000_1000;
+const MASK_SPRITES: u8 = 0b0001_0000;
+
+// PPUSTATUS
+const STATUS_SPRITE_OVERFLOW: u8 = 0b0010_0000;
+const STATUS_SPRITE_ZERO_HIT: u8 = 0b0100_0000;
+const STATUS_VBLANK: u8 = 0b1000_0000;
+
+/// Sprites the PPU can show on one scanline.
+const SPRITES_PER_SCANLINE: usize = 8;
+
+// Sprite attributes, OAM byte 2.
+const SPRITE_PALETTE: u8 = 0b0000_0011;
+const SPRITE_BEHIND_BACKGROUND: u8 = 0b0010_0000;
+const SPRITE_FLIP_HORIZONTAL: u8 = 0b0100_0000;
+const SPRITE_FLIP_VERTICAL: u8 = 0b1000_0000;
+
+/// A sprite picked for the next scanline, with its row of pattern data already fetched.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+struct SpriteRow {
+    x: u8,
+    attributes: u8,
+    low: u8,
+    high: u8,
+}
+
+impl SpriteRow {
+    /// The 2-bit color at screen column `x`, or 0 if the sprite doesn't cover it.
+    fn color(&self, x: u16) -> u8 {
+        match x.checked_sub(u16::from(self.x)) {
+            Some(column @ 0..=7) => {
+                let bit = 7 - column;
+                ((self.low >> bit) & 1) | (((self.high >> bit) & 1) << 1)
+            }
+            _ => 0,
+        }
+    }
+}
+
+/// The background tile being fetched: its nametable entry, then its palette and row of pattern
+/// data.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+struct Tile {
+    index: u8,
+    palette: u8,
+    low: u8,
+    high: u8,
+}
+
+/// Serializes everything but the finished picture, which is kept as it is when a state is loaded
+/// and redrawn by the next frame.
+#[derive(Serialize, Deserialize)]
+pub struct Ppu {
+    /// The frame layout: how many scanlines, and where vblank starts.
+    #[serde(with = "state::timing")]
+    timing: &'static Timing,
+    ctrl: u8,
+    mask: u8,
+    status: u8,
+    oam_addr: u8,
+    #[serde(with = "state::array")]
+    oam: [u8; OAM_SIZE],
+    #[serde(with = "state::array")]
+    vram: [u8; VRAM_SIZE],
+    palette: [u8; PALETTE_SIZE],
+    /// The current VRAM address ("v"). PPUDATA reads and writes go here, and during rendering it
+    /// walks the nametables as tiles are fetched. Laid out as scroll position:
+    ///
+    /// ```text
+    /// yyy NN YYYYY XXXXX
+    /// |   |  |     +------ coarse X
+    /// |   |  +------------ coarse Y
+    /// |   +--------------- nametable
+    /// +------------------- fine Y
+    /// ```
+    v: u16,
+    /// The temporary VRAM address ("t"): PPUCTRL, PPUSCROLL and PPUADDR write here, and rendering
+    /// copies it into `v` at the start of each line and frame.
+    t: u16,
+    fine_x: u8,
+    /// PPUSCROLL and PPUADDR take two writes each and share one toggle to track which is next.
+    write_toggle: bool,
+    /// PPUDATA reads below the palettes return the previous read's value.
+    read_buffer: u8,
+    /// The last value written to any register. Reading a write-only register returns it.
+    io_latch: u8,
+
+    scanline: u16,
+    dot: u16,
+    frame: u64,
+    next_tile: Tile,
+    /// The two tiles being drawn, low and high bit planes. Pixels shift out of the top.
+    pattern_shift: [u16; 2],
+    /// The palettes of the two tiles being drawn, one bit per pixel like the pattern.
+    attribute_shift: [u16; 2],
+    /// Sprites picked for the next line, as copies of their OAM entries.
+    secondary_oam: [u8; SPRITES_PER_SCANLINE * 4],
+    /// Sprites the previous scanline picked for this one.
+    sprites: [SpriteRow; SPRITES_PER_SCANLINE],
+    sprite_count: usize,
+    /// Whether `sprites[0]` is sprite 0 from OAM.
+    sprite_zero: bool,
+    /// The NMI output, vblank AND the PPUCTRL enable. The CPU reacts to it going high.
+    nmi_line: bool,
+    /// An NMI edge the CPU hasn't taken yet.
+    nmi_pending: bool,
+    /// PPUSTATUS was read the dot before vblank starts, which keeps the flag from being set.
+    suppress_vblank: bool,
+    /// The finished picture as NES color indices, one per pixel.
+    #[serde(skip, default = "blank_frame")]
+    pixels: Box<[u8; WIDTH * HEIGHT]>,
+    /// The same picture in RGBA8, for frontends.
+    #[serde(skip, default = "blank_frame")]
+    rgba: Box<[u8; WIDTH * HEIGHT * 4]>,
+}
+
+fn blank_frame<const N: usize>() -> Box<[u8; N]> {
+    Box::new([0; N])
+}
+
+impl Ppu {
+    pub fn new() -> Self {
+        Self::with_region(Region::default())
+    }
+
+    /// A PPU drawing `region`'s frame: 262 scanlines on NTSC, 312 on PAL and Dendy.
+    pub fn with_region(region: Region) -> Self {
+        Self {
+            timing: region.timing(),
+            ctrl: 0,
+            mask: 0,
+            status: 0,
+            oam_addr: 0,
+            oam: [0; OAM_SIZE],
+            vram: [0; VRAM_SIZE],
+            palette: [0; PALETTE_SIZE],
+            v: 0,
+            t: 0,
+            fine_x: 0,
+            write_toggle: false,
+            read_buffer: 0,
+            io_latch: 0,
+            scanline: 0,
+            dot: 0,
+            frame: 0,
+            next_tile: Tile::default(),
+            pattern_shift: [0; 2],
+            attribute_shift: [0; 2],
+            secondary_oam: [0xFF; SPRITES_PER_SCANLINE * 4],
+            sprites: [SpriteRow::default(); SPRITES_PER_SCANLINE],
+            sprite_count: 0,
+            sprite_zero: false,
+            nmi_line: false,
+            nmi_pending: false,
+            suppress_vblank: false,
+            pixels: blank_frame(),
+            rgba: blank_frame(),
+        }
+    }
+
+    /// Takes over the state of `loaded`, a PPU read from a save state, keeping this one's picture.
+    pub(crate) fn restore(&mut self, mut loaded: Ppu) {
+        mem::swap(&mut loaded.pixels, &mut self.pixels);
+        mem::swap(&mut loaded.rgba, &mut self.rgba);
+        *self = loaded;
+    }
+
+    /// Checks a PPU read from a save state for a position off the frame or registers holding more
+    /// bits than they have.
+    pub(crate) fn validate(&self) -> Result<()> {
+        if self.scanline >= self.timing.scanlines
+            || self.dot >= DOTS_PER_SCANLINE
+            || self.fine_x > 7
+            || self.sprite_count > SPRITES_PER_SCANLINE
+        {
+            return Err(NesError::InvalidState(
+                "save state's PPU position is out of range".into(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// The reset line: PPUCTRL, PPUMASK, the scroll and the write toggle are cleared. Memory and
+    /// the position in the frame are left alone.
+    pub fn reset(&mut self) {
+        self.ctrl = 0;
+        self.mask = 0;
+        self.t = 0;
+        self.fine_x = 0;
+        self.write_toggle = false;
+        self.read_buffer = 0;
+        self.update_nmi();
+    }
+
+    /// Frames completed since power-on.
+    pub fn frame_count(&self) -> u64 {
+        self.frame
+    }
+
+    pub fn scanline(&self) -> u16 {
+        self.scanline
+    }
+
+    pub fn dot(&self) -> u16 {
+        self.dot
+    }
+
+    /// The picture as RGBA8, 256x240, row by row from the top left. Lines are converted as they're
+    /// drawn, so mid-frame this is part this frame and part the last.
+    pub fn frame(&self) -> &[u8] {
+        &self.rgba[..]
+    }
+
+    /// The last line of the frame, which fetches like a visible one without drawing.
+    fn pre_render_scanline(&self) -> u16 {
+        self.timing.scanlines - 1
+    }
+
+    /// Advances the PPU by one dot.
+    pub fn tick(&mut self, mapper: &mut dyn Mapper) {
+        let pre_render = self.pre_render_scanline();
+        if self.scanline < HEIGHT as u16 || self.scanline == pre_render {
+            self.render_dot(mapper);
+        }
+        if self.dot == 1 {
+            if self.scanline == self.timing.vblank_scanline {
+                if !core::mem::take(&mut self.suppress_vblank) {
+                    self.status |= STATUS_VBLANK;
+                }
+                self.update_nmi();
+            } else if self.scanline == pre_render {
+                self.status &= !(STATUS_VBLANK | STATUS_SPRITE_ZERO_HIT | STATUS_SPRITE_OVERFLOW);
+                self.update_nmi();
+            }
+        }
+
+        self.dot += 1;
+        // With the background on, odd NTSC frames skip the pre-render line's last dot.
+        if self.timing.odd_frame_skip
+            && self.scanline == pre_render
+            && self.dot == DOTS_PER_SCANLINE - 1
+            && self.frame & 1 == 1
+            && self.mask & MASK_BACKGROUND != 0
+        {
+            self.dot = DOTS_PER_SCANLINE;
+        }
+        if self.dot == DOTS_PER_SCANLINE {
+            self.dot = 0;
+            self.scanline += 1;
+            if self.scanline == self.timing.scanlines {
+                self.scanline = 0;
+                self.frame += 1;
+            }
+        }
+    }
+
+    fn rendering_enabled(&self) -> bool {
+        self.mask & (MASK_BACKGROUND | MASK_SPRITES) != 0
+    }
+
+    /// Whether the PPU is fetching: rendering is on and it's on a visible or pre-render line.
+    fn rendering(&self) -> bool {
+        self.rendering_enabled()
+            && (self.scanline < HEIGHT as u16 || self.scanline == self.pre_render_scanline())
+    }
+
+    /// One dot of a visible or pre-render line. Fetches follow the hardware schedule, each access
+    /// taking two dots:
+    ///
+    /// ```text
+    /// 1-256    32 tiles: nametable, attribute, pattern low, pattern high; coarse X++ after each
+    /// 256      fine/coarse Y++
+    /// 257      copy X from t, evaluate sprites for the next line
+    /// 257-320  8 sprites: two garbage nametable reads, pattern low, pattern high
+    /// 280-304  copy Y from t (pre-render line only)
+    /// 321-336  the first two tiles of the next line
+    /// 337-340  two dummy nametable reads
+    /// ```
+    ///
+    /// Pixels come out of the background shift registers one per dot from dot 1, so mid-line
+    /// register writes take effect at the right place on screen.
+    fn render_dot(&mut self, mapper: &mut dyn Mapper) {
+        let dot = self.dot;
+        let visible = self.scanline < HEIGHT as u16;
+        if !self.rendering_enabled() {
+            if visible && (1..=WIDTH as u16).contains(&dot) {
+                let backdrop = self.palette[0];
+                self.put_pixel(dot - 1, backdrop);
+            }
+            if dot == 257 {
+                self.sprite_count = 0;
+            }
+            return;
+        }
+
+        if (2..=257).contains(&dot) || (322..=337).contains(&dot) {
+            self.shift_background();
+        }
+        if (dot % 8 == 1 && (9..=257).contains(&dot)) || dot == 329 || dot == 337 {
+            self.reload_background();
+        }
+        if visible && (1..=WIDTH as u16).contains(&dot) {
+            self.output_pixel(dot - 1);
+        }
+
+        match dot {
+            256 => self.increment_y(),
+            257 => self.copy_x(),
+            280..=304 if self.scanline == self.pre_render_scanline() => self.copy_y(),
+            _ => {}
+        }
+        match dot {
+            1..=256 | 321..=336 => self.fetch_background(dot, mapper),
+            257..=320 => self.fetch_sprite(dot, mapper),
+            337 | 339 => {
+                let dummy = self.tile_address().0;
+                self.read(dummy, mapper);
+            }
+            _ => {}
+        }
+    }
+
+    fn shift_background(&mut self) {
+        for shift in self
+            .pattern_shift
+            .iter_mut()
+            .chain(&mut self.attribute_shift)
+        {
+            *shift <<= 1;
+        }
+    }
+
+    /// Moves the fetched tile into the low half of the shift registers. Its palette is spread across
+    /// all eight bits so it shifts along with the pattern.
+    fn reload_background(&mut self) {
+        let tile = self.next_tile;
+        self.pattern_shift[0] = (self.pattern_shift[0] & 0xFF00) | u16::from(tile.low);
+        self.pattern_shift[1] = (self.pattern_shift[1] & 0xFF00) | u16::from(tile.high);
+        for (bit, shift) in self.attribute_shift.iter_mut().enumerate() {
+            let fill = if tile.palette >> bit & 1 != 0 {
+                0xFF
+            } else {
+                0
+            };
+            *shift = (*shift & 0xFF00) | fill;
+        }
+    }
+
+    /// Moves `v` one tile right, into the next nametable across after the last column.
+    fn increment_x(&mut self) {
+        if self.v & 0x001F == 31 {
+            self.v = (self.v & !0x001F) ^ 0x0400;
+        } else {
+            self.v += 1;
+        }
+    }
+
+    /// Moves `v` one pixel down, into the next nametable down after row 29. Rows 30 and 31 are
+    /// attribute data, and scrolling into them wraps to row 0 without switching nametables.
+    fn increment_y(&mut self) {
+        if self.v & 0x7000 != 0x7000 {
+            self.v += 0x1000;
+            return;
+        }
+        self.v &= !0x7000;
+        let coarse_y = match (self.v & 0x03E0) >> 5 {
+            29 => {
+                self.v ^= 0x0800;
+                0
+            }
+            31 => 0,
+            y => y + 1,
+        };
+        self.v = (self.v & !0x03E0) | coarse_y << 5;
+    }
+
+    /// Copies coarse X and the horizontal nametable bit from `t`.
+    fn copy_x(&mut self) {
+        self.v = (self.v & !0x041F) | (self.t & 0x041F);
+    }
+
+    /// Copies fine Y, coarse Y and the vertical nametable bit from `t`.
+    fn copy_y(&mut self) {
+        self.v = (self.v & !0x7BE0) | (self.t & 0x7BE0);
+    }
+
+    /// Nametable and attribute addresses of the tile at `v`, and the row within the tile.
+    fn tile_address(&self) -> (u16, u16, u16) {
+        let v = self.v;
+        let tile = NAMETABLES | (v & 0x0FFF);
+        let attribute = ATTRIBUTES | (v & 0x0C00) | ((v >> 4) & 0x38) | ((v >> 2) & 0x07);
+        let shift = ((v >> 4) & 0b100) | (v & 0b10);
+        let fine_y = v >> 12;
+        (tile, attribute, shift << 4 | fine_y)
+    }
+
+    /// The background fetch phase of `dot`.
+    fn fetch_background(&mut self, dot: u16, mapper: &mut dyn Mapper) {
+        let (tile_addr, attribute_addr, shift_and_row) = self.tile_address();
+        let table = if self.ctrl & CTRL_BACKGROUND_TABLE != 0 {
+            0x1000
+        } else {
+            0
+        };
+        let pattern = table | u16::from(self.next_tile.index) << 4 | (shift_and_row & 0b111);
+        match (dot - 1) % 8 {
+            0 => self.next_tile.index = self.read(tile_addr, mapper),
+            2 => {
+                let attribute = self.read(attribute_addr, mapper);
+                self.next_tile.palette = (attribute >> (shift_and_row >> 4)) & 0b11;
+            }
+            4 => self.next_tile.low = self.read(pattern, mapper),
+            6 => self.next_tile.high = self.read(pattern | 0b1000, mapper),
+            7 => self.increment_x(),
+            _ => {}
+        }
+    }
+    fn sprite_height(&self) -> u16 {
+        if self.ctrl & CTRL_SPRITE_SIZE != 0 {
+            16
+        } else {
+            8
+        }
+    }
+
+    /// Copies the first eight sprites in OAM that cover the next scanline into secondary OAM.
+    /// Finding a ninth sets the overflow flag.
+    fn evaluate_sprites(&mut self) {
+        self.secondary_oam.fill(0xFF);
+        let mut count = 0;
+        let mut sprite_zero = false;
+        // Nothing is evaluated on the pre-render line, so no sprites ever appear on line 0.
+        if self.scanline < HEIGHT as u16 {
+            for (index, sprite) in self.oam.chunks_exact(4).enumerate() {
+                let row = self.scanline.wrapping_sub(u16::from(sprite[0]));
+                if row >= self.sprite_height() {
+                    continue;
+                }
+                if count == SPRITES_PER_SCANLINE {
+                    self.status |= STATUS_SPRITE_OVERFLOW;
+                    break;
+                }
+                self.secondary_oam[count * 4..count * 4 + 4].copy_from_slice(sprite);
+                sprite_zero |= index == 0;
+                count += 1;
+            }
+        }
+        self.sprite_count = count;
+        self.sprite_zero = sprite_zero;
+    }
+
+    /// Pattern address of `row` (before flipping) of a sprite using `tile`.
+    fn sprite_pattern(&self, tile: u8, row: u16, attributes: u8) -> u16 {
+        let height = self.sprite_height();
+        // The row was found against the height at evaluation; PPUCTRL may have shrunk it since.
+        let row = row & (height - 1);
+        let row = if attributes & SPRITE_FLIP_VERTICAL != 0 {
+            height - 1 - row
+        } else {
+            row
+        };
+        let (table, tile) = if height == 16 {
+            // 8x16 sprites pick their table with bit 0 and use an even/odd tile pair.
+            let table = u16::from(tile & 1) * 0x1000;
+            (table, u16::from(tile & 0xFE) + row / 8)
+        } else {
+            let table = if self.ctrl & CTRL_SPRITE_TABLE != 0 {
+                0x1000
+            } else {
+                0
+            };
+            (table, u16::from(tile))
+        };
+        table | tile << 4 | (row & 7)
+    }
+
+    /// The sprite fetch phase of `dot`. Every slot is fetched whether or not a sprite is in it;
+    /// empty slots read tile $FF.
+    fn fetch_sprite(&mut self, dot: u16, mapper: &mut dyn Mapper) {
+        let slot = usize::from(dot - 257) / 8;
+        let phase = (dot - 257) % 8;
+        if slot == 0 && phase == 0 {
+            self.evaluate_sprites();
+        }
+
+        let (row, tile, attributes, x) = if slot < self.sprite_count {
+            let sprite = &self.secondary_oam[slot * 4..slot * 4 + 4];
+            let row = self.scanline.wrapping_sub(u16::from(sprite[0]));
+            (row, sprite[1], sprite[2], sprite[3])
+        } else {
+            (0, 0xFF, 0, 0xFF)
+        };
+        let pattern = self.sprite_pattern(tile, row, attributes);
+        let flip = |data: u8| {
+            if attributes & SPRITE_FLIP_HORIZONTAL != 0 {
+                data.reverse_bits()
+            } else {
+                data
+            }
+        };
+
+        match phase {
+            0 | 2 => {
+                let garbage = self.tile_address().0;
+                self.read(garbage, mapper);
+            }
+            4 => {
+                let low = flip(self.read(pattern, mapper));
+                self.sprites[slot] = SpriteRow {
+                    x,
+                    attributes,
+                    low,
+                    high: 0,
+                };
+            }
+            6 => self.sprites[slot].high = flip(self.read(pattern | 0b1000, mapper)),
+            _ => {}
+        }
+    }
+
+    /// Writes pixel `x` of the current line, applying grayscale and color emphasis.
+    fn put_pixel(&mut self, x: u16, color: u8) {
+        // Grayscale keeps only the brightness: the gray column.
+        let color = if self.mask & MASK_GRAYSCALE != 0 {
+            color & 0x30
+        } else {
+            color & 0x3F
+        };
+        let mut emphasis = self.mask >> 5;
+        if self.timing.emphasis_swapped {
+            emphasis =
+                (emphasis & palette::EMPHASIZE_BLUE) | (emphasis & 1) << 1 | (emphasis >> 1 & 1);
+        }
+        let rgba = palette::emphasized_rgba(color, emphasis);
+
+        let index = usize::from(self.scanline) * WIDTH + usize::from(x);
+        self.pixels[index] = color;
+        self.rgba[index * 4..index * 4 + 4].copy_from_slice(&rgba);
+    }
+
+    /// Combines the background and this line's sprites into pixel `x`.
+    fn output_pixel(&mut self, x: u16) {
+        let show_background =
+            self.mask & MASK_BACKGROUND != 0 && (self.mask & MASK_BACKGROUND_LEFT != 0 || x >= 8);
+        let show_sprites =
+            self.mask & MASK_SPRITES != 0 && (self.mask & MASK_SPRITES_LEFT != 0 || x >= 8);
+
+        let (background, palette) = if show_background {
+            let bit = 15 - u16::from(self.fine_x);
+            let pair = |shift: [u16; 2]| (shift[0] >> bit & 1 | (shift[1] >> bit & 1) << 1) as u8;
+            (pair(self.pattern_shift), pair(self.attribute_shift))
+        } else {
+            (0, 0)
+        };
+
+        // The first opaque sprite in OAM order wins, even if it then hides behind the background.
+        let sprite = if show_sprites {
+            self.sprites[..self.sprite_count]
+                .iter()
+                .find_map(|sprite| match sprite.color(x) {
+                    0 => None,
+                    color => Some((color, sprite.attributes)),
+                })
+        } else {
+            None
+        };
+
+        // Sprite 0 hits whenever its opaque pixel lands on an opaque background pixel, whatever
+        // the priorities, but never in the last column.
+        if self.sprite_zero
+            && background != 0
+            && x != WIDTH as u16 - 1
+            && show_sprites
+            && self.sprites[0].color(x) != 0
+        {
+            self.status |= STATUS_SPRITE_ZERO_HIT;
+        }
+
+        let entry = match (background, sprite) {
+            (0, None) => 0,
+            (0, Some((color, attributes))) => 0x10 | (attributes & SPRITE_PALETTE) << 2 | color,
+            (_, Some((color, attributes))) if attributes & SPRITE_BEHIND_BACKGROUND == 0 => {
+                0x10 | (attributes & SPRITE_PALETTE) << 2 | color
+            }
+            (color, _) => palette << 2 | color,
+        };
+        self.put_pixel(x, self.palette[usize::from(entry)]);
+    }
+    /// Whether the PPU will raise an NMI at the start of vblank.
+    pub fn nmi_enabled(&self) -> bool {
+        self.ctrl & CTRL_NMI_ENABLE != 0
+    }
+
+    /// Takes the pending NMI, if there is one. The CPU should poll this after every instruction.
+    pub fn poll_nmi(&mut self) -> bool {
+        core::mem::take(&mut self.nmi_pending)
+    }
+
+    /// Recomputes the NMI output after vblank or the enable changed. A rising edge raises an NMI,
+    /// so enabling NMIs in the middle of vblank fires one straight away.
+    fn update_nmi(&mut self) {
+        let line = self.nmi_enabled() && self.status & STATUS_VBLANK != 0;
+        if line && !self.nmi_line {
+            self.nmi_pending = true;
+        }
+        self.nmi_line = line;
+    }
+
+    pub fn oam(&self) -> &[u8; OAM_SIZE] {
+        &self.oam
+    }
+
+    /// Reads register `reg` ($2000-$2007).
+    pub fn read_register(&mut self, reg: u16, mapper: &mut dyn Mapper) -> u8 {
+        let data = match reg {
+            PPUSTATUS => {
+                // Only the top three bits are driven; the rest is whatever was last on the bus.
+                let data = (self.status & 0xE0) | (self.io_latch & 0x1F);
+                // Racing the start of vblank: a read just before it reads clear and stops the flag
+                // being set, and a read just after it sees the flag but still cancels the NMI. The
+                // bus only catches the PPU up between instructions, so through it these windows
+                // are a few dots off.
+                if self.scanline == self.timing.vblank_scanline {
+                    match self.dot {
+                        1 => self.suppress_vblank = true,
+                        2 | 3 => self.nmi_pending = false,
+                        _ => {}
+                    }
+                }
+                self.status &= !STATUS_VBLANK;
+                self.update_nmi();
+                self.write_toggle = false;
+                data
+            }
+            OAMDATA => self.oam[usize::from(self.oam_addr)],
+            PPUDATA => {
+                // Rendering leaves fine Y in bit 14, past the 14-bit PPU address space.
+                let addr = self.v & 0x3FFF;
+                self.increment_addr();
+                let data = self.read(addr, mapper);
+                if addr >= PALETTES {
+                    // Palette reads skip the buffer, but the buffer still picks up the nametable
+                    // byte underneath.
+                    self.read_buffer = self.read(addr - 0x1000, mapper);
+                    // Palette entries are six bits; the top two come from the bus.
+                    (data & 0x3F) | (self.io_latch & 0xC0)
+                } else {
+                    core::mem::replace(&mut self.read_buffer, data)
+                }
+            }
+            _ => {
+                trace!("read from write-only PPU register {reg:04X}");
+                self.io_latch
+            }
+        };
+        self.io_latch = data;
+        data
+    }
+
+    /// Writes register `reg` ($2000-$2007).
+    pub fn write_register(&mut self, reg: u16, data: u8, mapper: &mut dyn Mapper) {
+        self.io_latch = data;
+        match reg {
+            PPUCTRL => {
+                self.ctrl = data;
+                self.t = (self.t & !0x0C00) | u16::from(data & CTRL_NAMETABLE) << 10;
+                self.update_nmi();
+            }
+            PPUMASK => self.mask = data,
+            PPUSTATUS => trace!("write {data:02X} to read-only PPUSTATUS"),
+            OAMADDR => self.oam_addr = data,
+            OAMDATA => {
+                self.oam[usize::from(self.oam_addr)] = data;
+                self.oam_addr = self.oam_addr.wrapping_add(1);
+            }
+            PPUSCROLL => {
+                if self.write_toggle {
+                    let (coarse_y, fine_y) = (u16::from(data >> 3), u16::from(data & 7));
+                    self.t = (self.t & !0x73E0) | fine_y << 12 | coarse_y << 5;
+                } else {
+                    self.t = (self.t & !0x001F) | u16::from(data >> 3);
+                    self.fine_x = data & 7;
+                }
+                self.write_toggle = !self.write_toggle;
+            }
+            PPUADDR => {
+                if self.write_toggle {
+                    self.t = (self.t & 0xFF00) | u16::from(data);
+                    self.v = self.t;
+                } else {
+                    // The address is 14 bits wide; the high write clears the top bit of `t`.
+                    self.t = (u16::from(data & 0x3F) << 8) | (self.t & 0x00FF);
+                }
+                self.write_toggle = !self.write_toggle;
+            }
+            _ => {
+                let addr = self.v;
+                self.increment_addr();
+                self.write(addr, data, mapper);
+            }
+        }
+    }
+
+    fn increment_addr(&mut self) {
+        // While rendering, the PPUDATA increment collides with the tile fetches' own and bumps
+        // both coarse X and Y instead.
+        if self.rendering() {
+            self.increment_x();
+            self.increment_y();
+            return;
+        }
+        let step = if self.ctrl & CTRL_VRAM_INCREMENT != 0 {
+            32
+        } else {
+            1
+        };
+        self.v = self.v.wrapping_add(step) & 0x7FFF;
+    }
+
+    /// Reads the PPU's own address space.
+    fn read(&mut self, addr: u16, mapper: &mut dyn Mapper) -> u8 {
+        let addr = addr & 0x3FFF;
+        let data = match addr {
+            0x0000..=0x1FFF => mapper.ppu_read(addr),
+            NAMETABLES..=0x3EFF => mapper.nametable_read(NAMETABLES | (addr & 0x0FFF), &self.vram),
+            _ => self.palette[Self::palette_index(addr)],
+        };
+        mapper.ppu_access(addr);
+        data
+    }
+
+    fn write(&mut self, addr: u16, data: u8, mapper: &mut dyn Mapper) {
+        let addr = addr & 0x3FFF;
+        match addr {
+            0x0000..=0x1FFF => mapper.ppu_write(addr, data),
+            NAMETABLES..=0x3EFF => {
+                mapper.nametable_write(NAMETABLES | (addr & 0x0FFF), data, &mut self.vram)
+            }
+            _ => self.palette[Self::palette_index(addr)] = data,
+        }
+        mapper.ppu_access(addr);
+    }
+
+    /// Palette RAM is 32 bytes mirrored across $3F00-$3FFF. The sprite palettes' color 0 entries,
+    /// $3F10/$3F14/$3F18/$3F1C, are the background's $3F00/$3F04/$3F08/$3F0C.
+    fn palette_index(addr: u16) -> usize {
+        let index = usize::from(addr & 0x1F);
+        if index & 0x13 == 0x10 {
+            index & 0x0F
+        } else {
+            index
+        }
+    }
+}
+
+impl Default for Ppu {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::Mirroring;
+    use crate::cartridge::{test_image, Cartridge};
+    use crate::error::Result;
+    use crate::mapper::Nrom;
+    use alloc::vec::Vec;
+
+    // NTSC's frame layout, which the tests run with.
+    const SCANLINES: u16 = 262;
+    const VBLANK_SCANLINE: u16 = 241;
+    const PRE_RENDER_SCANLINE: u16 = SCANLINES - 1;
+
+    /// NROM that logs every PPU bus access.
+    struct Recorder {
+        nrom: Nrom,
+        accesses: Vec<u16>,
+    }
+
+    impl Mapper for Recorder {
+        fn cartridge(&self) -> &Cartridge {
+            self.nrom.cartridge()
+        }
+
+        fn cartridge_mut(&mut self) -> &mut Cartridge {
+            self.nrom.cartridge_mut()
+        }
+
+        fn cpu_read(&mut self, addr: u16) -> Option<u8> {
+            self.nrom.cpu_read(addr)
+        }
+
+        fn cpu_write(&mut self, addr: u16, data: u8) {
+            self.nrom.cpu_write(addr, data)
+        }
+
+        fn ppu_read(&mut self, addr: u16) -> u8 {
+            self.nrom.ppu_read(addr)
+        }
+
+        fn ppu_write(&mut self, addr: u16, data: u8) {
+            self.nrom.ppu_write(addr, data)
+        }
+
+        fn mirroring(&self) -> Mirroring {
+            self.nrom.mirroring()
+        }
+
+        fn ppu_access(&mut self, addr: u16) {
+            self.accesses.push(addr);
+        }
+
+        fn save_state(&self) -> Vec<u8> {
+            self.nrom.save_state()
+        }
+
+        fn load_state(&mut self, state: &[u8]) -> Result<()> {
+            self.nrom.load_state(state)
+        }
+    }
+
+    /// A PPU with an NROM board that has CHR RAM and vertical mirroring.
+    pub(super) fn ppu() -> (Ppu, Nrom) {
+        let cart = Cartridge::new(&test_image(0, 1, 0, 0b1)).unwrap();
+        (Ppu::new(), Nrom::new(cart))
+    }
+
+    fn set_addr(ppu: &mut Ppu, mapper: &mut Nrom, addr: u16) {
+        ppu.write_register(PPUADDR, (addr >> 8) as u8, mapper);
+        ppu.write_register(PPUADDR, addr as u8, mapper);
+    }
+
+    #[test]
+    fn test_ppudata_reads_are_buffered() {
+        let (mut ppu, mut mapper) = ppu();
+        set_addr(&mut ppu, &mut mapper, 0x2305);
+        ppu.write_register(PPUDATA, 0x66, &mut mapper);
+        ppu.write_register(PPUDATA, 0x77, &mut mapper);
+
+        set_addr(&mut ppu, &mut mapper, 0x2305);
+        ppu.read_register(PPUDATA, &mut mapper);
+        assert_eq!(ppu.read_register(PPUDATA, &mut mapper), 0x66);
+        assert_eq!(ppu.read_register(PPUDATA, &mut mapper), 0x77)
+    }
+
+    #[test]
+    fn test_ppudata_reads_ignore_fine_y_bit_14() {
+        let (mut ppu, mut mapper) = ppu();
+        set_addr(&mut ppu, &mut mapper, 0x2305);
+        ppu.write_register(PPUDATA, 0x66, &mut mapper);
+        ppu.write_register(PPUDATA, 0x77, &mut mapper);
+
+        // $6305 is nametable address $2305, not a palette.
+        ppu.v = 0x6305;
+        ppu.read_register(PPUDATA, &mut mapper);
+        assert_eq!(ppu.read_register(PPUDATA, &mut mapper), 0x66);
+        assert_eq!(ppu.read_register(PPUDATA, &mut mapper), 0x77)
+    }
+
+    #[test]
+    fn test_ppudata_increment_32() {
+        let (mut ppu, mut mapper) = ppu();
+        ppu.write_register(PPUCTRL, CTRL_VRAM_INCREMENT, &mut mapper);
+        set_addr(&mut ppu, &mut mapper, 0x2000);
+        ppu.write_register(PPUDATA, 0x11, &mut mapper);
+        ppu.write_register(PPUDATA, 0x22, &mut mapper);
+
+        assert_eq!(ppu.v, 0x2040);
+        assert_eq!(ppu.vram[0x0020], 0x22)
+    }
+
+    #[test]
+    fn test_chr_and_nametable_mirroring() {
+        let (mut ppu, mut mapper) = ppu();
+        set_addr(&mut ppu, &mut mapper, 0x0010);
+        ppu.write_register(PPUDATA, 0x5A, &mut mapper);
+        assert_eq!(mapper.ppu_read(0x0010), 0x5A);
+
+        // Vertical mirroring: $2800 is $2000, and $3000 mirrors $2000 too.
+        set_addr(&mut ppu, &mut mapper, 0x2801);
+        ppu.write_register(PPUDATA, 0x99, &mut mapper);
+        set_addr(&mut ppu, &mut mapper, 0x3001);
+        ppu.read_register(PPUDATA, &mut mapper);
+        assert_eq!(ppu.read_register(PPUDATA, &mut mapper), 0x99)
+    }
+
+    #[test]
+    fn test_palette_reads_skip_the_buffer() {
+        let (mut ppu, mut mapper) = ppu();
+        set_addr(&mut ppu, &mut mapper, 0x3F01);
+        ppu.write_register(PPUDATA, 0x2C, &mut mapper);
+
+        set_addr(&mut ppu, &mut mapper, 0x3F01);
+        assert_eq!(ppu.read_register(PPUDATA, &mut mapper), 0x2C)
+    }
+
+    #[test]
+    fn test_palette_mirroring() {
+        let (mut ppu, mut mapper) = ppu();
+        set_addr(&mut ppu, &mut mapper, 0x3F10);
+        ppu.write_register(PPUDATA, 0x21, &mut mapper);
+        set_addr(&mut ppu, &mut mapper, 0x3F1C);
+        ppu.write_register(PPUDATA, 0x22, &mut mapper);
+        set_addr(&mut ppu, &mut mapper, 0x3F11);
+        ppu.write_register(PPUDATA, 0x23, &mut mapper);
+
+        assert_eq!(ppu.palette[0x00], 0x21);
+        assert_eq!(ppu.palette[0x0C], 0x22);
+        assert_eq!(ppu.palette[0x11], 0x23);
+        assert_eq!(ppu.palette[0x01], 0x00);
+        // $3F20-$3FFF mirrors $3F00-$3F1F.
+        set_addr(&mut ppu, &mut mapper, 0x3FEC);
+        assert_eq!(ppu.read_register(PPUDATA, &mut mapper) & 0x3F, 0x22)
+    }
+
+    #[test]
+    fn test_rgba_frame() {
+        let (mut ppu, mut mapper) = ppu();
+        setup_background(&mut ppu, &mut mapper);
+        run_frame(&mut ppu, &mut mapper);
+
+        let frame = ppu.frame();
+        assert_eq!(frame.len(), WIDTH * HEIGHT * 4);
+        assert_eq!(frame[..4], palette::rgba(0x0F));
+        assert_eq!(frame[8 * 4..9 * 4], [152, 34, 32, 0xFF])
+    }
+
+    #[test]
+    fn test_grayscale_and_emphasis() {
+        let (mut ppu, mut mapper) = ppu();
+        setup_background(&mut ppu, &mut mapper);
+        ppu.mask |= MASK_GRAYSCALE | 0b0010_0000;
+        run_frame(&mut ppu, &mut mapper);
+
+        assert_eq!(ppu.pixels[8], 0x10);
+        assert_eq!(
+            ppu.frame()[8 * 4..9 * 4],
+            palette::emphasized_rgba(0x10, palette::EMPHASIZE_RED)
+        )
+    }
+
+    #[test]
+    fn test_status_read_clears_vblank_and_write_toggle() {
+        let (mut ppu, mut mapper) = ppu();
+        ppu.status = STATUS_VBLANK;
+        ppu.write_register(PPUADDR, 0x21, &mut mapper);
+
+        assert_eq!(
+            ppu.read_register(PPUSTATUS, &mut mapper) & 0xE0,
+            STATUS_VBLANK
+        );
+        assert_eq!(ppu.read_register(PPUSTATUS, &mut mapper) & 0xE0, 0);
+
+        // The toggle was reset, so this is a high byte again.
+        set_addr(&mut ppu, &mut mapper, 0x2345);
+        assert_eq!(ppu.v, 0x2345)
+    }
+
+    #[test]
+    fn test_oam_access() {
+        let (mut ppu, mut mapper) = ppu();
+        ppu.write_register(OAMADDR, 0xFF, &mut mapper);
+        ppu.write_register(OAMDATA, 0x12, &mut mapper);
+        ppu.write_register(OAMDATA, 0x34, &mut mapper);
+
+        assert_eq!(ppu.oam()[0xFF], 0x12);
+        assert_eq!(ppu.oam()[0x00], 0x34);
+        ppu.write_register(OAMADDR, 0x00, &mut mapper);
+        assert_eq!(ppu.read_register(OAMDATA, &mut mapper), 0x34)
+    }
+
+    /// Puts a solid tile 1 (color 1 everywhere) in CHR and a palette with entry 1 of background
+    /// palette 1 set to $16.
+    fn setup_background(ppu: &mut Ppu, mapper: &mut Nrom) {
+        for row in 0..8 {
+            mapper.ppu_write(0x0010 + row, 0xFF);
+        }
+        ppu.palette[0] = 0x0F;
+        ppu.palette[5] = 0x16;
+        // Top-left quadrant of the first attribute byte uses palette 1.
+        ppu.vram[0x3C0] = 0b01;
+        ppu.vram[0x001] = 1;
+        ppu.mask = MASK_BACKGROUND | MASK_BACKGROUND_LEFT;
+    }
+
+    /// Runs until a whole frame has been drawn after a pre-render line, which prefetches the first
+    /// two tiles of line 0.
+    fn run_frame(ppu: &mut Ppu, mapper: &mut Nrom) {
+        let frame = ppu.frame_count() + 2;
+        while ppu.frame_count() < frame {
+            ppu.tick(mapper);
+        }
+    }
+
+    #[test]
+    fn test_background_rendering() {
+        let (mut ppu, mut mapper) = ppu();
+        setup_background(&mut ppu, &mut mapper);
+        run_frame(&mut ppu, &mut mapper);
+
+        assert_eq!(ppu.pixels[7], 0x0F);
+        assert_eq!(ppu.pixels[8], 0x16);
+        assert_eq!(ppu.pixels[7 * WIDTH + 15], 0x16);
+        assert_eq!(ppu.pixels[8 * WIDTH + 8], 0x0F)
+    }
+
+    #[test]
+    fn test_background_scrolling() {
+        let (mut ppu, mut mapper) = ppu();
+        setup_background(&mut ppu, &mut mapper);
+        ppu.write_register(PPUSCROLL, 4, &mut mapper);
+        ppu.write_register(PPUSCROLL, 2, &mut mapper);
+        run_frame(&mut ppu, &mut mapper);
+
+        assert_eq!(ppu.pixels[3], 0x0F);
+        assert_eq!(ppu.pixels[4], 0x16);
+        assert_eq!(ppu.pixels[5 * WIDTH + 11], 0x16);
+        assert_eq!(ppu.pixels[6 * WIDTH + 11], 0x0F)
+    }
+
+    #[test]
+    fn test_scroll_register_writes() {
+        let (mut ppu, mut mapper) = ppu();
+        ppu.write_register(PPUCTRL, 0b10, &mut mapper);
+        assert_eq!(ppu.t, 0x0800);
+        ppu.write_register(PPUCTRL, 0, &mut mapper);
+        ppu.read_register(PPUSTATUS, &mut mapper);
+
+        ppu.write_register(PPUSCROLL, 0x7D, &mut mapper);
+        assert_eq!((ppu.t, ppu.fine_x), (0x000F, 5));
+        ppu.write_register(PPUSCROLL, 0x5E, &mut mapper);
+        assert_eq!(ppu.t, 0x616F);
+
+        // PPUADDR shares `t`, and the second write copies it over to `v`.
+        ppu.write_register(PPUADDR, 0x3D, &mut mapper);
+        assert_eq!((ppu.t, ppu.v), (0x3D6F, 0));
+        ppu.write_register(PPUADDR, 0xF0, &mut mapper);
+        assert_eq!((ppu.t, ppu.v), (0x3DF0, 0x3DF0))
+    }
+
+    #[test]
+    fn test_scroll_increments() {
+        let (mut ppu, _) = ppu();
+        ppu.v = 0x001F;
+        ppu.increment_x();
+        assert_eq!(ppu.v, 0x0400);
+
+        // Fine Y 7 on row 29 moves to the nametable below.
+        ppu.v = 0x7000 | 29 << 5;
+        ppu.increment_y();
+        assert_eq!(ppu.v, 0x0800);
+        // Row 31 wraps around in the same nametable.
+        ppu.v = 0x7400 | 31 << 5;
+        ppu.increment_y();
+        assert_eq!(ppu.v, 0x0400)
+    }
+
+    #[test]
+    fn test_mid_frame_scroll_split() {
+        let (mut ppu, mut mapper) = ppu();
+        setup_background(&mut ppu, &mut mapper);
+        ppu.palette[1] = 0x16;
+        for row in 0..30 {
+            ppu.vram[row * 32 + 1] = 1;
+        }
+        run_frame(&mut ppu, &mut mapper);
+
+        // Scroll right by a tile from line 100's hblank. The next line is already fetched, so
+        // the split shows from line 102.
+        while (ppu.scanline(), ppu.dot()) != (100, 300) {
+            ppu.tick(&mut mapper);
+        }
+        ppu.write_register(PPUSCROLL, 8, &mut mapper);
+        ppu.write_register(PPUSCROLL, 0, &mut mapper);
+        while ppu.scanline() != HEIGHT as u16 {
+            ppu.tick(&mut mapper);
+        }
+
+        let pixel = |x: usize, y: usize| ppu.pixels[y * WIDTH + x];
+        assert_eq!((pixel(0, 101), pixel(8, 101)), (0x0F, 0x16));
+        assert_eq!((pixel(0, 102), pixel(8, 102)), (0x16, 0x0F));
+        assert_eq!((pixel(0, 239), pixel(8, 239)), (0x16, 0x0F))
+    }
+
+    /// Solid tile 2 in color 2, with its top row in color 3 so flips are visible, and sprite
+    /// palette 0 entries 2 and 3 set to $21 and $22.
+    fn setup_sprites(ppu: &mut Ppu, mapper: &mut Nrom) {
+        for row in 0..8 {
+            mapper.ppu_write(0x0028 + row, 0xFF);
+        }
+        mapper.ppu_write(0x0020, 0xF0);
+        ppu.palette[0x12] = 0x21;
+        ppu.palette[0x13] = 0x22;
+        ppu.mask |= MASK_SPRITES | MASK_SPRITES_LEFT;
+    }
+
+    fn place_sprite(ppu: &mut Ppu, index: usize, x: u8, y: u8, tile: u8, attributes: u8) {
+        ppu.oam[index * 4..index * 4 + 4].copy_from_slice(&[y, tile, attributes, x]);
+    }
+
+    #[test]
+    fn test_sprite_rendering_and_flipping() {
+        let (mut ppu, mut mapper) = ppu();
+        setup_sprites(&mut ppu, &mut mapper);
+        ppu.oam.fill(0xFF);
+        // Sprites show up one line below their OAM Y.
+        place_sprite(&mut ppu, 0, 40, 19, 2, 0);
+        place_sprite(
+            &mut ppu,
+            1,
+            80,
+            19,
+            2,
+            SPRITE_FLIP_HORIZONTAL | SPRITE_FLIP_VERTICAL,
+        );
+        run_frame(&mut ppu, &mut mapper);
+
+        let pixel = |x: usize, y: usize| ppu.pixels[y * WIDTH + x];
+        assert_eq!(pixel(40, 19), 0x00);
+        assert_eq!(pixel(40, 20), 0x22);
+        assert_eq!(pixel(44, 20), 0x21);
+        assert_eq!(pixel(47, 27), 0x21);
+        assert_eq!(pixel(48, 20), 0x00);
+
+        assert_eq!(pixel(80, 27), 0x21);
+        assert_eq!(pixel(87, 27), 0x22);
+        assert_eq!(pixel(87, 20), 0x21)
+    }
+
+    #[test]
+    fn test_sprite_priority() {
+        let (mut ppu, mut mapper) = ppu();
+        setup_background(&mut ppu, &mut mapper);
+        setup_sprites(&mut ppu, &mut mapper);
+        ppu.oam.fill(0xFF);
+        // Tile 1 covers (8, 0)-(15, 7). One sprite in front of it, one behind it.
+        place_sprite(&mut ppu, 0, 4, 0, 2, SPRITE_BEHIND_BACKGROUND);
+        place_sprite(&mut ppu, 1, 12, 2, 2, 0);
+        run_frame(&mut ppu, &mut mapper);
+
+        let pixel = |x: usize, y: usize| ppu.pixels[y * WIDTH + x];
+        // Behind: shows through the backdrop only.
+        assert_eq!(pixel(5, 2), 0x21);
+        assert_eq!(pixel(9, 2), 0x16);
+        assert_eq!(pixel(13, 4), 0x21)
+    }
+
+    #[test]
+    fn test_sprite_zero_hit() {
+        let (mut ppu, mut mapper) = ppu();
+        setup_background(&mut ppu, &mut mapper);
+        setup_sprites(&mut ppu, &mut mapper);
+        ppu.oam.fill(0xFF);
+        // Sprite 0 over the solid tile at (8, 0) hits even when it's behind it.
+        place_sprite(&mut ppu, 0, 12, 2, 2, SPRITE_BEHIND_BACKGROUND);
+        run_frame(&mut ppu, &mut mapper);
+        while ppu.scanline() != 2 {
+            ppu.tick(&mut mapper);
+        }
+        assert_eq!(ppu.status & STATUS_SPRITE_ZERO_HIT, 0);
+        while ppu.scanline() != 4 {
+            ppu.tick(&mut mapper);
+        }
+        assert_ne!(ppu.status & STATUS_SPRITE_ZERO_HIT, 0);
+
+        // Any other sprite doesn't count.
+        ppu.oam.copy_within(0..4, 4);
+        ppu.oam[0] = 0xFF;
+        run_frame(&mut ppu, &mut mapper);
+        assert_eq!(ppu.status & STATUS_SPRITE_ZERO_HIT, 0)
+    }
+
+    #[test]
+    fn test_sprite_zero_hit_left_column() {
+        let (mut ppu, mut mapper) = ppu();
+        setup_background(&mut ppu, &mut mapper);
+        setup_sprites(&mut ppu, &mut mapper);
+        // Move the solid tile to column 0, under a sprite confined to the left 8 pixels.
+        ppu.vram[0] = 1;
+        ppu.vram[1] = 0;
+        ppu.oam.fill(0xFF);
+        place_sprite(&mut ppu, 0, 0, 2, 2, 0);
+        ppu.mask &= !MASK_SPRITES_LEFT;
+        run_frame(&mut ppu, &mut mapper);
+        assert_eq!(ppu.status & STATUS_SPRITE_ZERO_HIT, 0);
+
+        ppu.mask |= MASK_SPRITES_LEFT | MASK_BACKGROUND_LEFT;
+        run_frame(&mut ppu, &mut mapper);
+        while ppu.scanline() != 10 {
+            ppu.tick(&mut mapper);
+        }
+        assert_ne!(ppu.status & STATUS_SPRITE_ZERO_HIT, 0)
+    }
+
+    #[test]
+    fn test_8x16_sprites() {
+        let (mut ppu, mut mapper) = ppu();
+        setup_sprites(&mut ppu, &mut mapper);
+        // Tile 3 (the bottom half of the 2/3 pair) is solid color 1.
+        for row in 0..8 {
+            mapper.ppu_write(0x0030 + row, 0xFF);
+        }
+        ppu.palette[0x11] = 0x30;
+        ppu.ctrl |= CTRL_SPRITE_SIZE;
+        ppu.oam.fill(0xFF);
+        place_sprite(&mut ppu, 0, 100, 49, 2, 0);
+        run_frame(&mut ppu, &mut mapper);
+
+        assert_eq!(ppu.pixels[51 * WIDTH + 100], 0x21);
+        assert_eq!(ppu.pixels[58 * WIDTH + 100], 0x30);
+        assert_eq!(ppu.pixels[66 * WIDTH + 100], 0x00)
+    }
+
+    #[test]
+    fn test_eight_sprites_per_scanline() {
+        let (mut ppu, mut mapper) = ppu();
+        setup_sprites(&mut ppu, &mut mapper);
+        ppu.oam.fill(0xFF);
+        for index in 0..9 {
+            place_sprite(&mut ppu, index, index as u8 * 10, 99, 2, 0);
+        }
+        run_frame(&mut ppu, &mut mapper);
+
+        assert_eq!(ppu.pixels[101 * WIDTH + 70], 0x21);
+        assert_eq!(ppu.pixels[101 * WIDTH + 80], 0x00);
+        // The flag was cleared on the pre-render line and set again on line 99.
+        while ppu.scanline() != 100 {
+            ppu.tick(&mut mapper);
+        }
+        assert_ne!(ppu.status & STATUS_SPRITE_OVERFLOW, 0)
+    }
+
+    #[test]
+    fn test_fetch_schedule() {
+        let (mut ppu, nrom) = ppu();
+        let mut mapper = Recorder {
+            nrom,
+            accesses: Vec::new(),
+        };
+        ppu.mask = MASK_BACKGROUND;
+        while ppu.scanline() != 10 {
+            ppu.tick(&mut mapper);
+        }
+        mapper.accesses.clear();
+
+        let mut counts = Vec::new();
+        while ppu.scanline() == 10 {
+            ppu.tick(&mut mapper);
+            counts.push(mapper.accesses.len());
+        }
+        // counts[n] is the total after dot n.
+        assert_eq!(counts[8], 4);
+        assert_eq!(counts[256], 128);
+        assert_eq!(counts[320], 160);
+        assert_eq!(counts[336], 168);
+        assert_eq!(counts[340], 170);
+
+        // The line starts on column 2, as the line before fetched the first two. Then come the
+        // sprite slots' garbage nametable reads and tile $FF.
+        assert_eq!(mapper.accesses[..4], [0x2022, 0x23C0, 0x0002, 0x000A]);
+        assert_eq!(mapper.accesses[128..132], [0x2020, 0x2020, 0x0FF0, 0x0FF8]);
+        // The next line's first two tiles, then the dummy reads of the third's nametable entry.
+        assert_eq!(mapper.accesses[160], 0x2020);
+        assert_eq!(mapper.accesses[164], 0x2021);
+        assert_eq!(mapper.accesses[168..], [0x2022, 0x2022])
+    }
+
+    #[test]
+    fn test_sprite_zero_hit_dot() {
+        let (mut ppu, mut mapper) = ppu();
+        setup_background(&mut ppu, &mut mapper);
+        setup_sprites(&mut ppu, &mut mapper);
+        ppu.oam.fill(0xFF);
+        place_sprite(&mut ppu, 0, 12, 2, 2, 0);
+        run_frame(&mut ppu, &mut mapper);
+
+        // Pixel x comes out on dot x + 1.
+        while (ppu.scanline(), ppu.dot()) != (3, 13) {
+            ppu.tick(&mut mapper);
+        }
+        assert_eq!(ppu.status & STATUS_SPRITE_ZERO_HIT, 0);
+        ppu.tick(&mut mapper);
+        assert_ne!(ppu.status & STATUS_SPRITE_ZERO_HIT, 0)
+    }
+
+    #[test]
+    fn test_mid_line_mask_change() {
+        let (mut ppu, mut mapper) = ppu();
+        setup_background(&mut ppu, &mut mapper);
+        run_frame(&mut ppu, &mut mapper);
+        while (ppu.scanline(), ppu.dot()) != (4, 12) {
+            ppu.tick(&mut mapper);
+        }
+        ppu.write_register(PPUMASK, 0, &mut mapper);
+        while ppu.scanline() != 5 {
+            ppu.tick(&mut mapper);
+        }
+
+        assert_eq!(ppu.pixels[4 * WIDTH + 10], 0x16);
+        assert_eq!(ppu.pixels[4 * WIDTH + 11], 0x0F);
+        assert_eq!(ppu.pixels[3 * WIDTH + 11], 0x16)
+    }
+
+    #[test]
+    fn test_rendering_disabled_shows_the_backdrop() {
+        let (mut ppu, mut mapper) = ppu();
+        setup_background(&mut ppu, &mut mapper);
+        ppu.mask = 0;
+        run_frame(&mut ppu, &mut mapper);
+
+        assert!(ppu.pixels.iter().all(|&pixel| pixel == 0x0F))
+    }
+
+    #[test]
+    fn test_vblank_flag_timing() {
+        let (mut ppu, mut mapper) = ppu();
+        while (ppu.scanline(), ppu.dot()) != (VBLANK_SCANLINE, 1) {
+            ppu.tick(&mut mapper);
+        }
+        assert_eq!(ppu.status & STATUS_VBLANK, 0);
+        ppu.tick(&mut mapper);
+        assert_ne!(ppu.status & STATUS_VBLANK, 0);
+
+        while (ppu.scanline(), ppu.dot()) != (PRE_RENDER_SCANLINE, 2) {
+            ppu.tick(&mut mapper);
+        }
+        assert_eq!(ppu.status & STATUS_VBLANK, 0)
+    }
+
+    /// Runs until `dot` on the vblank scanline is next.
+    fn run_to_vblank(ppu: &mut Ppu, mapper: &mut Nrom, dot: u16) {
+        while (ppu.scanline(), ppu.dot()) != (VBLANK_SCANLINE, dot) {
+            ppu.tick(mapper);
+        }
+    }
+
+    #[test]
+    fn test_nmi() {
+        let (mut ppu, mut mapper) = ppu();
+        ppu.write_register(PPUCTRL, CTRL_NMI_ENABLE, &mut mapper);
+        run_to_vblank(&mut ppu, &mut mapper, 1);
+        assert!(!ppu.poll_nmi());
+        ppu.tick(&mut mapper);
+        assert!(ppu.poll_nmi());
+        assert!(!ppu.poll_nmi());
+
+        // Turning NMIs back on during vblank fires another, as long as the flag is still set.
+        ppu.write_register(PPUCTRL, 0, &mut mapper);
+        ppu.write_register(PPUCTRL, CTRL_NMI_ENABLE, &mut mapper);
+        assert!(ppu.poll_nmi());
+        ppu.read_register(PPUSTATUS, &mut mapper);
+        ppu.write_register(PPUCTRL, 0, &mut mapper);
+        ppu.write_register(PPUCTRL, CTRL_NMI_ENABLE, &mut mapper);
+        assert!(!ppu.poll_nmi());
+
+        // No NMI without the enable.
+        ppu.write_register(PPUCTRL, 0, &mut mapper);
+        run_to_vblank(&mut ppu, &mut mapper, 2);
+        assert!(!ppu.poll_nmi())
+    }
+
+    #[test]
+    fn test_status_read_races_vblank() {
+        let (mut ppu, mut mapper) = ppu();
+        ppu.write_register(PPUCTRL, CTRL_NMI_ENABLE, &mut mapper);
+
+        // The dot before: reads clear, and neither the flag nor the NMI happen this frame.
+        run_to_vblank(&mut ppu, &mut mapper, 1);
+        assert_eq!(ppu.read_register(PPUSTATUS, &mut mapper) & STATUS_VBLANK, 0);
+        ppu.tick(&mut mapper);
+        assert_eq!(ppu.status & STATUS_VBLANK, 0);
+        assert!(!ppu.poll_nmi());
+
+        // Just after: reads set, but the NMI is cancelled.
+        ppu.tick(&mut mapper);
+        run_to_vblank(&mut ppu, &mut mapper, 2);
+        assert_ne!(ppu.read_register(PPUSTATUS, &mut mapper) & STATUS_VBLANK, 0);
+        assert!(!ppu.poll_nmi());
+
+        // Later in vblank the NMI has already happened.
+        run_to_vblank(&mut ppu, &mut mapper, 0);
+        run_to_vblank(&mut ppu, &mut mapper, 10);
+        assert_ne!(ppu.read_register(PPUSTATUS, &mut mapper) & STATUS_VBLANK, 0);
+        assert!(ppu.poll_nmi())
+    }
+
+    #[test]
+    fn test_odd_frames_skip_a_dot() {
+        let (mut ppu, mut mapper) = ppu();
+        let mut frame_length = |ppu: &mut Ppu| {
+            let frame = ppu.frame_count();
+            let mut dots = 0;
+            while ppu.frame_count() == frame {
+                ppu.tick(&mut mapper);
+                dots += 1;
+            }
+            dots
+        };
+        let full = u32::from(DOTS_PER_SCANLINE) * u32::from(SCANLINES);
+        assert_eq!(frame_length(&mut ppu), full);
+        assert_eq!(frame_length(&mut ppu), full);
+
+        ppu.mask = MASK_BACKGROUND;
+        assert_eq!(frame_length(&mut ppu), full);
+        assert_eq!(frame_length(&mut ppu), full - 1);
+        // Sprites alone don't count.
+        ppu.mask = MASK_SPRITES;
+        frame_length(&mut ppu);
+        assert_eq!(frame_length(&mut ppu), full)
+    }
+
+    #[test]
+    fn test_pal_and_dendy_frames() {
+        let (_, mut mapper) = ppu();
+        for (region, vblank) in [(Region::Pal, 241), (Region::Dendy, 291)] {
+            let mut ppu = Ppu::with_region(region);
+            ppu.mask = MASK_BACKGROUND;
+            let mut dots = 0;
+            let mut vblank_at = None;
+            // No dot is skipped on odd frames.
+            while ppu.frame_count() < 2 {
+                ppu.tick(&mut mapper);
+                dots += 1;
+                if vblank_at.is_none() && ppu.status & STATUS_VBLANK != 0 {
+                    vblank_at = Some(ppu.scanline());
+                }
+            }
+            assert_eq!(dots, 2 * 341 * 312, "{region:?}");
+            assert_eq!(vblank_at, Some(vblank), "{region:?}");
+        }
+    }
+
+    #[test]
+    fn test_pal_swaps_red_and_green_emphasis() {
+        let (_, mut mapper) = ppu();
+        let mut ppu = Ppu::with_region(Region::Pal);
+        set_addr(&mut ppu, &mut mapper, 0x3F00);
+        ppu.write_register(PPUDATA, 0x30, &mut mapper);
+        // Bit 5, red on NTSC.
+        ppu.write_register(PPUMASK, 0x20, &mut mapper);
+        ppu.put_pixel(0, 0x30);
+        assert_eq!(
+            ppu.frame()[..4],
+            palette::emphasized_rgba(0x30, palette::EMPHASIZE_GREEN)
+        )
+    }
+
+    #[test]
+    fn test_validate() {
+        let mut ppu = Ppu::new();
+        assert!(ppu.validate().is_ok());
+        ppu.sprite_count = SPRITES_PER_SCANLINE + 1;
+        assert!(matches!(ppu.validate(), Err(NesError::InvalidState(_))));
+
+        let mut ppu = Ppu::new();
+        ppu.scanline = SCANLINES;
+        assert!(ppu.validate().is_err());
+        let mut ppu = Ppu::new();
+        ppu.fine_x = 8;
+        assert!(ppu.validate().is_err())
+    }
+
+    #[test]
+    fn test_write_only_registers_read_the_io_latch() {
+        let (mut ppu, mut mapper) = ppu();
+        ppu.write_register(PPUMASK, 0xA5, &mut mapper);
+
+        assert_eq!(ppu.read_register(PPUCTRL, &mut mapper), 0xA5);
+        assert_eq!(ppu.read_register(PPUSTATUS, &mut mapper), 0x05)
+    }
+}
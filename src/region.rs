@@ -0,0 +1,167 @@
+//! Console regions and their timing.
+//!
+//! Every region derives its CPU and PPU clocks from one master crystal, dividing it by a different
+//! amount, and draws a different number of scanlines per frame.
+//!
+//! ```text
+//!         master clock  CPU  PPU  scanlines  vblank at
+//! NTSC    21.477272MHz  /12  /4   262        241
+//! PAL     26.601712MHz  /16  /5   312        241
+//! Dendy   26.601712MHz  /15  /5   312        291
+//! ```
+//!
+//! The PAL 2A07 also has its own APU period tables to make up for its slower clock, and the PAL
+//! PPU swaps the red and green emphasis bits and never skips a dot on odd frames. The Dendy pairs
+//! the PAL picture with an NTSC-style CPU and APU.
+
+use serde::{Deserialize, Serialize};
+
+/// The TV system a console (or cartridge) was made for.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Region {
+    /// North America and Japan. Also used for cartridges that work everywhere.
+    #[default]
+    Ntsc,
+    /// Europe and Australia.
+    Pal,
+    /// The Dendy and other Famiclones: PAL video with NTSC-like CPU timing.
+    Dendy,
+}
+
+/// The clock rates and frame layout of one region.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Timing {
+    pub region: Region,
+    pub master_clock_hz: u32,
+    /// Master clock ticks per CPU cycle.
+    pub cpu_divider: u32,
+    /// Master clock ticks per PPU dot.
+    pub ppu_divider: u32,
+    /// Scanlines per frame, including vblank and the pre-render line.
+    pub scanlines: u16,
+    /// The scanline the vblank flag is set on.
+    pub vblank_scanline: u16,
+    pub frame_rate_hz: f64,
+    /// CPU cycles at which the APU frame counter clocks its quarter-frame steps in 4-step mode. The
+    /// last one also clocks the half-frame units and raises the frame IRQ.
+    pub frame_counter_steps: [u32; 4],
+    /// Length of one 5-step frame counter sequence in CPU cycles.
+    pub frame_counter_5_step: u32,
+    /// Noise timer periods in CPU cycles, indexed by the low four bits of $400E.
+    pub noise_periods: [u16; 16],
+    /// DMC timer periods in CPU cycles, indexed by the low four bits of $4010.
+    pub dmc_rates: [u16; 16],
+    /// Whether the pre-render line is a dot short on odd frames with the background on.
+    pub odd_frame_skip: bool,
+    /// Whether PPUMASK bit 5 emphasizes green and bit 6 red, rather than the other way round.
+    pub emphasis_swapped: bool,
+}
+
+const NTSC: Timing = Timing {
+    region: Region::Ntsc,
+    master_clock_hz: 21_477_272,
+    cpu_divider: 12,
+    ppu_divider: 4,
+    scanlines: 262,
+    vblank_scanline: 241,
+    frame_rate_hz: 60.0988,
+    frame_counter_steps: [7457, 14913, 22371, 29829],
+    frame_counter_5_step: 37281,
+    noise_periods: [
+        4, 8, 16, 32, 64, 96, 128, 160, 202, 254, 380, 508, 762, 1016, 2034, 4068,
+    ],
+    dmc_rates: [
+        428, 380, 340, 320, 286, 254, 226, 214, 190, 160, 142, 128, 106, 84, 72, 54,
+    ],
+    odd_frame_skip: true,
+    emphasis_swapped: false,
+};
+
+const PAL: Timing = Timing {
+    region: Region::Pal,
+    master_clock_hz: 26_601_712,
+    cpu_divider: 16,
+    ppu_divider: 5,
+    scanlines: 312,
+    vblank_scanline: 241,
+    frame_rate_hz: 50.0070,
+    frame_counter_steps: [8313, 16627, 24939, 33253],
+    frame_counter_5_step: 41565,
+    noise_periods: [
+        4, 8, 14, 30, 60, 88, 118, 148, 188, 236, 354, 472, 708, 944, 1890, 3778,
+    ],
+    dmc_rates: [
+        398, 354, 316, 298, 276, 236, 210, 198, 176, 148, 132, 118, 98, 78, 66, 50,
+    ],
+    odd_frame_skip: false,
+    emphasis_swapped: true,
+};
+
+const DENDY: Timing = Timing {
+    region: Region::Dendy,
+    master_clock_hz: 26_601_712,
+    cpu_divider: 15,
+    ppu_divider: 5,
+    scanlines: 312,
+    vblank_scanline: 291,
+    frame_rate_hz: 50.0070,
+    frame_counter_steps: NTSC.frame_counter_steps,
+    frame_counter_5_step: NTSC.frame_counter_5_step,
+    noise_periods: NTSC.noise_periods,
+    dmc_rates: NTSC.dmc_rates,
+    odd_frame_skip: false,
+    emphasis_swapped: true,
+};
+
+impl Region {
+    pub fn timing(self) -> &'static Timing {
+        match self {
+            Region::Ntsc => &NTSC,
+            Region::Pal => &PAL,
+            Region::Dendy => &DENDY,
+        }
+    }
+}
+
+impl Timing {
+    /// CPU cycles per second.
+    pub fn cpu_clock_hz(&self) -> f64 {
+        f64::from(self.master_clock_hz) / f64::from(self.cpu_divider)
+    }
+
+    /// PPU dots per CPU cycle: exactly 3 on NTSC and Dendy, 3.2 on PAL.
+    pub fn dots_per_cpu_cycle(&self) -> f64 {
+        f64::from(self.cpu_divider) / f64::from(self.ppu_divider)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_clock_rates() {
+        let ntsc = Region::Ntsc.timing();
+        assert_eq!(ntsc.cpu_clock_hz() as u32, 1_789_772);
+        assert_eq!(ntsc.dots_per_cpu_cycle(), 3.0);
+
+        let pal = Region::Pal.timing();
+        assert_eq!(pal.cpu_clock_hz() as u32, 1_662_607);
+        assert_eq!(pal.dots_per_cpu_cycle(), 3.2);
+
+        assert_eq!(Region::Dendy.timing().dots_per_cpu_cycle(), 3.0)
+    }
+
+    #[test]
+    fn test_frame_rate_matches_dots_per_frame() {
+        for region in [Region::Ntsc, Region::Pal] {
+            let timing = region.timing();
+            let dots = 341.0 * f64::from(timing.scanlines);
+            let fps = f64::from(timing.master_clock_hz) / f64::from(timing.ppu_divider) / dots;
+            assert!(
+                (fps - timing.frame_rate_hz).abs() < 0.01,
+                "{region:?}: {fps}"
+            );
+        }
+    }
+}
@@ -0,0 +1,295 @@
+//! A rolling history of save states, for rewinding.
+//!
+//! [`Nes`](crate::Nes) saves a snapshot every [`RewindConfig::interval`] frames into a
+//! [`RewindBuffer`] and [`Nes::rewind`](crate::Nes::rewind) goes back through them. Consecutive
+//! states are nearly identical, so only the newest is kept whole. Every older one is stored as how
+//! it differs from the one after it: the two XORed together, which is mostly zeros, with the runs
+//! of zeros squeezed out.
+//!
+//! ```text
+//! repeated: zero run length, literal length (both LEB128), literal bytes
+//! ```
+//!
+//! Going back a snapshot undoes the newest difference, and when the buffer outgrows its memory
+//! budget the oldest differences are dropped, without anything else needing to be recompressed.
+
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+
+/// How often snapshots are taken and how much memory they may use.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RewindConfig {
+    /// Frames between snapshots. Rewinding goes back this many frames at a time.
+    pub interval: u32,
+    /// Bytes the snapshots may take up before the oldest are dropped.
+    pub budget: usize,
+}
+
+impl Default for RewindConfig {
+    /// A snapshot every other frame in 32MB, which is minutes of play for most games.
+    fn default() -> Self {
+        Self {
+            interval: 2,
+            budget: 32 << 20,
+        }
+    }
+}
+
+/// An older snapshot, stored as its difference from the next one.
+#[derive(Debug, Clone)]
+struct Delta {
+    frame: u64,
+    /// The snapshot's length, which can differ from the next one's.
+    len: usize,
+    data: Vec<u8>,
+}
+
+#[derive(Debug, Clone)]
+pub struct RewindBuffer {
+    config: RewindConfig,
+    /// Frames until the next snapshot is due.
+    countdown: u32,
+    /// The newest snapshot in full, with the PPU frame count it was taken at.
+    latest: Option<(u64, Vec<u8>)>,
+    /// The older ones, oldest first.
+    deltas: VecDeque<Delta>,
+    delta_bytes: usize,
+}
+
+impl RewindBuffer {
+    pub fn new(config: RewindConfig) -> Self {
+        let config = RewindConfig {
+            interval: config.interval.max(1),
+            ..config
+        };
+        Self {
+            config,
+            countdown: config.interval,
+            latest: None,
+            deltas: VecDeque::new(),
+            delta_bytes: 0,
+        }
+    }
+
+    pub fn config(&self) -> RewindConfig {
+        self.config
+    }
+
+    /// Snapshots held, the newest included.
+    pub fn len(&self) -> usize {
+        self.deltas.len() + usize::from(self.latest.is_some())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.latest.is_none()
+    }
+
+    /// How many bytes the snapshots take up.
+    pub fn memory_used(&self) -> usize {
+        self.latest.as_ref().map_or(0, |(_, state)| state.len()) + self.delta_bytes
+    }
+
+    /// The frame count of the oldest snapshot, as far back as rewinding can go.
+    pub fn oldest_frame(&self) -> Option<u64> {
+        match self.deltas.front() {
+            Some(delta) => Some(delta.frame),
+            None => self.latest.as_ref().map(|&(frame, _)| frame),
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.latest = None;
+        self.deltas.clear();
+        self.delta_bytes = 0;
+        self.countdown = self.config.interval;
+    }
+
+    /// Counts a frame. Returns whether a snapshot is due.
+    pub(crate) fn frame_done(&mut self) -> bool {
+        self.countdown -= 1;
+        if self.countdown > 0 {
+            return false;
+        }
+        self.countdown = self.config.interval;
+        true
+    }
+
+    /// Adds `state`, saved at PPU frame `frame`, as the newest snapshot.
+    pub fn push(&mut self, frame: u64, state: Vec<u8>) {
+        if let Some((previous_frame, previous)) = self.latest.replace((frame, state)) {
+            let newest = &self.latest.as_ref().expect("just replaced").1;
+            let data = encode(newest, &previous);
+            self.delta_bytes += data.len();
+            self.deltas.push_back(Delta {
+                frame: previous_frame,
+                len: previous.len(),
+                data,
+            });
+        }
+        while self.memory_used() > self.config.budget {
+            let Some(oldest) = self.deltas.pop_front() else {
+                break;
+            };
+            self.delta_bytes -= oldest.data.len();
+        }
+    }
+
+    /// Drops every snapshot taken after PPU frame `frame`, and returns the newest one left with
+    /// its frame count. Stops at the oldest snapshot if none is that old. The returned snapshot stays
+    /// in the buffer, so rewinding can carry on from it.
+    pub fn rewind_to(&mut self, frame: u64) -> Option<(u64, &[u8])> {
+        loop {
+            let (latest_frame, latest) = self.latest.as_mut()?;
+            if *latest_frame <= frame {
+                break;
+            }
+            let Some(delta) = self.deltas.pop_back() else {
+                break;
+            };
+            self.delta_bytes -= delta.data.len();
+            *latest = decode(latest, &delta.data, delta.len);
+            *latest_frame = delta.frame;
+        }
+        self.countdown = self.config.interval;
+        self.latest
+            .as_ref()
+            .map(|(frame, state)| (*frame, state.as_slice()))
+    }
+}
+
+/// `older` as a difference from `newer`.
+fn encode(newer: &[u8], older: &[u8]) -> Vec<u8> {
+    let len = newer.len().max(older.len());
+    let xor = |i: usize| newer.get(i).unwrap_or(&0) ^ older.get(i).unwrap_or(&0);
+    let mut out = Vec::new();
+    let mut i = 0;
+    while i < len {
+        let zeros_start = i;
+        while i < len && xor(i) == 0 {
+            i += 1;
+        }
+        let literal_start = i;
+        while i < len && xor(i) != 0 {
+            i += 1;
+        }
+        write_len(&mut out, literal_start - zeros_start);
+        write_len(&mut out, i - literal_start);
+        out.extend((literal_start..i).map(xor));
+    }
+    out
+}
+
+/// Undoes [`encode`]: the `len`-byte older snapshot from `newer` and their difference.
+fn decode(newer: &[u8], delta: &[u8], len: usize) -> Vec<u8> {
+    let mut older = newer.to_vec();
+    older.resize(older.len().max(len), 0);
+    let mut delta = delta.iter().copied();
+    let mut i = 0;
+    while let Some(zeros) = read_len(&mut delta) {
+        i += zeros;
+        let literals = read_len(&mut delta).unwrap_or(0);
+        for (byte, xor) in older[i..i + literals].iter_mut().zip(&mut delta) {
+            *byte ^= xor;
+        }
+        i += literals;
+    }
+    older.truncate(len);
+    older
+}
+
+fn write_len(out: &mut Vec<u8>, mut len: usize) {
+    while len >= 0x80 {
+        out.push(len as u8 | 0x80);
+        len >>= 7;
+    }
+    out.push(len as u8);
+}
+
+fn read_len(bytes: &mut impl Iterator<Item = u8>) -> Option<usize> {
+    let mut len = 0;
+    let mut shift = 0;
+    loop {
+        let byte = bytes.next()?;
+        len |= usize::from(byte & 0x7F) << shift;
+        if byte & 0x80 == 0 {
+            return Some(len);
+        }
+        shift += 7;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    #[test]
+    fn test_delta_round_trip() {
+        let mut newer = vec![0x55; 1000];
+        newer[300] = 1;
+        let mut older = newer.clone();
+        older[10] = 9;
+        older[500..700].fill(0xAA);
+        let delta = encode(&newer, &older);
+        assert!(delta.len() < 220, "{}", delta.len());
+        assert_eq!(decode(&newer, &delta, older.len()), older);
+
+        // Longer and shorter neighbours.
+        older.push(3);
+        assert_eq!(decode(&newer, &encode(&newer, &older), older.len()), older);
+        older.truncate(20);
+        assert_eq!(decode(&newer, &encode(&newer, &older), older.len()), older);
+        assert!(encode(&newer, &newer).len() <= 4)
+    }
+
+    /// A 100-byte state for frame `frame`.
+    fn state(frame: u64) -> Vec<u8> {
+        let mut state = vec![0; 100];
+        state[..8].copy_from_slice(&frame.to_le_bytes());
+        state
+    }
+
+    #[test]
+    fn test_rewind_to() {
+        let mut buffer = RewindBuffer::new(RewindConfig::default());
+        assert!(buffer.rewind_to(0).is_none());
+        for frame in [2, 4, 6, 8] {
+            buffer.push(frame, state(frame));
+        }
+        assert_eq!(buffer.len(), 4);
+        assert_eq!(buffer.oldest_frame(), Some(2));
+
+        assert_eq!(buffer.rewind_to(5), Some((4, &state(4)[..])));
+        assert_eq!(buffer.len(), 2);
+        // The snapshot rewound to is kept.
+        assert_eq!(buffer.rewind_to(4), Some((4, &state(4)[..])));
+        assert_eq!(buffer.rewind_to(0), Some((2, &state(2)[..])));
+        assert_eq!(buffer.len(), 1)
+    }
+
+    #[test]
+    fn test_budget_drops_the_oldest() {
+        let mut buffer = RewindBuffer::new(RewindConfig {
+            interval: 1,
+            budget: 150,
+        });
+        for frame in 0..20 {
+            buffer.push(frame, state(frame));
+            assert!(buffer.memory_used() <= 150);
+        }
+        assert_eq!(buffer.rewind_to(19), Some((19, &state(19)[..])));
+        let oldest = buffer.oldest_frame().unwrap();
+        assert!(oldest > 0 && oldest < 19);
+        assert_eq!(buffer.rewind_to(0), Some((oldest, &state(oldest)[..])))
+    }
+
+    #[test]
+    fn test_interval() {
+        let mut buffer = RewindBuffer::new(RewindConfig {
+            interval: 3,
+            ..RewindConfig::default()
+        });
+        let due: Vec<bool> = (0..6).map(|_| buffer.frame_done()).collect();
+        assert_eq!(due, [false, false, true, false, false, true])
+    }
+}
@@ -0,0 +1,353 @@
+//! Numbered save state slots, with an autosave on the side.
+//!
+//! A [`SaveSlot`] is a save state along with what a frontend's slot menu shows about it: when it
+//! was saved, how far into the game, and a small picture of the screen. [`SlotManager`] keeps a
+//! fixed number of them and, if asked to, autosaves into a separate slot every so many frames.
+//!
+//! The core has no clock of its own, so timestamps come from the caller, as time since the Unix
+//! epoch. Slots can be written out and read back with [`SaveSlot::to_bytes`] and
+//! [`SaveSlot::from_bytes`]:
+//!
+//! ```text
+//! 0-7   "NESSLOT" followed by $1A
+//! 8-19  when it was saved: u64 seconds and u32 nanoseconds
+//! 20-27 the PPU's frame count
+//! 28-   the thumbnail, then the save state, each with a u32 length in front
+//! ```
+
+use alloc::format;
+use alloc::vec::Vec;
+use core::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::{NesError, Result};
+use crate::nes::Nes;
+use crate::ppu::{HEIGHT, WIDTH};
+use crate::state;
+
+const SLOT_MAGIC: [u8; 8] = *b"NESSLOT\x1A";
+
+/// Thumbnails are the picture scaled down by this much each way.
+const THUMBNAIL_SCALE: usize = 4;
+pub const THUMBNAIL_WIDTH: usize = WIDTH / THUMBNAIL_SCALE;
+pub const THUMBNAIL_HEIGHT: usize = HEIGHT / THUMBNAIL_SCALE;
+
+/// How many numbered slots [`SlotManager::default`] has.
+pub const DEFAULT_SLOTS: usize = 10;
+
+/// A save state and what to show about it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SaveSlot {
+    saved_at: Duration,
+    frame: u64,
+    thumbnail: Vec<u8>,
+    state: Vec<u8>,
+}
+
+impl SaveSlot {
+    /// Saves `nes` as it is now, at `saved_at` since the Unix epoch.
+    pub fn capture(nes: &Nes, saved_at: Duration) -> Self {
+        Self {
+            saved_at,
+            frame: nes.bus().ppu().frame_count(),
+            thumbnail: thumbnail(nes.frame()),
+            state: nes.save_state(),
+        }
+    }
+
+    /// When the slot was saved, as time since the Unix epoch.
+    pub fn saved_at(&self) -> Duration {
+        self.saved_at
+    }
+
+    /// Frames the console had run when it was saved.
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+
+    /// The screen when it was saved, RGBA8 at [`THUMBNAIL_WIDTH`] by [`THUMBNAIL_HEIGHT`].
+    pub fn thumbnail(&self) -> &[u8] {
+        &self.thumbnail
+    }
+
+    /// The save state itself, for [`Nes::load_state`].
+    pub fn state(&self) -> &[u8] {
+        &self.state
+    }
+
+    /// The slot as one blob, thumbnail included.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        state::to_bytes(&(SLOT_MAGIC, self))
+    }
+
+    /// Reads back a blob from [`to_bytes`](Self::to_bytes).
+    ///
+    /// # Errors
+    /// Returns [`NesError::InvalidState`] if `bytes` isn't a save slot.
+    pub fn from_bytes(bytes: &[u8]) -> Result<SaveSlot> {
+        if !bytes.starts_with(&SLOT_MAGIC) {
+            return Err(NesError::InvalidState("not a save slot".into()));
+        }
+        let (_, slot): ([u8; 8], SaveSlot) = state::from_bytes(bytes)?;
+        if slot.thumbnail.len() != THUMBNAIL_WIDTH * THUMBNAIL_HEIGHT * 4 {
+            return Err(NesError::InvalidState("bad save slot thumbnail".into()));
+        }
+        Ok(slot)
+    }
+}
+
+#[cfg(feature = "std")]
+impl SaveSlot {
+    /// Reads a slot saved with [`save_file`](Self::save_file).
+    ///
+    /// # Errors
+    /// Returns [`NesError::Io`] if the file can't be read, or [`NesError::InvalidState`] if it
+    /// isn't a save slot.
+    pub fn from_file(path: impl AsRef<std::path::Path>) -> Result<SaveSlot> {
+        SaveSlot::from_bytes(&std::fs::read(path)?)
+    }
+
+    /// Writes the slot to a file.
+    ///
+    /// # Errors
+    /// Returns [`NesError::Io`] if the file can't be written.
+    pub fn save_file(&self, path: impl AsRef<std::path::Path>) -> Result<()> {
+        std::fs::write(path, self.to_bytes())?;
+        Ok(())
+    }
+}
+
+/// Scales an RGBA8 picture down by [`THUMBNAIL_SCALE`], averaging each block of pixels.
+fn thumbnail(frame: &[u8]) -> Vec<u8> {
+    let mut thumbnail = Vec::with_capacity(THUMBNAIL_WIDTH * THUMBNAIL_HEIGHT * 4);
+    for y in 0..THUMBNAIL_HEIGHT {
+        for x in 0..THUMBNAIL_WIDTH {
+            let mut sum = [0u32; 4];
+            for dy in 0..THUMBNAIL_SCALE {
+                let row = (y * THUMBNAIL_SCALE + dy) * WIDTH;
+                for dx in 0..THUMBNAIL_SCALE {
+                    let pixel = (row + x * THUMBNAIL_SCALE + dx) * 4;
+                    for (sum, &channel) in sum.iter_mut().zip(&frame[pixel..pixel + 4]) {
+                        *sum += u32::from(channel);
+                    }
+                }
+            }
+            let pixels = (THUMBNAIL_SCALE * THUMBNAIL_SCALE) as u32;
+            thumbnail.extend(sum.map(|sum| (sum / pixels) as u8));
+        }
+    }
+    thumbnail
+}
+
+/// A row of numbered save slots and an autosave.
+#[derive(Debug, Clone)]
+pub struct SlotManager {
+    slots: Vec<Option<SaveSlot>>,
+    autosave: Option<SaveSlot>,
+    /// Frames between autosaves, `None` when autosave is off.
+    autosave_interval: Option<u32>,
+    frames_since_autosave: u32,
+}
+
+impl Default for SlotManager {
+    fn default() -> Self {
+        Self::new(DEFAULT_SLOTS)
+    }
+}
+
+impl SlotManager {
+    /// `count` empty slots, numbered from 0, with autosave off.
+    pub fn new(count: usize) -> Self {
+        Self {
+            slots: alloc::vec![None; count],
+            autosave: None,
+            autosave_interval: None,
+            frames_since_autosave: 0,
+        }
+    }
+
+    pub fn slot_count(&self) -> usize {
+        self.slots.len()
+    }
+
+    /// What's in slot `slot`, or `None` if it's empty or doesn't exist.
+    pub fn slot(&self, slot: usize) -> Option<&SaveSlot> {
+        self.slots.get(slot)?.as_ref()
+    }
+
+    /// Saves `nes` into slot `slot`, replacing what was there. `now` is the time since the Unix
+    /// epoch.
+    ///
+    /// # Errors
+    /// Returns [`NesError::InvalidState`] if there is no slot `slot`.
+    pub fn save(&mut self, slot: usize, nes: &Nes, now: Duration) -> Result<&SaveSlot> {
+        let entry = self.entry(slot)?;
+        Ok(entry.insert(SaveSlot::capture(nes, now)))
+    }
+
+    /// Loads slot `slot` into `nes`.
+    ///
+    /// # Errors
+    /// Returns [`NesError::InvalidState`] if the slot is empty or doesn't exist, or its state
+    /// doesn't fit `nes`.
+    pub fn load(&self, slot: usize, nes: &mut Nes) -> Result<()> {
+        let saved = self
+            .slot(slot)
+            .ok_or_else(|| NesError::InvalidState(format!("save slot {slot} is empty")))?;
+        nes.load_state(saved.state())
+    }
+
+    /// Puts `saved` into slot `slot`, e.g. one read back from a file, or empties it with `None`.
+    /// Returns what was there.
+    ///
+    /// # Errors
+    /// Returns [`NesError::InvalidState`] if there is no slot `slot`.
+    pub fn set_slot(&mut self, slot: usize, saved: Option<SaveSlot>) -> Result<Option<SaveSlot>> {
+        let entry = self.entry(slot)?;
+        Ok(core::mem::replace(entry, saved))
+    }
+
+    /// The most recent autosave.
+    pub fn autosave(&self) -> Option<&SaveSlot> {
+        self.autosave.as_ref()
+    }
+
+    /// Loads the most recent autosave into `nes`.
+    ///
+    /// # Errors
+    /// Returns [`NesError::InvalidState`] if there is no autosave yet or it doesn't fit `nes`.
+    pub fn load_autosave(&self, nes: &mut Nes) -> Result<()> {
+        let saved = self
+            .autosave()
+            .ok_or_else(|| NesError::InvalidState("no autosave".into()))?;
+        nes.load_state(saved.state())
+    }
+
+    pub fn set_autosave(&mut self, saved: Option<SaveSlot>) {
+        self.autosave = saved;
+    }
+
+    pub fn autosave_interval(&self) -> Option<u32> {
+        self.autosave_interval
+    }
+
+    /// Autosaves every `frames` frames, or never with `None`. The count starts over.
+    pub fn set_autosave_interval(&mut self, frames: Option<u32>) {
+        self.autosave_interval = frames.map(|frames| frames.max(1));
+        self.frames_since_autosave = 0;
+    }
+
+    /// Call after every [`Nes::run_frame`]. Autosaves `nes` when it's due, returning whether it
+    /// did. `now` is the time since the Unix epoch.
+    pub fn end_frame(&mut self, nes: &Nes, now: Duration) -> bool {
+        let Some(interval) = self.autosave_interval else {
+            return false;
+        };
+        self.frames_since_autosave += 1;
+        if self.frames_since_autosave < interval {
+            return false;
+        }
+        self.frames_since_autosave = 0;
+        self.autosave = Some(SaveSlot::capture(nes, now));
+        true
+    }
+
+    fn entry(&mut self, slot: usize) -> Result<&mut Option<SaveSlot>> {
+        let count = self.slots.len();
+        self.slots.get_mut(slot).ok_or_else(|| {
+            NesError::InvalidState(format!("no save slot {slot}, there are {count}"))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::{test_image, Cartridge};
+
+    /// Turns rendering on over a blank screen and spins.
+    fn nes() -> Nes {
+        let mut raw = test_image(0, 1, 1, 0);
+        raw[16..24].copy_from_slice(&[0xA9, 0x08, 0x8D, 0x01, 0x20, 0x4C, 0x05, 0xC0]);
+        raw[16 + 0x3FFC..16 + 0x3FFE].copy_from_slice(&[0x00, 0xC0]);
+        Nes::new(Cartridge::new(&raw).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let mut nes = nes();
+        let mut slots = SlotManager::default();
+        assert_eq!(slots.slot_count(), DEFAULT_SLOTS);
+        assert!(slots.slot(3).is_none());
+        assert!(slots.load(3, &mut nes).is_err());
+
+        nes.run_frame().unwrap();
+        let saved = slots.save(3, &nes, Duration::from_secs(1_000)).unwrap();
+        assert_eq!(saved.frame(), 1);
+        assert_eq!(saved.saved_at(), Duration::from_secs(1_000));
+        nes.run_frame().unwrap();
+        slots.load(3, &mut nes).unwrap();
+        assert_eq!(nes.bus().ppu().frame_count(), 1);
+
+        assert!(slots.save(DEFAULT_SLOTS, &nes, Duration::ZERO).is_err());
+        assert!(slots.set_slot(3, None).unwrap().is_some());
+        assert!(slots.slot(3).is_none())
+    }
+
+    #[test]
+    fn test_thumbnail() {
+        let mut nes = nes();
+        nes.run_frame().unwrap();
+        let saved = SaveSlot::capture(&nes, Duration::ZERO);
+        assert_eq!(
+            saved.thumbnail().len(),
+            THUMBNAIL_WIDTH * THUMBNAIL_HEIGHT * 4
+        );
+        assert_eq!(saved.thumbnail()[..4], nes.frame()[..4]);
+
+        let mut frame = alloc::vec![0; WIDTH * HEIGHT * 4];
+        // Half of the top left block white.
+        for y in 0..2 {
+            frame[y * WIDTH * 4..(y * WIDTH + 4) * 4].fill(0xFF);
+        }
+        assert_eq!(thumbnail(&frame)[..8], [0x7F, 0x7F, 0x7F, 0x7F, 0, 0, 0, 0])
+    }
+
+    #[test]
+    fn test_slot_bytes() {
+        let mut nes = nes();
+        nes.run_frame().unwrap();
+        let saved = SaveSlot::capture(&nes, Duration::new(1_700_000_000, 5));
+        let bytes = saved.to_bytes();
+        assert_eq!(&bytes[..8], b"NESSLOT\x1A");
+        assert_eq!(SaveSlot::from_bytes(&bytes).unwrap(), saved);
+        assert!(SaveSlot::from_bytes(saved.state()).is_err());
+        assert!(SaveSlot::from_bytes(&bytes[..bytes.len() - 1]).is_err())
+    }
+
+    #[test]
+    fn test_autosave() {
+        let mut nes = nes();
+        let mut slots = SlotManager::new(1);
+        nes.run_frame().unwrap();
+        assert!(!slots.end_frame(&nes, Duration::ZERO));
+
+        slots.set_autosave_interval(Some(3));
+        let mut saves = 0;
+        for second in 0..7 {
+            nes.run_frame().unwrap();
+            if slots.end_frame(&nes, Duration::from_secs(second)) {
+                saves += 1;
+            }
+        }
+        assert_eq!(saves, 2);
+        let autosave = slots.autosave().unwrap();
+        assert_eq!(autosave.saved_at(), Duration::from_secs(5));
+        assert_eq!(autosave.frame(), 7);
+
+        nes.run_frame().unwrap();
+        slots.load_autosave(&mut nes).unwrap();
+        assert_eq!(nes.bus().ppu().frame_count(), 7)
+    }
+}
@@ -0,0 +1,874 @@
+//! Save states: the whole console frozen into bytes, to be thawed later.
+//!
+//! Components derive serde's traits and are written in a compact binary format of this crate's
+//! own. Fields follow one another in declaration order with nothing in between: integers and floats
+//! are little-endian and fixed width, `bool`s and `Option` tags one byte, and sequences, strings and
+//! enum variants are prefixed with a `u32`. Nothing is self-describing, so a state only loads into
+//! the build of the structs that wrote it; [`STATE_VERSION`] is bumped whenever they change.
+//!
+//! Every state starts with a header:
+//!
+//! ```text
+//! 0-3   "NESS"
+//! 4-5   STATE_VERSION
+//! 6-9   region, 0 NTSC, 1 PAL, 2 Dendy
+//! 10-13 checksum of the cartridge's ROM
+//! ```
+//!
+//! Only emulated state is saved. Host settings (audio filters and volumes, the sample rate,
+//! emulation speed) and the finished picture are left as they are when a state is loaded.
+
+use alloc::string::ToString;
+use alloc::vec::Vec;
+use core::fmt::Display;
+
+use serde::de::{self, DeserializeOwned, DeserializeSeed, IntoDeserializer, Visitor};
+use serde::ser::{self, Serialize};
+
+use crate::cartridge::Cartridge;
+use crate::error::{NesError, Result};
+use crate::region::Region;
+
+const MAGIC: [u8; 4] = *b"NESS";
+
+/// The layout of the states this build writes. States with any other version are refused.
+pub const STATE_VERSION: u16 = 1;
+
+/// Starts a state for a `region` console running the cartridge with ROM checksum `rom`.
+pub(crate) fn write_header(writer: &mut StateWriter, region: Region, rom: u32) {
+    writer.write(&(MAGIC, STATE_VERSION, region, rom));
+}
+
+/// Checks that a state was written by this build for the same console and cartridge.
+pub(crate) fn read_header(reader: &mut StateReader, region: Region, rom: u32) -> Result<()> {
+    let (magic, version, state_region, state_rom): ([u8; 4], u16, Region, u32) = reader.read()?;
+    if magic != MAGIC {
+        return Err(NesError::InvalidState("not a save state".into()));
+    }
+    if version != STATE_VERSION {
+        return Err(NesError::InvalidState(alloc::format!(
+            "save state version {version}, expected {STATE_VERSION}"
+        )));
+    }
+    if state_region != region {
+        return Err(NesError::InvalidState(alloc::format!(
+            "save state is from a {state_region:?} console"
+        )));
+    }
+    if state_rom != rom {
+        return Err(NesError::InvalidState(
+            "save state is from a different cartridge".into(),
+        ));
+    }
+    Ok(())
+}
+
+/// An FNV-1a hash of the cartridge's ROM, so states aren't loaded into the wrong game. CHR RAM is
+/// state, not ROM, and is left out.
+pub(crate) fn rom_checksum(cart: &Cartridge) -> u32 {
+    let chr: &[u8] = if cart.chr_ram { &[] } else { &cart.chr_rom };
+    cart.prg_rom
+        .iter()
+        .chain(chr)
+        .fold(0x811C_9DC5, |hash, &byte| {
+            (hash ^ u32::from(byte)).wrapping_mul(0x0100_0193)
+        })
+}
+
+/// `value` on its own, for components saved as a separate blob.
+pub(crate) fn to_bytes<T: Serialize + ?Sized>(value: &T) -> Vec<u8> {
+    let mut writer = StateWriter::new();
+    writer.write(value);
+    writer.into_bytes()
+}
+
+/// Reads back a blob from [`to_bytes`], which must hold nothing else.
+pub(crate) fn from_bytes<T: DeserializeOwned>(bytes: &[u8]) -> Result<T> {
+    let mut reader = StateReader::new(bytes);
+    let value = reader.read()?;
+    reader.finish()?;
+    Ok(value)
+}
+
+/// Appends values to a state.
+#[derive(Debug, Default)]
+pub(crate) struct StateWriter {
+    bytes: Vec<u8>,
+}
+
+impl StateWriter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn write<T: Serialize + ?Sized>(&mut self, value: &T) {
+        // Only lengths past u32::MAX can fail, and nothing in a console is that big.
+        value
+            .serialize(&mut *self)
+            .expect("state too large to serialize");
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.bytes
+    }
+
+    fn write_len(&mut self, len: Option<usize>) -> Result<()> {
+        let len = len.ok_or_else(|| NesError::InvalidState("unknown length".into()))?;
+        let len = u32::try_from(len).map_err(|_| NesError::InvalidState("too long".into()))?;
+        self.bytes.extend_from_slice(&len.to_le_bytes());
+        Ok(())
+    }
+}
+
+/// Reads values back out of a state, in the order they were written.
+pub(crate) struct StateReader<'de> {
+    bytes: &'de [u8],
+}
+
+impl<'de> StateReader<'de> {
+    pub fn new(bytes: &'de [u8]) -> Self {
+        Self { bytes }
+    }
+
+    pub fn read<T: DeserializeOwned>(&mut self) -> Result<T> {
+        T::deserialize(&mut *self)
+    }
+
+    /// Reads a byte sequence into `dest`, which it must exactly fill.
+    pub fn read_into(&mut self, dest: &mut [u8]) -> Result<()> {
+        let len = self.read_len()?;
+        if len != dest.len() {
+            return Err(NesError::InvalidState(alloc::format!(
+                "expected {} bytes of memory, found {len}",
+                dest.len()
+            )));
+        }
+        for byte in dest {
+            *byte = self.take::<1>()?[0];
+        }
+        Ok(())
+    }
+
+    /// Fails if anything is left over.
+    pub fn finish(self) -> Result<()> {
+        if self.bytes.is_empty() {
+            Ok(())
+        } else {
+            Err(NesError::InvalidState("trailing bytes".into()))
+        }
+    }
+
+    fn take<const N: usize>(&mut self) -> Result<[u8; N]> {
+        let (bytes, rest) = self
+            .bytes
+            .split_first_chunk()
+            .ok_or_else(|| NesError::InvalidState("truncated".into()))?;
+        self.bytes = rest;
+        Ok(*bytes)
+    }
+
+    fn take_slice(&mut self, len: usize) -> Result<&'de [u8]> {
+        if self.bytes.len() < len {
+            return Err(NesError::InvalidState("truncated".into()));
+        }
+        let (bytes, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(bytes)
+    }
+
+    fn read_len(&mut self) -> Result<usize> {
+        Ok(u32::from_le_bytes(self.take()?) as usize)
+    }
+}
+
+impl ser::Error for NesError {
+    fn custom<T: Display>(msg: T) -> Self {
+        NesError::InvalidState(msg.to_string())
+    }
+}
+
+impl de::Error for NesError {
+    fn custom<T: Display>(msg: T) -> Self {
+        NesError::InvalidState(msg.to_string())
+    }
+}
+
+impl ser::Serializer for &mut StateWriter {
+    type Ok = ();
+    type Error = NesError;
+    type SerializeSeq = Self;
+    type SerializeTuple = Self;
+    type SerializeTupleStruct = Self;
+    type SerializeTupleVariant = Self;
+    type SerializeMap = Self;
+    type SerializeStruct = Self;
+    type SerializeStructVariant = Self;
+
+    fn serialize_bool(self, v: bool) -> Result<()> {
+        self.bytes.push(u8::from(v));
+        Ok(())
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<()> {
+        self.bytes.extend_from_slice(&v.to_le_bytes());
+        Ok(())
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<()> {
+        self.bytes.extend_from_slice(&v.to_le_bytes());
+        Ok(())
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<()> {
+        self.bytes.extend_from_slice(&v.to_le_bytes());
+        Ok(())
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<()> {
+        self.bytes.extend_from_slice(&v.to_le_bytes());
+        Ok(())
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<()> {
+        self.bytes.push(v);
+        Ok(())
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<()> {
+        self.bytes.extend_from_slice(&v.to_le_bytes());
+        Ok(())
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<()> {
+        self.bytes.extend_from_slice(&v.to_le_bytes());
+        Ok(())
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<()> {
+        self.bytes.extend_from_slice(&v.to_le_bytes());
+        Ok(())
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<()> {
+        self.bytes.extend_from_slice(&v.to_le_bytes());
+        Ok(())
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<()> {
+        self.bytes.extend_from_slice(&v.to_le_bytes());
+        Ok(())
+    }
+
+    fn serialize_char(self, v: char) -> Result<()> {
+        self.serialize_u32(u32::from(v))
+    }
+
+    fn serialize_str(self, v: &str) -> Result<()> {
+        self.serialize_bytes(v.as_bytes())
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<()> {
+        self.write_len(Some(v.len()))?;
+        self.bytes.extend_from_slice(v);
+        Ok(())
+    }
+
+    fn serialize_none(self) -> Result<()> {
+        self.bytes.push(0);
+        Ok(())
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<()> {
+        self.bytes.push(1);
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<()> {
+        Ok(())
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<()> {
+        Ok(())
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        variant_index: u32,
+        _variant: &'static str,
+    ) -> Result<()> {
+        self.serialize_u32(variant_index)
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<()> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        variant_index: u32,
+        _variant: &'static str,
+        value: &T,
+    ) -> Result<()> {
+        self.serialize_u32(variant_index)?;
+        value.serialize(self)
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> Result<Self> {
+        self.write_len(len)?;
+        Ok(self)
+    }
+
+    fn serialize_tuple(self, _len: usize) -> Result<Self> {
+        Ok(self)
+    }
+
+    fn serialize_tuple_struct(self, _name: &'static str, _len: usize) -> Result<Self> {
+        Ok(self)
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self> {
+        self.serialize_u32(variant_index)?;
+        Ok(self)
+    }
+
+    fn serialize_map(self, len: Option<usize>) -> Result<Self> {
+        self.write_len(len)?;
+        Ok(self)
+    }
+
+    fn serialize_struct(self, _name: &'static str, _len: usize) -> Result<Self> {
+        Ok(self)
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self> {
+        self.serialize_u32(variant_index)?;
+        Ok(self)
+    }
+
+    fn is_human_readable(&self) -> bool {
+        false
+    }
+}
+
+impl ser::SerializeSeq for &mut StateWriter {
+    type Ok = ();
+    type Error = NesError;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        value.serialize(&mut **self)
+    }
+
+    fn end(self) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl ser::SerializeTuple for &mut StateWriter {
+    type Ok = ();
+    type Error = NesError;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        value.serialize(&mut **self)
+    }
+
+    fn end(self) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl ser::SerializeTupleStruct for &mut StateWriter {
+    type Ok = ();
+    type Error = NesError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        value.serialize(&mut **self)
+    }
+
+    fn end(self) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl ser::SerializeTupleVariant for &mut StateWriter {
+    type Ok = ();
+    type Error = NesError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        value.serialize(&mut **self)
+    }
+
+    fn end(self) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl ser::SerializeMap for &mut StateWriter {
+    type Ok = ();
+    type Error = NesError;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<()> {
+        key.serialize(&mut **self)
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        value.serialize(&mut **self)
+    }
+
+    fn end(self) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl ser::SerializeStruct for &mut StateWriter {
+    type Ok = ();
+    type Error = NesError;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        _key: &'static str,
+        value: &T,
+    ) -> Result<()> {
+        value.serialize(&mut **self)
+    }
+
+    fn end(self) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl ser::SerializeStructVariant for &mut StateWriter {
+    type Ok = ();
+    type Error = NesError;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        _key: &'static str,
+        value: &T,
+    ) -> Result<()> {
+        value.serialize(&mut **self)
+    }
+
+    fn end(self) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl<'de> de::Deserializer<'de> for &mut StateReader<'de> {
+    type Error = NesError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value> {
+        Err(NesError::InvalidState(
+            "save states aren't self-describing".into(),
+        ))
+    }
+
+    fn deserialize_bool<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        match self.take::<1>()? {
+            [0] => visitor.visit_bool(false),
+            [1] => visitor.visit_bool(true),
+            [byte] => Err(NesError::InvalidState(alloc::format!("bad bool {byte}"))),
+        }
+    }
+
+    fn deserialize_i8<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_i8(i8::from_le_bytes(self.take()?))
+    }
+
+    fn deserialize_i16<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_i16(i16::from_le_bytes(self.take()?))
+    }
+
+    fn deserialize_i32<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_i32(i32::from_le_bytes(self.take()?))
+    }
+
+    fn deserialize_i64<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_i64(i64::from_le_bytes(self.take()?))
+    }
+
+    fn deserialize_u8<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_u8(self.take::<1>()?[0])
+    }
+
+    fn deserialize_u16<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_u16(u16::from_le_bytes(self.take()?))
+    }
+
+    fn deserialize_u32<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_u32(u32::from_le_bytes(self.take()?))
+    }
+
+    fn deserialize_u64<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_u64(u64::from_le_bytes(self.take()?))
+    }
+
+    fn deserialize_f32<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_f32(f32::from_le_bytes(self.take()?))
+    }
+
+    fn deserialize_f64<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_f64(f64::from_le_bytes(self.take()?))
+    }
+
+    fn deserialize_char<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        let code = u32::from_le_bytes(self.take()?);
+        let c = char::from_u32(code)
+            .ok_or_else(|| NesError::InvalidState(alloc::format!("bad char {code:#x}")))?;
+        visitor.visit_char(c)
+    }
+
+    fn deserialize_str<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        let len = self.read_len()?;
+        let s = core::str::from_utf8(self.take_slice(len)?)
+            .map_err(|_| NesError::InvalidState("bad UTF-8".into()))?;
+        visitor.visit_borrowed_str(s)
+    }
+
+    fn deserialize_string<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        self.deserialize_str(visitor)
+    }
+
+    fn deserialize_bytes<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        let len = self.read_len()?;
+        visitor.visit_borrowed_bytes(self.take_slice(len)?)
+    }
+
+    fn deserialize_byte_buf<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        self.deserialize_bytes(visitor)
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        match self.take::<1>()? {
+            [0] => visitor.visit_none(),
+            [1] => visitor.visit_some(self),
+            [tag] => Err(NesError::InvalidState(alloc::format!(
+                "bad option tag {tag}"
+            ))),
+        }
+    }
+
+    fn deserialize_unit<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_unit()
+    }
+
+    fn deserialize_unit_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value> {
+        visitor.visit_unit()
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        let len = self.read_len()?;
+        visitor.visit_seq(Elements { reader: self, len })
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(self, len: usize, visitor: V) -> Result<V::Value> {
+        visitor.visit_seq(Elements { reader: self, len })
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        len: usize,
+        visitor: V,
+    ) -> Result<V::Value> {
+        self.deserialize_tuple(len, visitor)
+    }
+
+    fn deserialize_map<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        let len = self.read_len()?;
+        visitor.visit_map(Elements { reader: self, len })
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value> {
+        self.deserialize_tuple(fields.len(), visitor)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value> {
+        visitor.visit_enum(self)
+    }
+
+    fn deserialize_identifier<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        self.deserialize_u32(visitor)
+    }
+
+    fn deserialize_ignored_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        self.deserialize_any(visitor)
+    }
+
+    fn is_human_readable(&self) -> bool {
+        false
+    }
+}
+
+/// The items of a sequence, tuple or map, `len` of them.
+struct Elements<'a, 'de> {
+    reader: &'a mut StateReader<'de>,
+    len: usize,
+}
+
+impl<'de> de::SeqAccess<'de> for Elements<'_, 'de> {
+    type Error = NesError;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(&mut self, seed: T) -> Result<Option<T::Value>> {
+        if self.len == 0 {
+            return Ok(None);
+        }
+        self.len -= 1;
+        seed.deserialize(&mut *self.reader).map(Some)
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        // Lengths come from the state itself, so don't let a corrupt one preallocate gigabytes.
+        Some(self.len.min(self.reader.bytes.len()))
+    }
+}
+
+impl<'de> de::MapAccess<'de> for Elements<'_, 'de> {
+    type Error = NesError;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(&mut self, seed: K) -> Result<Option<K::Value>> {
+        if self.len == 0 {
+            return Ok(None);
+        }
+        self.len -= 1;
+        seed.deserialize(&mut *self.reader).map(Some)
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value> {
+        seed.deserialize(&mut *self.reader)
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.len.min(self.reader.bytes.len()))
+    }
+}
+
+impl<'de> de::EnumAccess<'de> for &mut StateReader<'de> {
+    type Error = NesError;
+    type Variant = Self;
+
+    fn variant_seed<V: DeserializeSeed<'de>>(self, seed: V) -> Result<(V::Value, Self)> {
+        let index = u32::from_le_bytes(self.take()?);
+        let variant = seed.deserialize(IntoDeserializer::<NesError>::into_deserializer(index))?;
+        Ok((variant, self))
+    }
+}
+
+impl<'de> de::VariantAccess<'de> for &mut StateReader<'de> {
+    type Error = NesError;
+
+    fn unit_variant(self) -> Result<()> {
+        Ok(())
+    }
+
+    fn newtype_variant_seed<T: DeserializeSeed<'de>>(self, seed: T) -> Result<T::Value> {
+        seed.deserialize(self)
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(self, len: usize, visitor: V) -> Result<V::Value> {
+        de::Deserializer::deserialize_tuple(self, len, visitor)
+    }
+
+    fn struct_variant<V: Visitor<'de>>(
+        self,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value> {
+        de::Deserializer::deserialize_tuple(self, fields.len(), visitor)
+    }
+}
+
+/// `#[serde(with = "crate::state::array")]` for arrays longer than the 32 elements serde supports
+/// on its own.
+pub(crate) mod array {
+    use core::fmt;
+    use core::marker::PhantomData;
+
+    use serde::de::{self, Deserialize, Deserializer, SeqAccess, Visitor};
+    use serde::ser::{Serialize, SerializeTuple, Serializer};
+
+    pub fn serialize<S, T, const N: usize>(array: &[T; N], serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+        T: Serialize,
+    {
+        let mut tuple = serializer.serialize_tuple(N)?;
+        for element in array {
+            tuple.serialize_element(element)?;
+        }
+        tuple.end()
+    }
+
+    pub fn deserialize<'de, D, T, const N: usize>(deserializer: D) -> Result<[T; N], D::Error>
+    where
+        D: Deserializer<'de>,
+        T: Deserialize<'de> + Copy + Default,
+    {
+        deserializer.deserialize_tuple(N, ArrayVisitor(PhantomData))
+    }
+
+    struct ArrayVisitor<T, const N: usize>(PhantomData<T>);
+
+    impl<'de, T, const N: usize> Visitor<'de> for ArrayVisitor<T, N>
+    where
+        T: Deserialize<'de> + Copy + Default,
+    {
+        type Value = [T; N];
+
+        fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "an array of {N} elements")
+        }
+
+        fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<[T; N], A::Error> {
+            let mut array = [T::default(); N];
+            for (i, element) in array.iter_mut().enumerate() {
+                *element = seq
+                    .next_element()?
+                    .ok_or_else(|| de::Error::invalid_length(i, &self))?;
+            }
+            Ok(array)
+        }
+    }
+}
+
+/// `#[serde(with = "crate::state::timing")]` for a component's `&'static Timing`, saved as the
+/// region it belongs to.
+pub(crate) mod timing {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    use crate::region::{Region, Timing};
+
+    pub fn serialize<S: Serializer>(
+        timing: &&'static Timing,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        timing.region.serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<&'static Timing, D::Error> {
+        Ok(Region::deserialize(deserializer)?.timing())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::string::String;
+    use alloc::vec;
+
+    use serde::{Deserialize, Serialize};
+
+    use super::*;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    enum Shape {
+        Empty,
+        Circle(f32),
+        Rect { w: u16, h: u16 },
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Everything {
+        flag: bool,
+        small: i8,
+        wide: u64,
+        name: String,
+        maybe: Option<u16>,
+        list: Vec<Shape>,
+        #[serde(with = "array")]
+        big: [u8; 40],
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let value = Everything {
+            flag: true,
+            small: -3,
+            wide: 0x0123_4567_89AB_CDEF,
+            name: "mario".into(),
+            maybe: Some(0xBEEF),
+            list: vec![Shape::Empty, Shape::Circle(1.5), Shape::Rect { w: 3, h: 4 }],
+            big: [7; 40],
+        };
+        let bytes = to_bytes(&value);
+        assert_eq!(&bytes[..4], [1, 0xFD, 0xEF, 0xCD]);
+        assert_eq!(from_bytes::<Everything>(&bytes).unwrap(), value)
+    }
+
+    #[test]
+    fn test_rejects_bad_input() {
+        let bytes = to_bytes(&(1u16, true));
+        assert!(matches!(
+            from_bytes::<(u16, bool)>(&bytes[..2]),
+            Err(NesError::InvalidState(_))
+        ));
+        assert!(matches!(
+            from_bytes::<u16>(&bytes),
+            Err(NesError::InvalidState(_))
+        ));
+        assert!(matches!(
+            from_bytes::<(u16, bool)>(&[1, 0, 2]),
+            Err(NesError::InvalidState(_))
+        ));
+        assert!(matches!(
+            from_bytes::<Shape>(&[9, 0, 0, 0]),
+            Err(NesError::InvalidState(_))
+        ))
+    }
+
+    #[test]
+    fn test_header() {
+        let mut writer = StateWriter::new();
+        write_header(&mut writer, Region::Pal, 0x1234);
+        let bytes = writer.into_bytes();
+        assert_eq!(&bytes[..4], b"NESS");
+        assert!(read_header(&mut StateReader::new(&bytes), Region::Pal, 0x1234).is_ok());
+        assert!(read_header(&mut StateReader::new(&bytes), Region::Ntsc, 0x1234).is_err());
+        assert!(read_header(&mut StateReader::new(&bytes), Region::Pal, 0x4321).is_err());
+
+        let mut old = bytes.clone();
+        old[4] = 0;
+        let error = read_header(&mut StateReader::new(&old), Region::Pal, 0x1234).unwrap_err();
+        assert!(error.to_string().contains("version 0"))
+    }
+}